@@ -1,4 +1,4 @@
-use crate::{read_scriptint, ExecError};
+use crate::{read_scriptint, Error, ExecError};
 use alloc::rc::Rc;
 use bitcoin::script;
 use core::cell::RefCell;
@@ -11,28 +11,25 @@ pub enum StackEntry {
 }
 
 impl StackEntry {
-    // This assumes the StackEntry fit in a u32 and will pad it with leading zeros to 4 bytes.
-    pub fn serialize_to_bytes(self) -> Vec<u8> {
+    // This assumes the StackEntry fits in a u32 and pads it with trailing zero bytes to 4 bytes,
+    // so only the non-negative numbers keep their value. Wider or negative entries are rejected:
+    // their sign and width must be carried by the caller's own encoding.
+    pub fn serialize_to_bytes(self) -> Result<Vec<u8>, Error> {
         match self {
             StackEntry::Num(num) => {
-                assert!(
-                    num <= u32::MAX.into(),
-                    "There should not be entries with more than 32 bits on the stack at this point"
-                );
-                // Since num is for sure <= u32::MAX, we can safely convert it to u32
-                let num = num as u32;
-                num.to_le_bytes().to_vec()
+                let num = u32::try_from(num)
+                    .map_err(|_| Error::Other("stack entry is negative or wider than 32 bits"))?;
+                Ok(num.to_le_bytes().to_vec())
             }
             StackEntry::StrRef(v) => {
                 let mut v = v.borrow().to_vec();
-                assert!(
-                    v.len() <= 4,
-                    "There should not be entries with more than 32 bits on the stack at this point"
-                );
+                if v.len() > 4 {
+                    return Err(Error::Other("stack entry is wider than 32 bits"));
+                }
                 while v.len() < 4 {
                     v.push(0)
                 }
-                v
+                Ok(v)
             }
         }
     }
@@ -171,12 +168,12 @@ impl Stack {
 
     // Will serialize the stack into a series of bytes such that every 4 bytes correspond to a u32
     // (or smaller) stack entry (smaller entries are padded with 0).
-    pub fn serialize_to_bytes(self) -> Vec<u8> {
+    pub fn serialize_to_bytes(self) -> Result<Vec<u8>, Error> {
         let mut bytes = vec![];
         for entry in self.0 {
-            bytes.extend(entry.serialize_to_bytes());
+            bytes.extend(entry.serialize_to_bytes()?);
         }
-        bytes
+        Ok(bytes)
     }
 }
 
@@ -0,0 +1,289 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bitcoin::ScriptBuf;
+use clap::{Parser, ValueEnum};
+
+use bitcoin_splitter::split::{
+    artifact::{ArtifactFormat, SplitArtifact, SplitParams},
+    core::{fuzzy_split, try_naive_split, SplitType, DEFAULT_SCRIPT_SIZE, STACK_SIZE_INDEX},
+    hints::AnnotatedScript,
+    metric::{AverageDisproveCost, MaxDisproveCost, SplitMetric},
+    observer::ProgressBarObserver,
+    optimal::{try_optimal_split, try_static_optimal_split},
+    periodic::with_loop_hints,
+    report::SplitReport,
+    script::SplitResult,
+    stream::split_to_directory,
+};
+
+/// Format of the script files
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ScriptFormat {
+    Asm,
+    Hex,
+}
+
+/// Splitter used to find the shards
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Mode {
+    /// Cuts the script into chunks of the given size
+    Naive,
+    /// Searches over the chunk sizes for the split with the lowest cost
+    Fuzzy,
+    /// Finds the split with the lowest cost over all instruction boundaries
+    Optimal,
+    /// Same as optimal, but estimates the state sizes without executing the script
+    StaticOptimal,
+}
+
+/// How the size of the shard is measured. The split by weight is not exposed, since
+/// its table of opcode weights can only be built in code (see `OpcodeWeights`).
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum SplitKind {
+    Instructions,
+    Bytes,
+    StackSize,
+}
+
+/// Metric used to compare the splits
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Metric {
+    Max,
+    Average,
+}
+
+#[derive(Parser)]
+#[command(version, about = "Splits the Bitcoin script into shards")]
+struct Args {
+    /// filepath to script file
+    #[arg(required = true)]
+    script_path: PathBuf,
+    /// filepath to the input script file, the input is empty if not given
+    #[arg(long)]
+    input: Option<PathBuf>,
+    /// Format of the script and input files
+    #[arg(long, value_enum, default_value_t = ScriptFormat::Asm)]
+    format: ScriptFormat,
+    /// Splitter to use
+    #[arg(long, value_enum, default_value_t = Mode::Naive)]
+    mode: Mode,
+    /// How the size of the shard is measured (ignored by the optimal splitter)
+    #[arg(long, value_enum, default_value_t = SplitKind::Instructions)]
+    split_type: SplitKind,
+    /// Size of the shard for the naive splitter
+    #[arg(long, default_value_t = DEFAULT_SCRIPT_SIZE)]
+    chunk_size: usize,
+    /// Tolerance (in bytes) around the chunk size for the stack-size split type
+    #[arg(long, default_value_t = 100)]
+    tolerance: usize,
+    /// Metric used to compare the splits
+    #[arg(long, value_enum, default_value_t = Metric::Max)]
+    metric: Metric,
+    /// Weight of a single state element in the cost of the split
+    #[arg(long, default_value_t = STACK_SIZE_INDEX)]
+    alpha: usize,
+    /// Whether to cut only at the iteration boundaries of the loops detected in the script
+    /// (ignored if the script already has hints)
+    #[arg(long)]
+    detect_loops: bool,
+    /// Whether to write each shard with the state after it right after it is cut, keeping only
+    /// the current shard in memory (naive splitter only, no artifact and report are written).
+    /// Shards are written to the `stream_shards` subdirectory, which is cleared first.
+    #[arg(long, conflicts_with_all = ["binary", "metric", "alpha"])]
+    stream: bool,
+    /// Directory to write the shards, intermediate states and the report to
+    #[arg(long, short)]
+    output: PathBuf,
+    /// Whether to write the intermediate states in binary format instead of JSON
+    #[arg(long)]
+    binary: bool,
+}
+
+/// Reads the script from the file in the given format
+fn read_script(path: &Path, format: ScriptFormat) -> Result<ScriptBuf, String> {
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("error reading {}: {}", path.display(), e))?;
+
+    match format {
+        ScriptFormat::Asm => ScriptBuf::parse_asm(&content)
+            .map_err(|e| format!("error parsing {}: {:?}", path.display(), e)),
+        ScriptFormat::Hex => hex::decode(content.trim())
+            .map(ScriptBuf::from_bytes)
+            .map_err(|e| format!("error parsing {}: {}", path.display(), e)),
+    }
+}
+
+/// Returns the split type given by the arguments
+fn split_type(args: &Args) -> SplitType {
+    match args.split_type {
+        SplitKind::Instructions => SplitType::ByInstructions,
+        SplitKind::Bytes => SplitType::ByBytes,
+        SplitKind::StackSize => SplitType::ByStackSize {
+            tolerance: args.tolerance,
+        },
+    }
+}
+
+/// Marks the loops of the script as the preferred cut points if asked to
+fn prepare_script(args: &Args, script: ScriptBuf) -> Result<AnnotatedScript, String> {
+    if !args.detect_loops {
+        return Ok(script.into());
+    }
+
+    with_loop_hints(script).map_err(|e| format!("error detecting the loops: {:?}", e))
+}
+
+/// Splits the script according to the arguments
+fn split(
+    args: &Args,
+    input: ScriptBuf,
+    script: ScriptBuf,
+    metric: &impl SplitMetric,
+) -> Result<(SplitResult, SplitParams), String> {
+    let split_type = split_type(args);
+    let script = prepare_script(args, script)?;
+
+    let mut params = SplitParams {
+        splitter: format!("{:?}", args.mode).to_lowercase(),
+        split_type: Some(format!("{:?}", split_type)),
+        chunk_size: None,
+    };
+
+    let split_result = match args.mode {
+        Mode::Naive => {
+            params.chunk_size = Some(args.chunk_size);
+            try_naive_split(input, script, split_type, args.chunk_size)
+        }
+        Mode::Fuzzy => fuzzy_split(
+            input,
+            script,
+            split_type,
+            metric,
+            &mut ProgressBarObserver::default(),
+        ),
+        Mode::Optimal => {
+            params.split_type = None;
+            try_optimal_split(input, script, metric)
+        }
+        Mode::StaticOptimal => {
+            params.split_type = None;
+            try_static_optimal_split(input, script, metric)
+        }
+    }
+    .map_err(|e| format!("error splitting the script: {:?}", e))?;
+
+    Ok((split_result, params))
+}
+
+/// Writes the shards, the artifact with intermediate states and the report to the output directory
+fn write_output(
+    args: &Args,
+    input: &ScriptBuf,
+    script: &ScriptBuf,
+    metric: &impl SplitMetric,
+) -> Result<(), String> {
+    let (split_result, params) = split(args, input.clone(), script.clone(), metric)?;
+    let report = SplitReport::new(input, &split_result, metric);
+
+    let write = |path: PathBuf, content: String| {
+        fs::write(&path, content).map_err(|e| format!("error writing {}: {}", path.display(), e))
+    };
+
+    // Shards left by the previous run would be mixed with the new ones
+    let shards_dir = args.output.join("shards");
+    if shards_dir.exists() {
+        fs::remove_dir_all(&shards_dir)
+            .map_err(|e| format!("error clearing {}: {}", shards_dir.display(), e))?;
+    }
+    fs::create_dir_all(&shards_dir)
+        .map_err(|e| format!("error creating {}: {}", shards_dir.display(), e))?;
+    for (i, shard) in split_result.shards.iter().enumerate() {
+        write(
+            shards_dir.join(format!("shard_{:04}.asm", i)),
+            shard.to_asm_string(),
+        )?;
+    }
+
+    let (artifact_path, format) = if args.binary {
+        (args.output.join("split.bin"), ArtifactFormat::Binary)
+    } else {
+        (args.output.join("split.json"), ArtifactFormat::Json)
+    };
+    SplitArtifact::new(&split_result, script, params)
+        .save(&artifact_path, format)
+        .map_err(|e| format!("error writing {}: {:?}", artifact_path.display(), e))?;
+
+    write(args.output.join("report.md"), report.to_markdown())?;
+    write(args.output.join("report.csv"), report.to_csv())?;
+
+    println!("Script size: {} bytes", script.len());
+    println!("Number of shards: {}", split_result.len());
+    println!("Unique shards: {}", report.unique_shards);
+    println!("Total states size: {}", report.total_states_size);
+    println!("Cost: {}", report.cost);
+    println!("Output written to {}", args.output.display());
+
+    Ok(())
+}
+
+/// Splits the script with the streaming splitter, writing the shards right away
+fn write_stream(args: &Args, input: &ScriptBuf, script: ScriptBuf) -> Result<(), String> {
+    if !matches!(args.mode, Mode::Naive) {
+        return Err("only the naive splitter supports streaming".to_string());
+    }
+
+    let script = prepare_script(args, script)?;
+
+    // Shards left by the previous run would be mixed with the new ones
+    let shards_dir = args.output.join("stream_shards");
+    if shards_dir.exists() {
+        fs::remove_dir_all(&shards_dir)
+            .map_err(|e| format!("error clearing {}: {}", shards_dir.display(), e))?;
+    }
+    let shards_number = split_to_directory(
+        input,
+        &script,
+        split_type(args),
+        args.chunk_size,
+        &shards_dir,
+    )
+    .map_err(|e| format!("error splitting the script: {:?}", e))?;
+
+    println!("Script size: {} bytes", script.script().len());
+    println!("Number of shards: {}", shards_number);
+    println!("Output written to {}", shards_dir.display());
+
+    Ok(())
+}
+
+fn inner_main() -> Result<(), String> {
+    let args = Args::parse();
+
+    let script = read_script(&args.script_path, args.format)?;
+    let input = match &args.input {
+        Some(path) => read_script(path, args.format)?,
+        None => ScriptBuf::new(),
+    };
+
+    if args.stream {
+        return write_stream(&args, &input, script);
+    }
+
+    match args.metric {
+        Metric::Max => write_output(&args, &input, &script, &MaxDisproveCost::new(args.alpha)),
+        Metric::Average => write_output(
+            &args,
+            &input,
+            &script,
+            &AverageDisproveCost::new(args.alpha),
+        ),
+    }
+}
+
+fn main() {
+    if let Err(e) = inner_main() {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
+    }
+}
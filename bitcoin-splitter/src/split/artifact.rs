@@ -0,0 +1,419 @@
+//! Module containing the [`SplitArtifact`], which is the versioned on-disk form of
+//! the [`SplitResult`], so that the operator and the challenger can exchange exactly
+//! the same split. The artifact can be stored either as JSON or in the compact binary format.
+
+use std::{fs, io, path::Path};
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin_scriptexec::Stack;
+use bitcoin_utils::treepp::*;
+use serde::{Deserialize, Serialize};
+
+use super::{intermediate_state::IntermediateState, script::SplitResult};
+
+/// Current version of the artifact format
+pub const ARTIFACT_VERSION: u32 = 1;
+
+/// Magic bytes the binary artifact starts with
+const BINARY_MAGIC: &[u8; 4] = b"BSPL";
+
+/// Error of the artifact loading
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArtifactError {
+    /// The file cannot be read or written
+    Io(io::ErrorKind),
+    /// The JSON artifact cannot be parsed
+    InvalidJson(String),
+    /// The binary artifact does not start with the magic bytes
+    InvalidMagic,
+    /// The binary artifact ends unexpectedly
+    UnexpectedEnd,
+    /// The binary artifact has bytes after its end
+    TrailingBytes,
+    /// The optional field of the binary artifact has the given tag, which is neither 0 nor 1
+    InvalidTag(u8),
+    /// Some value does not fit into the `u32` of the binary format
+    ValueOverflow,
+    /// Some field is not a valid hex string
+    InvalidHex,
+    /// Some field is not a valid UTF-8 string
+    InvalidUtf8,
+    /// The artifact has the version this implementation does not support
+    UnsupportedVersion(u32),
+    /// The artifact was made for a different script
+    ScriptHashMismatch,
+}
+
+impl From<io::Error> for ArtifactError {
+    fn from(error: io::Error) -> Self {
+        ArtifactError::Io(error.kind())
+    }
+}
+
+/// Format of the artifact on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtifactFormat {
+    Json,
+    Binary,
+}
+
+/// Parameters with which the split was made
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitParams {
+    /// Name of the splitter, for instance, `naive`, `fuzzy` or `optimal`
+    pub splitter: String,
+    /// Split type, if the splitter uses one
+    pub split_type: Option<String>,
+    /// Chunk size, if the splitter uses one
+    pub chunk_size: Option<usize>,
+}
+
+/// Single shard together with the state after it. All fields are hex-encoded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardArtifact {
+    /// Shard script
+    pub script: String,
+    /// Elements of the stack after the shard, from the bottom to the top
+    pub stack: Vec<String>,
+    /// Elements of the altstack after the shard, from the bottom to the top
+    pub altstack: Vec<String>,
+}
+
+impl ShardArtifact {
+    /// Creates the artifact of the shard and the state after it
+    pub fn new(shard: &Script, state: &IntermediateState) -> Self {
+        let encode_stack = |stack: &Stack| stack.iter_str().map(hex::encode).collect();
+
+        Self {
+            script: hex::encode(shard.as_bytes()),
+            stack: encode_stack(&state.stack),
+            altstack: encode_stack(&state.altstack),
+        }
+    }
+
+    /// Rebuilds the shard and the state after it
+    pub fn to_shard(&self) -> Result<(Script, IntermediateState), ArtifactError> {
+        let decode = |value: &String| hex::decode(value).map_err(|_| ArtifactError::InvalidHex);
+        let decode_stack = |elements: &[String]| -> Result<Stack, ArtifactError> {
+            Ok(Stack::from_u8_vec(
+                elements.iter().map(decode).collect::<Result<_, _>>()?,
+            ))
+        };
+
+        Ok((
+            Script::from_bytes(decode(&self.script)?),
+            IntermediateState {
+                stack: decode_stack(&self.stack)?,
+                altstack: decode_stack(&self.altstack)?,
+            },
+        ))
+    }
+
+    /// Serializes the shard artifact to JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("artifact is always serializable")
+    }
+
+    /// Deserializes the shard artifact from JSON
+    pub fn from_json(json: &str) -> Result<Self, ArtifactError> {
+        serde_json::from_str(json).map_err(|err| ArtifactError::InvalidJson(err.to_string()))
+    }
+}
+
+/// Versioned artifact of the split
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitArtifact {
+    /// Version of the format
+    pub version: u32,
+    /// Parameters of the split
+    pub params: SplitParams,
+    /// Hex-encoded SHA256 of the original script
+    pub script_hash: String,
+    /// Shards together with the intermediate states
+    pub shards: Vec<ShardArtifact>,
+}
+
+impl SplitArtifact {
+    /// Creates the artifact for the split of the given script
+    pub fn new(split_result: &SplitResult, script: &Script, params: SplitParams) -> Self {
+        let shards = split_result
+            .shards
+            .iter()
+            .zip(split_result.intermediate_states.iter())
+            .map(|(shard, state)| ShardArtifact::new(shard, state))
+            .collect();
+
+        Self {
+            version: ARTIFACT_VERSION,
+            params,
+            script_hash: script_hash(script),
+            shards,
+        }
+    }
+
+    /// Checks that the artifact was made for the given script
+    pub fn check_script(&self, script: &Script) -> Result<(), ArtifactError> {
+        if self.script_hash != script_hash(script) {
+            return Err(ArtifactError::ScriptHashMismatch);
+        }
+
+        Ok(())
+    }
+
+    /// Rebuilds the [`SplitResult`] stored in the artifact, checking first that the artifact
+    /// was made for the given script
+    pub fn to_split_result(&self, script: &Script) -> Result<SplitResult, ArtifactError> {
+        self.check_script(script)?;
+
+        let mut shards = Vec::with_capacity(self.shards.len());
+        let mut intermediate_states = Vec::with_capacity(self.shards.len());
+        for shard in &self.shards {
+            let (shard, state) = shard.to_shard()?;
+            shards.push(shard);
+            intermediate_states.push(state);
+        }
+
+        Ok(SplitResult::new(shards, intermediate_states))
+    }
+
+    /// Serializes the artifact to JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("artifact is always serializable")
+    }
+
+    /// Deserializes the artifact from JSON, checking that all hex-encoded fields are valid
+    pub fn from_json(json: &str) -> Result<Self, ArtifactError> {
+        let artifact: Self = serde_json::from_str(json)
+            .map_err(|err| ArtifactError::InvalidJson(err.to_string()))?;
+        artifact.check_version()?;
+        artifact.check_hex()?;
+
+        Ok(artifact)
+    }
+
+    /// Serializes the artifact to the compact binary format. All variable-length fields
+    /// are prefixed with their length as the little-endian `u32`, and hex-encoded values
+    /// are stored as raw bytes. Returns an error if some of them is not a valid hex string
+    /// or does not fit into the format.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ArtifactError> {
+        let mut writer = BinaryWriter::default();
+        writer.bytes.extend_from_slice(BINARY_MAGIC);
+        writer.write_u32(self.version);
+
+        writer.write_str(&self.params.splitter)?;
+        writer.write_option(self.params.split_type.as_deref(), BinaryWriter::write_str)?;
+        writer.write_option(self.params.chunk_size, BinaryWriter::write_length)?;
+
+        writer.write_hex(&self.script_hash)?;
+        writer.write_length(self.shards.len())?;
+        for shard in &self.shards {
+            writer.write_hex(&shard.script)?;
+            for stack in [&shard.stack, &shard.altstack] {
+                writer.write_length(stack.len())?;
+                for element in stack {
+                    writer.write_hex(element)?;
+                }
+            }
+        }
+
+        Ok(writer.bytes)
+    }
+
+    /// Deserializes the artifact from the compact binary format
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ArtifactError> {
+        let mut reader = BinaryReader { bytes };
+        if reader.read_slice(BINARY_MAGIC.len())? != BINARY_MAGIC {
+            return Err(ArtifactError::InvalidMagic);
+        }
+
+        let version = reader.read_u32()?;
+        let params = SplitParams {
+            splitter: reader.read_str()?,
+            split_type: reader.read_option(BinaryReader::read_str)?,
+            chunk_size: reader.read_option(|reader| Ok(reader.read_u32()? as usize))?,
+        };
+        let script_hash = reader.read_hex()?;
+
+        let shards_number = reader.read_u32()?;
+        let mut shards = vec![];
+        for _ in 0..shards_number {
+            let script = reader.read_hex()?;
+            let mut stacks = [vec![], vec![]];
+            for stack in stacks.iter_mut() {
+                for _ in 0..reader.read_u32()? {
+                    stack.push(reader.read_hex()?);
+                }
+            }
+
+            let [stack, altstack] = stacks;
+            shards.push(ShardArtifact {
+                script,
+                stack,
+                altstack,
+            });
+        }
+        if !reader.bytes.is_empty() {
+            return Err(ArtifactError::TrailingBytes);
+        }
+
+        let artifact = Self {
+            version,
+            params,
+            script_hash,
+            shards,
+        };
+        artifact.check_version()?;
+
+        Ok(artifact)
+    }
+
+    /// Saves the artifact to the file in the given format
+    pub fn save(
+        &self,
+        path: impl AsRef<Path>,
+        format: ArtifactFormat,
+    ) -> Result<(), ArtifactError> {
+        match format {
+            ArtifactFormat::Json => fs::write(path, self.to_json())?,
+            ArtifactFormat::Binary => fs::write(path, self.to_bytes()?)?,
+        }
+
+        Ok(())
+    }
+
+    /// Loads the artifact from the file, detecting its format by the magic bytes
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ArtifactError> {
+        let bytes = fs::read(path)?;
+
+        if bytes.starts_with(BINARY_MAGIC) {
+            return Self::from_bytes(&bytes);
+        }
+
+        Self::from_json(std::str::from_utf8(&bytes).map_err(|_| ArtifactError::InvalidUtf8)?)
+    }
+
+    fn check_version(&self) -> Result<(), ArtifactError> {
+        if self.version != ARTIFACT_VERSION {
+            return Err(ArtifactError::UnsupportedVersion(self.version));
+        }
+
+        Ok(())
+    }
+
+    fn check_hex(&self) -> Result<(), ArtifactError> {
+        let values =
+            std::iter::once(&self.script_hash).chain(self.shards.iter().flat_map(|shard| {
+                std::iter::once(&shard.script)
+                    .chain(shard.stack.iter())
+                    .chain(shard.altstack.iter())
+            }));
+        for value in values {
+            hex::decode(value).map_err(|_| ArtifactError::InvalidHex)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the hex-encoded SHA256 of the script
+fn script_hash(script: &Script) -> String {
+    hex::encode(sha256::Hash::hash(script.as_bytes()).to_byte_array())
+}
+
+/// Writer of the binary artifact
+#[derive(Default)]
+struct BinaryWriter {
+    bytes: Vec<u8>,
+}
+
+impl BinaryWriter {
+    fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_length(&mut self, length: usize) -> Result<(), ArtifactError> {
+        let length = u32::try_from(length).map_err(|_| ArtifactError::ValueOverflow)?;
+        self.write_u32(length);
+        Ok(())
+    }
+
+    fn write_slice(&mut self, value: &[u8]) -> Result<(), ArtifactError> {
+        self.write_length(value.len())?;
+        self.bytes.extend_from_slice(value);
+        Ok(())
+    }
+
+    fn write_str(&mut self, value: &str) -> Result<(), ArtifactError> {
+        self.write_slice(value.as_bytes())
+    }
+
+    fn write_hex(&mut self, value: &str) -> Result<(), ArtifactError> {
+        // Fields of the artifact are public, so they are not trusted to be valid hex strings
+        self.write_slice(&hex::decode(value).map_err(|_| ArtifactError::InvalidHex)?)
+    }
+
+    fn write_option<T>(
+        &mut self,
+        value: Option<T>,
+        write: impl FnOnce(&mut Self, T) -> Result<(), ArtifactError>,
+    ) -> Result<(), ArtifactError> {
+        match value {
+            Some(value) => {
+                self.bytes.push(1);
+                write(self, value)
+            }
+            None => {
+                self.bytes.push(0);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Reader of the binary artifact
+struct BinaryReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> BinaryReader<'a> {
+    fn read_slice(&mut self, length: usize) -> Result<&'a [u8], ArtifactError> {
+        if self.bytes.len() < length {
+            return Err(ArtifactError::UnexpectedEnd);
+        }
+
+        let (value, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(value)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, ArtifactError> {
+        let bytes = self.read_slice(4)?;
+        Ok(u32::from_le_bytes(
+            bytes.try_into().expect("slice has length 4"),
+        ))
+    }
+
+    fn read_bytes(&mut self) -> Result<&'a [u8], ArtifactError> {
+        let length = self.read_u32()? as usize;
+        self.read_slice(length)
+    }
+
+    fn read_str(&mut self) -> Result<String, ArtifactError> {
+        String::from_utf8(self.read_bytes()?.to_vec()).map_err(|_| ArtifactError::InvalidUtf8)
+    }
+
+    fn read_hex(&mut self) -> Result<String, ArtifactError> {
+        Ok(hex::encode(self.read_bytes()?))
+    }
+
+    fn read_option<T>(
+        &mut self,
+        read: impl FnOnce(&mut Self) -> Result<T, ArtifactError>,
+    ) -> Result<Option<T>, ArtifactError> {
+        match self.read_slice(1)?[0] {
+            0 => Ok(None),
+            1 => read(self).map(Some),
+            tag => Err(ArtifactError::InvalidTag(tag)),
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! Module containing the split at the checkpoints pinned by the user.
+//!
+//! Sometimes most of the existing split must be kept while one or two boundaries are moved,
+//! for instance, after the change of some gadget or to match the cut points that were
+//! already reviewed. The cut points of the existing split are given by [`SplitResult::cuts`],
+//! and [`split_at_checkpoints`] builds the split exactly at the given ones, checking that
+//! each of them is a legal cut.
+
+use bitcoin_utils::treepp::*;
+
+use super::{
+    conditional::validate_shards,
+    core::{form_states_incrementally, split_at_instructions, MAX_SCRIPT_SIZE},
+    error::{CheckpointError, SplitError},
+    hints::AnnotatedScript,
+    profile::boundaries,
+    script::SplitResult,
+};
+
+/// Position of the cut pinned by the user. Similarly to the split hints, positions
+/// refer to the instructions of the script (that is, of the concatenation of the shards).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checkpoint {
+    /// Cut right before the instruction with the given index
+    Instruction(usize),
+    /// Cut at the given byte offset, which must be the start of some instruction
+    Byte(usize),
+}
+
+/// What to do with the checkpoint at which the script cannot be cut
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CheckpointFallback {
+    /// Return [`CheckpointError::IllegalCut`]
+    #[default]
+    Reject,
+    /// Move the checkpoint to the nearest legal cut (the earlier one in case of a tie)
+    Nearest,
+}
+
+/// Returns the instruction indices of the given checkpoints. Checkpoints must be strictly
+/// increasing and lie inside the script, and each of them must be a legal cut, that is,
+/// it must not lie inside the conditional block or the atomic region. Otherwise, the
+/// checkpoint is either rejected or moved according to the `fallback`. Checkpoints moved
+/// to the same cut are merged.
+pub fn resolve_checkpoints(
+    script: impl Into<AnnotatedScript>,
+    checkpoints: &[Checkpoint],
+    fallback: CheckpointFallback,
+) -> Result<Vec<usize>, CheckpointError> {
+    let script = script.into();
+    let hints = script.checked_hints()?;
+    let script = script.into_script();
+
+    // Byte offset of each boundary and whether the script can be cut at it
+    let (offsets, mut legal) = boundaries(&script)?;
+    for (boundary, legal) in legal.iter_mut().enumerate() {
+        *legal = *legal && !hints.is_atomic(boundary);
+    }
+
+    // Cuts at the start or at the end of the script give empty shards
+    let instructions_number = offsets.len() - 1;
+    let inner_boundaries = 1..instructions_number;
+
+    let mut cuts: Vec<usize> = vec![];
+    let mut previous = 0;
+
+    for &checkpoint in checkpoints {
+        let requested = match checkpoint {
+            Checkpoint::Instruction(index) => index,
+            Checkpoint::Byte(offset) => offsets
+                .binary_search(&offset)
+                .map_err(|_| CheckpointError::NotInstructionBoundary(checkpoint))?,
+        };
+
+        if !inner_boundaries.contains(&requested) {
+            return Err(CheckpointError::OutOfRange(checkpoint));
+        }
+        if requested <= previous {
+            return Err(CheckpointError::NotIncreasing(checkpoint));
+        }
+        previous = requested;
+
+        let cut = match fallback {
+            _ if legal[requested] => requested,
+            CheckpointFallback::Reject => return Err(CheckpointError::IllegalCut(checkpoint)),
+            CheckpointFallback::Nearest => (1..instructions_number)
+                .flat_map(|distance| [requested.checked_sub(distance), Some(requested + distance)])
+                .flatten()
+                .find(|boundary| inner_boundaries.contains(boundary) && legal[*boundary])
+                .ok_or(CheckpointError::NoLegalCut(checkpoint))?,
+        };
+
+        // Nearest cuts of the increasing checkpoints can be merged, but never swapped
+        if cuts.last() != Some(&cut) {
+            cuts.push(cut);
+        }
+    }
+
+    Ok(cuts)
+}
+
+/// Splits the script executed on the given input exactly at the given checkpoints,
+/// see [`resolve_checkpoints`]
+pub fn split_at_checkpoints(
+    input: Script,
+    script: impl Into<AnnotatedScript>,
+    checkpoints: &[Checkpoint],
+    fallback: CheckpointFallback,
+) -> Result<SplitResult, CheckpointError> {
+    let script = script.into();
+    let cuts = resolve_checkpoints(&script, checkpoints, fallback)?;
+    let shards = split_at_instructions(script.script(), &cuts);
+    validate_shards(&shards)?;
+
+    if let Some((shard_id, shard)) = shards
+        .iter()
+        .enumerate()
+        .find(|(_, shard)| shard.len() > MAX_SCRIPT_SIZE)
+    {
+        return Err(SplitError::ShardTooLarge {
+            shard_id,
+            size: shard.len(),
+        }
+        .into());
+    }
+
+    let intermediate_states = form_states_incrementally(&shards, input);
+    Ok(SplitResult::new(shards, intermediate_states))
+}
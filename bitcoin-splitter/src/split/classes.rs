@@ -0,0 +1,83 @@
+//! Module containing the detection of the repeated shards.
+//!
+//! Scripts consisting of the same step repeated many times (for instance, the square
+//! Fibonacci sequence from the test scripts) are split into many byte-identical shards.
+//! Grouping them into classes lets the consumers compile and analyze each distinct
+//! shard only once.
+
+use std::collections::HashMap;
+
+use bitcoin_utils::treepp::*;
+
+/// Classes of byte-identical shards. Classes are numbered in the order
+/// of the first occurrence of their shards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShardClasses {
+    /// Class of each shard
+    class_ids: Vec<usize>,
+    /// Index of the first shard of each class
+    representatives: Vec<usize>,
+}
+
+impl ShardClasses {
+    /// Groups the given shards into classes of byte-identical ones
+    pub fn new(shards: &[Script]) -> Self {
+        let mut classes: HashMap<&[u8], usize> = HashMap::new();
+        let mut representatives = vec![];
+
+        let class_ids = shards
+            .iter()
+            .enumerate()
+            .map(|(shard_id, shard)| {
+                *classes.entry(shard.as_bytes()).or_insert_with(|| {
+                    representatives.push(shard_id);
+                    representatives.len() - 1
+                })
+            })
+            .collect();
+
+        Self {
+            class_ids,
+            representatives,
+        }
+    }
+
+    /// Returns the number of classes, that is, the number of distinct shards
+    pub fn len(&self) -> usize {
+        self.representatives.len()
+    }
+
+    /// Returns whether there are no classes (and thus no shards)
+    pub fn is_empty(&self) -> bool {
+        self.representatives.is_empty()
+    }
+
+    /// Returns the class of each shard
+    pub fn class_ids(&self) -> &[usize] {
+        &self.class_ids
+    }
+
+    /// Returns the class of the given shard
+    pub fn class_of(&self, shard_id: usize) -> usize {
+        self.class_ids[shard_id]
+    }
+
+    /// Returns the index of the first shard of the given class
+    pub fn representative(&self, class_id: usize) -> usize {
+        self.representatives[class_id]
+    }
+
+    /// Returns the indices of the first shards of all classes
+    pub fn representatives(&self) -> &[usize] {
+        &self.representatives
+    }
+
+    /// Returns the indices of all shards of the given class
+    pub fn members(&self, class_id: usize) -> impl Iterator<Item = usize> + '_ {
+        self.class_ids
+            .iter()
+            .enumerate()
+            .filter(move |(_, &id)| id == class_id)
+            .map(|(shard_id, _)| shard_id)
+    }
+}
@@ -0,0 +1,198 @@
+//! Module containing the composition of the splittable scripts.
+//!
+//! To prove `g(f(x))`, the scripts of `f` and `g` are executed one after another,
+//! so the output of `f` becomes the input of `g`. The composition is split by splitting
+//! both components separately, so each of them keeps its natural split points.
+
+use std::marker::PhantomData;
+
+use bitcoin_utils::{stack_to_script, treepp::*};
+
+use super::{
+    core::SplitType,
+    error::CompositionError,
+    hints::AnnotatedScript,
+    intermediate_state::IntermediateState,
+    program::{ProgramIOPair, SplitProgram},
+    script::{IOPair, SplitResult, SplitableScript},
+};
+
+/// Composition of two splittable scripts: the script `G` is executed on the output
+/// of the script `F`. The output size of `F` must be equal to the input size of `G`,
+/// which is `MIDDLE_SIZE`.
+///
+/// See [`ComposedProgram`] for the composition of the programs with the sizes
+/// known at runtime only.
+pub struct ComposedScript<F, G, const MIDDLE_SIZE: usize> {
+    _scripts: PhantomData<(F, G)>,
+}
+
+impl<F, G, const INPUT_SIZE: usize, const MIDDLE_SIZE: usize, const OUTPUT_SIZE: usize>
+    SplitableScript<INPUT_SIZE, OUTPUT_SIZE> for ComposedScript<F, G, MIDDLE_SIZE>
+where
+    F: SplitableScript<INPUT_SIZE, MIDDLE_SIZE>,
+    G: SplitableScript<MIDDLE_SIZE, OUTPUT_SIZE>,
+{
+    fn script() -> Script {
+        script! {
+            { F::script() }
+            { G::script() }
+        }
+    }
+
+    fn annotated_script() -> AnnotatedScript {
+        let mut script = F::annotated_script();
+        script.push(G::annotated_script());
+        script
+    }
+
+    fn generate_valid_io_pair() -> IOPair<INPUT_SIZE, OUTPUT_SIZE> {
+        let IOPair { input, output } = F::generate_valid_io_pair();
+
+        IOPair {
+            input,
+            output: chained_output(&output, &G::script()),
+        }
+    }
+
+    fn generate_invalid_io_pair() -> IOPair<INPUT_SIZE, OUTPUT_SIZE> {
+        let IOPair { input, output } = Self::generate_valid_io_pair();
+
+        IOPair {
+            input,
+            output: distorted_output(&output),
+        }
+    }
+
+    fn default_split(input: Script, split_type: SplitType) -> SplitResult {
+        chain_splits(F::default_split(input, split_type.clone()), |middle| {
+            G::default_split(middle, split_type)
+        })
+    }
+
+    fn split(input: Script, split_type: SplitType, chunk_size: usize) -> SplitResult {
+        chain_splits(F::split(input, split_type.clone(), chunk_size), |middle| {
+            G::split(middle, split_type, chunk_size)
+        })
+    }
+}
+
+/// Composition of two programs: the second program is executed on the output of the first one
+pub struct ComposedProgram {
+    first: Box<dyn SplitProgram>,
+    second: Box<dyn SplitProgram>,
+}
+
+impl ComposedProgram {
+    /// Composes the programs, checking that the output size of the first
+    /// one is equal to the input size of the second one
+    pub fn new(
+        first: Box<dyn SplitProgram>,
+        second: Box<dyn SplitProgram>,
+    ) -> Result<Self, CompositionError> {
+        if first.output_size() != second.input_size() {
+            return Err(CompositionError::SizeMismatch {
+                output_size: first.output_size(),
+                input_size: second.input_size(),
+            });
+        }
+
+        Ok(Self { first, second })
+    }
+}
+
+impl SplitProgram for ComposedProgram {
+    fn input_size(&self) -> usize {
+        self.first.input_size()
+    }
+
+    fn output_size(&self) -> usize {
+        self.second.output_size()
+    }
+
+    fn script(&self) -> Script {
+        script! {
+            { self.first.script() }
+            { self.second.script() }
+        }
+    }
+
+    fn annotated_script(&self) -> AnnotatedScript {
+        let mut script = self.first.annotated_script();
+        script.push(self.second.annotated_script());
+        script
+    }
+
+    fn generate_valid_io_pair(&self) -> ProgramIOPair {
+        let ProgramIOPair { input, output } = self.first.generate_valid_io_pair();
+
+        ProgramIOPair {
+            input,
+            output: chained_output(&output, &self.second.script()),
+        }
+    }
+
+    fn generate_invalid_io_pair(&self) -> ProgramIOPair {
+        let ProgramIOPair { input, output } = self.generate_valid_io_pair();
+
+        ProgramIOPair {
+            input,
+            output: distorted_output(&output),
+        }
+    }
+
+    fn default_split(&self, input: Script, split_type: SplitType) -> SplitResult {
+        chain_splits(
+            self.first.default_split(input, split_type.clone()),
+            |middle| self.second.default_split(middle, split_type),
+        )
+    }
+
+    fn split(&self, input: Script, split_type: SplitType, chunk_size: usize) -> SplitResult {
+        chain_splits(
+            self.first.split(input, split_type.clone(), chunk_size),
+            |middle| self.second.split(middle, split_type, chunk_size),
+        )
+    }
+}
+
+/// Executes the script on the given input and returns the script pushing the resultant stack
+fn chained_output(input: &Script, script: &Script) -> Script {
+    let state = IntermediateState::from_input_script(input, script);
+    stack_to_script(&state.stack)
+}
+
+/// Returns the output with the top element replaced by a different one
+fn distorted_output(output: &Script) -> Script {
+    let mut elements: Vec<Vec<u8>> = IntermediateState::from_inject_script(output)
+        .stack
+        .iter_str()
+        .collect();
+
+    match elements.last_mut() {
+        Some(last) if last.is_empty() => *last = vec![1],
+        Some(last) => last.clear(),
+        None => elements.push(vec![1]),
+    }
+
+    script! {
+        for element in elements {
+            { element }
+        }
+    }
+}
+
+/// Appends the split of the second component, made for the last state of the first one
+fn chain_splits(first: SplitResult, second: impl FnOnce(Script) -> SplitResult) -> SplitResult {
+    let middle = first.must_last_state().inject_script();
+    let second = second(middle);
+
+    SplitResult::new(
+        first.shards.into_iter().chain(second.shards).collect(),
+        first
+            .intermediate_states
+            .into_iter()
+            .chain(second.intermediate_states)
+            .collect(),
+    )
+}
@@ -0,0 +1,102 @@
+//! Module containing the [`ConditionTracker`], which follows the nesting of the
+//! `OP_IF`/`OP_NOTIF ... OP_ELSE ... OP_ENDIF` blocks, so that the splitter cuts
+//! the script only into well-formed fragments.
+
+use bitcoin::{
+    opcodes::all::{OP_ELSE, OP_ENDIF, OP_IF, OP_NOTIF},
+    script::Instruction,
+};
+use bitcoin_utils::treepp::*;
+
+use super::error::SplitError;
+
+/// Error of the conditional nesting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConditionError {
+    /// `OP_ELSE` outside of any conditional block
+    UnexpectedElse,
+    /// `OP_ENDIF` outside of any conditional block
+    UnexpectedEndif,
+    /// The conditional block is not closed by the end of the fragment
+    UnclosedBlock,
+}
+
+/// Tracker of the conditional nesting
+#[derive(Debug, Clone, Default)]
+pub struct ConditionTracker {
+    /// Number of the currently open blocks
+    depth: usize,
+}
+
+impl ConditionTracker {
+    /// Creates the tracker outside of any conditional block
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Processes the next instruction
+    pub fn step(&mut self, instruction: &Instruction) -> Result<(), ConditionError> {
+        let Instruction::Op(op) = instruction else {
+            return Ok(());
+        };
+
+        match *op {
+            OP_IF | OP_NOTIF => self.depth += 1,
+            OP_ELSE if self.depth == 0 => return Err(ConditionError::UnexpectedElse),
+            OP_ENDIF => {
+                self.depth = self
+                    .depth
+                    .checked_sub(1)
+                    .ok_or(ConditionError::UnexpectedEndif)?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of the currently open blocks
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns whether all the blocks are closed, that is, whether the script can be cut here
+    pub fn is_balanced(&self) -> bool {
+        self.depth == 0
+    }
+}
+
+/// Checks that the conditional blocks of the shard are well-formed. Returns the
+/// byte position of the offending instruction within the shard otherwise.
+pub fn validate_shard(shard: &Script) -> Result<(), (usize, ConditionError)> {
+    let mut tracker = ConditionTracker::new();
+
+    for (position, instruction) in shard.instruction_indices() {
+        // Corrupted scripts are reported by the splitter itself, so here we stop at them
+        let Ok(instruction) = instruction else {
+            break;
+        };
+        tracker
+            .step(&instruction)
+            .map_err(|error| (position, error))?;
+    }
+
+    if !tracker.is_balanced() {
+        return Err((shard.len(), ConditionError::UnclosedBlock));
+    }
+
+    Ok(())
+}
+
+/// Checks that each shard is a well-formed fragment, see [`validate_shard`]
+pub fn validate_shards(shards: &[Script]) -> Result<(), SplitError> {
+    for (shard_id, shard) in shards.iter().enumerate() {
+        validate_shard(shard).map_err(|(position, error)| SplitError::MalformedShard {
+            shard_id,
+            position,
+            error,
+        })?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,441 @@
+//! Module containing the split under the resource limits of the disprove scripts.
+//!
+//! The size of the shard alone says little about whether its disprove script can be spent:
+//! the script also verifies the signed states before and after the shard, the witness holds
+//! their signatures, and the whole execution must keep within the Tapscript stack limit.
+//! The [`DisproveModel`] bounds all three from the state sizes, and [`constrained_split`]
+//! picks the cuts such that every disprove script fits into the given [`SplitConstraints`].
+
+use std::collections::{BTreeMap, VecDeque};
+
+use bitcoin_utils::{debug::script_exec_with_stack, treepp::*};
+
+use super::{
+    conditional::validate_shard,
+    core::{form_states_incrementally, split_at_instructions},
+    encoding::{decode_element_script, encode_elements, group_words, TAG_FLAG},
+    error::{Constraint, ConstraintError, EncodingError, SplitError},
+    hints::AnnotatedScript,
+    intermediate_state::IntermediateState,
+    profile::ScriptProfile,
+    script::SplitResult,
+};
+
+/// Maximum number of elements in the stack and altstack together allowed by Tapscript
+pub const MAX_STACK_ITEMS: usize = 1000;
+
+/// Maximum weight of the standard transaction. Witness bytes weigh one unit each,
+/// so the leaf script, the witness and the control block together cannot exceed it.
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+
+/// Weight of the transaction spending the disprove script besides its witness: the version,
+/// a single input and a single output to a Taproot address, and the lock time (94 bytes
+/// weighing four units each), plus the segwit marker and flag
+pub const BASE_TX_WEIGHT: usize = 4 * 94 + 2;
+
+/// Size of the control block of the leaf at the largest depth of the tree allowed by BIP 341
+pub const MAX_CONTROL_BLOCK_SIZE: usize = 33 + 32 * 128;
+
+/// Bytes of the witness besides the leaf script and the items pushed for it: the number
+/// of the items and the length prefixes of the leaf script and the control block
+pub const WITNESS_FRAMING_SIZE: usize = 3 + 5 + 3;
+
+/// Upper bounds of the resources taken by the disprove script as linear functions of the
+/// sizes of the states before and after the shard. The sizes are counted in the signed words,
+/// which is the number of elements unless the state has wide elements (see [`super::encoding`]).
+/// The coefficients depend on the signature scheme, so they are measured by its implementation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisproveModel {
+    /// Bytes of the leaf script besides the shard which do not depend on the states
+    pub leaf_overhead: usize,
+    /// Bytes of the leaf script per element of the state before the shard
+    pub leaf_bytes_per_input_element: usize,
+    /// Bytes of the leaf script per element of the state after the shard
+    pub leaf_bytes_per_output_element: usize,
+    /// Bytes of the witness per element of both states
+    pub witness_bytes_per_element: usize,
+    /// Items pushed by the witness per element of both states
+    pub witness_items_per_element: usize,
+    /// Items added on top of the witness or the shard execution by the verification itself
+    pub verification_items: usize,
+}
+
+impl DisproveModel {
+    /// Returns the bound of the leaf script size for the shard of the given size
+    pub fn leaf_size(
+        &self,
+        shard_size: usize,
+        from_state_size: usize,
+        to_state_size: usize,
+    ) -> usize {
+        self.leaf_overhead
+            + shard_size
+            + self.leaf_bytes_per_input_element * from_state_size
+            + self.leaf_bytes_per_output_element * to_state_size
+    }
+
+    /// Returns the bound of the witness size
+    pub fn witness_size(&self, from_state_size: usize, to_state_size: usize) -> usize {
+        self.witness_bytes_per_element * (from_state_size + to_state_size)
+    }
+
+    /// Returns the bound of the number of items in the stack and altstack during the
+    /// execution, given the largest state reached inside the shard. While the shard is
+    /// executed, the verified state after it is kept in the altstack.
+    pub fn stack_items(
+        &self,
+        peak_state_size: usize,
+        from_state_size: usize,
+        to_state_size: usize,
+    ) -> usize {
+        let witness_items = self.witness_items_per_element * (from_state_size + to_state_size);
+        witness_items.max(peak_state_size + to_state_size) + self.verification_items
+    }
+}
+
+/// Limits every disprove script of the split must fit into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SplitConstraints {
+    /// Maximum size of the leaf script in bytes
+    pub max_leaf_size: usize,
+    /// Maximum number of items in the stack and altstack together during the execution
+    pub max_stack_items: usize,
+    /// Maximum size of the witness in bytes
+    pub max_witness_size: usize,
+    /// Maximum weight of the spending transaction, which counts the leaf script,
+    /// the witness and everything else in the transaction together
+    pub max_tx_weight: usize,
+    /// Weight of the spending transaction besides the leaf script and the witness
+    pub tx_overhead: usize,
+    /// Model bounding the resources of the disprove script
+    pub model: DisproveModel,
+}
+
+impl SplitConstraints {
+    /// Creates the constraints of the standard Tapscript spending. Neither the leaf script
+    /// nor the witness can take the weight left after the rest of the transaction and the
+    /// control block of the deepest leaf, while both of them together are bounded by the
+    /// weight of the whole transaction.
+    pub fn tapscript(model: DisproveModel) -> Self {
+        let tx_overhead = BASE_TX_WEIGHT + MAX_CONTROL_BLOCK_SIZE + WITNESS_FRAMING_SIZE;
+
+        Self {
+            max_leaf_size: MAX_STANDARD_TX_WEIGHT - tx_overhead,
+            max_stack_items: MAX_STACK_ITEMS,
+            max_witness_size: MAX_STANDARD_TX_WEIGHT - tx_overhead,
+            max_tx_weight: MAX_STANDARD_TX_WEIGHT,
+            tx_overhead,
+            model,
+        }
+    }
+
+    /// Checks the disprove script of the shard, returning the first violated constraint
+    /// together with the required value and the limit
+    pub fn check(
+        &self,
+        shard_size: usize,
+        peak_state_size: usize,
+        from_state_size: usize,
+        to_state_size: usize,
+    ) -> Result<(), (Constraint, usize, usize)> {
+        let leaf_size = self
+            .model
+            .leaf_size(shard_size, from_state_size, to_state_size);
+        if leaf_size > self.max_leaf_size {
+            return Err((Constraint::LeafSize, leaf_size, self.max_leaf_size));
+        }
+
+        let stack_items = self
+            .model
+            .stack_items(peak_state_size, from_state_size, to_state_size);
+        if stack_items > self.max_stack_items {
+            return Err((Constraint::StackItems, stack_items, self.max_stack_items));
+        }
+
+        let witness_size = self.model.witness_size(from_state_size, to_state_size);
+        if witness_size > self.max_witness_size {
+            return Err((Constraint::WitnessSize, witness_size, self.max_witness_size));
+        }
+
+        let tx_weight = self.tx_overhead + leaf_size + witness_size;
+        if tx_weight > self.max_tx_weight {
+            return Err((Constraint::TxWeight, tx_weight, self.max_tx_weight));
+        }
+
+        Ok(())
+    }
+}
+
+/// Size of the state as it is signed in the disprove script
+#[derive(Debug, Clone, Copy, Default)]
+struct SignedSize {
+    /// Number of the signed words
+    words: usize,
+    /// Upper bound of the bytes of the leaf script restoring the elements from their words,
+    /// besides the ones restoring the plain words which are accounted by the model
+    decoding_bytes: usize,
+}
+
+impl SignedSize {
+    /// Returns whether any shard fits at least as well when it starts or ends
+    /// at the state of this size as at the state of the other one
+    fn dominates(&self, other: &SignedSize) -> bool {
+        self.words <= other.words && self.decoding_bytes <= other.decoding_bytes
+    }
+}
+
+/// Returns the size of the state with the given elements as it is signed in the disprove
+/// script, or an error if the disprove script cannot restore some of its elements
+fn signed_size(elements: impl IntoIterator<Item = Vec<u8>>) -> Result<SignedSize, EncodingError> {
+    let decoding_size = |element: &[u32]| -> Result<usize, EncodingError> {
+        Ok(decode_element_script(element, true)?
+            .len()
+            .max(decode_element_script(element, false)?.len()))
+    };
+    let plain_decoding_size = decoding_size(&[0])?;
+
+    let words = encode_elements(elements);
+    let mut decoding_bytes = 0;
+    for element in group_words(&words) {
+        // Small non-negative numbers are restored the same way as any plain word
+        if let [word] = element {
+            if *word < TAG_FLAG {
+                continue;
+            }
+        }
+        decoding_bytes += decoding_size(element)?.saturating_sub(plain_decoding_size);
+    }
+
+    Ok(SignedSize {
+        words: words.len(),
+        decoding_bytes,
+    })
+}
+
+/// Executes the script once more and returns the signed size of the state at each boundary
+/// of its profile. The boundaries at which the disprove script cannot restore the state are
+/// marked as illegal, and such a state at the start or at the end of the script is an error.
+fn signed_sizes(
+    input: &Script,
+    script: &Script,
+    profile: &mut ScriptProfile,
+) -> Result<Vec<SignedSize>, EncodingError> {
+    let initial_state = IntermediateState::from_inject_script(input);
+    let mut current_size = signed_size(
+        initial_state
+            .stack
+            .iter_str()
+            .chain(initial_state.altstack.iter_str()),
+    )?;
+
+    // Similarly to the profile, the boundaries after the failed instruction
+    // keep the size of the last state
+    let mut exec = script_exec_with_stack(
+        script.clone(),
+        initial_state.stack.clone(),
+        initial_state.altstack.clone(),
+    );
+    let mut sizes = vec![current_size];
+    for boundary in 1..=profile.len() {
+        if exec.exec_next().is_ok() {
+            match signed_size(exec.stack().iter_str().chain(exec.altstack().iter_str())) {
+                Ok(size) => current_size = size,
+                Err(error) if boundary == profile.len() => return Err(error),
+                Err(_) => profile.legal[boundary] = false,
+            }
+        }
+        sizes.push(current_size);
+    }
+
+    Ok(sizes)
+}
+
+/// Reachable boundary that can start the next shard
+struct Predecessor {
+    boundary: usize,
+    /// Largest state from the boundary up to the current one
+    peak_state_size: usize,
+}
+
+/// Splits the script so that the disprove script of every shard fits into the constraints.
+///
+/// We go over the boundaries with the dynamic programming: the boundary is reachable if it is
+/// legal and some reachable boundary before it starts the shard which fits, and each boundary
+/// is reached with the fewest shards. Among the boundaries reached with the same number of shards,
+/// a boundary is dominated by the later one with the state that is not larger, since any shard
+/// starting at the former fits when started at the latter. Thus, for each number of shards
+/// the boundaries dominated by the next one are dropped.
+///
+/// The states are measured as they are signed, that is, in the words together with the bytes
+/// restoring the wide elements (see `super::encoding`), and the boundaries at which the state
+/// cannot be restored are never cut. If no split fits, returns [`ConstraintError::Blocked`]
+/// naming the region that cannot be covered by any fitting shard, and if the input or the
+/// output of the script cannot be restored, returns [`ConstraintError::Encoding`].
+pub fn constrained_split(
+    input: Script,
+    script: impl Into<AnnotatedScript>,
+    constraints: &SplitConstraints,
+) -> Result<SplitResult, ConstraintError> {
+    let script = script.into();
+    let hints = script.checked_hints()?;
+    let script = script.into_script();
+    validate_shard(&script).map_err(|(position, error)| SplitError::MalformedShard {
+        shard_id: 0,
+        position,
+        error,
+    })?;
+
+    let mut profile = ScriptProfile::execute(&input, &script);
+    hints.restrict(&mut profile);
+    let sizes = signed_sizes(&input, &script, &mut profile)?;
+
+    let cuts = constrained_cuts(&profile, &sizes, constraints)?;
+    let shards = split_at_instructions(&script, &cuts);
+    let intermediate_states = form_states_incrementally(&shards, input);
+
+    Ok(SplitResult::new(shards, intermediate_states))
+}
+
+/// Finds the cuts such that every shard fits into the constraints, see [`constrained_split`]
+fn constrained_cuts(
+    profile: &ScriptProfile,
+    sizes: &[SignedSize],
+    constraints: &SplitConstraints,
+) -> Result<Vec<usize>, ConstraintError> {
+    let boundaries_number = profile.len() + 1;
+    let mut predecessors: Vec<Option<usize>> = vec![None; boundaries_number];
+    let mut last_reachable = 0;
+
+    // Reachable boundaries which are not dominated, by the number of shards before them
+    let mut fronts: BTreeMap<usize, VecDeque<Predecessor>> = BTreeMap::from([(
+        0,
+        VecDeque::from([Predecessor {
+            boundary: 0,
+            peak_state_size: profile.state_sizes[0],
+        }]),
+    )]);
+
+    for j in 1..boundaries_number {
+        let state_size = profile.state_sizes[j];
+        let size = sizes[j];
+
+        for front in fronts.values_mut() {
+            // The leaf script is never smaller than the shard itself
+            while front
+                .front()
+                .is_some_and(|i| profile.shard_size(i.boundary, j) > constraints.max_leaf_size)
+            {
+                front.pop_front();
+            }
+            for predecessor in front.iter_mut() {
+                predecessor.peak_state_size = predecessor.peak_state_size.max(state_size);
+            }
+        }
+        fronts.retain(|_, front| !front.is_empty());
+
+        if !profile.legal[j] {
+            continue;
+        }
+
+        // Earlier predecessors give larger shards with larger peaks, so once the shard
+        // or the peak does not fit, there is no point to look further
+        let chosen = fronts.iter().find_map(|(&shards_number, front)| {
+            front
+                .iter()
+                .rev()
+                .take_while(|i| {
+                    profile.shard_size(i.boundary, j) <= constraints.max_leaf_size
+                        && i.peak_state_size + size.words <= constraints.max_stack_items
+                })
+                .find(|i| check_shard(profile, sizes, constraints, i, j).is_ok())
+                .map(|i| (shards_number, i.boundary))
+        });
+
+        let Some((shards_number, i)) = chosen else {
+            continue;
+        };
+        predecessors[j] = Some(i);
+        last_reachable = j;
+
+        let front = fronts.entry(shards_number + 1).or_default();
+        while front
+            .back()
+            .is_some_and(|i| size.dominates(&sizes[i.boundary]))
+        {
+            front.pop_back();
+        }
+        front.push_back(Predecessor {
+            boundary: j,
+            peak_state_size: state_size,
+        });
+    }
+
+    if boundaries_number > 1 && predecessors[boundaries_number - 1].is_none() {
+        return Err(blocked_region(profile, sizes, constraints, last_reachable));
+    }
+
+    // Restoring the cuts by going back from the end of the script
+    let mut cuts = vec![];
+    let mut boundary = boundaries_number - 1;
+    while boundary != 0 {
+        boundary = predecessors[boundary].expect("reachable boundaries have predecessors");
+        if boundary != 0 {
+            cuts.push(boundary);
+        }
+    }
+    cuts.reverse();
+
+    Ok(cuts)
+}
+
+/// Returns the error naming the shortest shard that starts at the last reachable boundary.
+/// Since the boundary after it is not reachable, the shard does not fit.
+fn blocked_region(
+    profile: &ScriptProfile,
+    sizes: &[SignedSize],
+    constraints: &SplitConstraints,
+    start: usize,
+) -> ConstraintError {
+    let end = (start + 1..=profile.len())
+        .find(|&j| profile.legal[j] || j == profile.len())
+        .unwrap_or(profile.len());
+    let predecessor = Predecessor {
+        boundary: start,
+        peak_state_size: profile.state_sizes[start..=end]
+            .iter()
+            .copied()
+            .max()
+            .unwrap_or(0),
+    };
+
+    let (constraint, required, limit) = check_shard(profile, sizes, constraints, &predecessor, end)
+        .expect_err("the shard after the last reachable boundary cannot fit");
+
+    ConstraintError::Blocked {
+        start,
+        end,
+        constraint,
+        required,
+        limit,
+    }
+}
+
+/// Checks whether the shard from the given predecessor up to the boundary `end` fits
+fn check_shard(
+    profile: &ScriptProfile,
+    sizes: &[SignedSize],
+    constraints: &SplitConstraints,
+    predecessor: &Predecessor,
+    end: usize,
+) -> Result<(), (Constraint, usize, usize)> {
+    let (from_size, to_size) = (sizes[predecessor.boundary], sizes[end]);
+
+    constraints.check(
+        profile.shard_size(predecessor.boundary, end)
+            + from_size.decoding_bytes
+            + to_size.decoding_bytes,
+        predecessor.peak_state_size,
+        from_size.words,
+        to_size.words,
+    )
+}
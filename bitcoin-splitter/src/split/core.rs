@@ -1,15 +1,21 @@
 //! Module containing the logic of splitting the script into smaller parts
 
-use std::panic;
-
-use bitcoin::{
-    opcodes::all::{OP_ENDIF, OP_IF, OP_NOTIF},
-    script::Instruction,
+use std::{
+    cmp::Reverse,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
 };
-use bitcoin_utils::treepp::*;
-use indicatif::ProgressBar;
 
-use super::script::SplitResult;
+use bitcoin_utils::{debug::script_exec_with_stack, treepp::*};
+
+use super::{
+    conditional::validate_shards, error::SplitError, hints::AnnotatedScript, metric::SplitMetric,
+    observer::SplitObserver, profile::ScriptProfile, script::SplitResult, stream::Shards,
+    weights::OpcodeWeights,
+};
 use crate::split::intermediate_state::IntermediateState;
 
 /// Optimal size of the script in bytes
@@ -19,7 +25,7 @@ use crate::split::intermediate_state::IntermediateState;
 /// that will be used to split the script and the algorithm
 /// will try to keep the size of the script as close to the optimal
 /// as possible.
-pub(super) const DEFAULT_SCRIPT_SIZE: usize = 7000;
+pub const DEFAULT_SCRIPT_SIZE: usize = 7000;
 
 /// Maximum scriptsize in bytes that is allowed by the Bitcoin network
 pub(super) const MAX_SCRIPT_SIZE: usize = 50000;
@@ -33,121 +39,295 @@ pub(super) const MAX_SCRIPT_SIZE: usize = 50000;
 /// `shard_size_i + (z_i_size + z_(i-1)_size) * STACK_SIZE_INDEX`
 ///
 /// The `STACK_SIZE_INDEX` is the factor of how much intermediate states
-/// are contributing to the total size of the script.
-pub(super) const STACK_SIZE_INDEX: usize = 1000;
+/// are contributing to the total size of the script. It is the default
+/// `alpha` of the metrics in [`super::metric`].
+pub const STACK_SIZE_INDEX: usize = 1000;
 
 /// Type of the split that we are going to use
 ///
 /// - [`SplitType::ByInstructions`]- splits the script by the number of instructions
 /// - [`SplitType::ByBytes`] - splits the script by the number of bytes
-#[derive(Debug, Clone, Copy, Default)]
+/// - [`SplitType::ByStackSize`] - splits the script by the number of bytes, but within
+///   `tolerance` bytes around the chunk size cuts where the state (stack + altstack) is the smallest
+/// - [`SplitType::ByWeight`] - splits the script by the total weight of the instructions
+///   according to the given [`OpcodeWeights`] table
+#[derive(Debug, Clone, Default)]
 pub enum SplitType {
     #[default]
     ByInstructions,
     ByBytes,
+    ByStackSize {
+        tolerance: usize,
+    },
+    ByWeight(Arc<OpcodeWeights>),
 }
 
 /// Splits the given script into smaller parts. Tries to keep each chunk size
 /// to the optimal size `chunk_size` as close as possible.
-pub fn split_into_shards(script: &Script, chunk_size: usize, split_type: SplitType) -> Vec<Script> {
-    let instructions: Vec<Instruction> = script
-        .instructions()
-        .map(|instruction| instruction.expect("script is most likely corrupted"))
-        .collect();
+///
+/// Panics if the script cannot be split, see [`try_split_into_shards`] for the
+/// non-panicking version.
+pub fn split_into_shards(
+    script: impl Into<AnnotatedScript>,
+    chunk_size: usize,
+    split_type: SplitType,
+) -> Vec<Script> {
+    try_split_into_shards(script, chunk_size, split_type)
+        .unwrap_or_else(|err| panic!("failed to split the script: {:?}", err))
+}
 
-    // Now, we are going to collect the chunks
-    let mut shards: Vec<Script> = vec![Script::new()];
+/// Splits the given script into smaller parts. Tries to keep each chunk size
+/// to the optimal size `chunk_size` as close as possible.
+///
+/// The script can be given together with the split hints (see [`super::hints`]):
+/// the shard is closed only at the boundary allowed by them. Note that with
+/// [`SplitType::ByInstructions`] the instructions are counted over the whole script, so the
+/// cut is considered only after every `chunk_size`-th instruction, and the chunk size of `1`
+/// is needed to cut at every allowed boundary.
+///
+/// Returns an error if the script is corrupted or some shard exceeds the maximum script size.
+/// Since [`SplitType::ByStackSize`] needs to execute the script, it is supported only
+/// by [`try_split_into_shards_with_input`].
+pub fn try_split_into_shards(
+    script: impl Into<AnnotatedScript>,
+    chunk_size: usize,
+    split_type: SplitType,
+) -> Result<Vec<Script>, SplitError> {
+    // The shards are cut lazily, so the instructions of the script are never collected
+    Shards::new(&script.into(), chunk_size, split_type)?.collect()
+}
 
-    // Now, one of the biggest problems that can occur is that OP_IF and OP_ENDIF are not in the same shard.
-    // For that reason, we count both of them and if the number of OP_IFs is not equal to the number of OP_ENDIFs,
-    // we need to continue splitting the script until we have the same number of both.
-    let mut if_count = 0;
-    let mut endif_count = 0;
-
-    for (instruction_id, instruction) in instructions.clone().into_iter().enumerate() {
-        // Pushing the instruction to the current shard.
-        let current_shard = shards.last_mut().expect("shards should not be empty");
-        current_shard.push_instruction(instruction);
-
-        let current_shard_size = match split_type {
-            SplitType::ByInstructions => instruction_id % chunk_size + 1,
-            SplitType::ByBytes => current_shard.len(),
-        };
-
-        // Checking if the current instruction is OP_IF or OP_ENDIF
-        if let Instruction::Op(op) = instruction {
-            match op {
-                OP_IF | OP_NOTIF => {
-                    if_count += 1;
-                }
-                OP_ENDIF => {
-                    endif_count += 1;
-                }
-                _ => {}
+/// Splits the given script into smaller parts, executing it with the given input if the
+/// split type needs to know the state at each instruction (see [`SplitType::ByStackSize`]).
+/// For other split types, this is the same as [`try_split_into_shards`].
+pub fn try_split_into_shards_with_input(
+    input: &Script,
+    script: impl Into<AnnotatedScript>,
+    chunk_size: usize,
+    split_type: SplitType,
+) -> Result<Vec<Script>, SplitError> {
+    let SplitType::ByStackSize { tolerance } = split_type else {
+        return try_split_into_shards(script, chunk_size, split_type);
+    };
+
+    // Checking the hints also makes sure the script is not corrupted before profiling it
+    let script = script.into();
+    let hints = script.checked_hints()?;
+
+    let mut profile = ScriptProfile::execute(input, script.script());
+    hints.restrict(&mut profile);
+    let cuts = stack_size_cuts(&profile, chunk_size, tolerance);
+    let shards = try_split_at_instructions(script.script(), &cuts)?;
+    validate_shards(&shards)?;
+
+    if let Some((shard_id, shard)) = shards
+        .iter()
+        .enumerate()
+        .find(|(_, shard)| shard.len() > MAX_SCRIPT_SIZE)
+    {
+        return Err(SplitError::ShardTooLarge {
+            shard_id,
+            size: shard.len(),
+        });
+    }
+
+    Ok(shards)
+}
+
+/// Finds the cuts for [`SplitType::ByStackSize`]. Each shard is closed at the legal boundary
+/// with the smallest state among the ones lying within `[chunk_size - tolerance, chunk_size + tolerance]`
+/// bytes from the shard start (the latest one in case of a tie). If there is no such boundary,
+/// the shard is closed at the first legal boundary after the window.
+pub(super) fn stack_size_cuts(
+    profile: &ScriptProfile,
+    chunk_size: usize,
+    tolerance: usize,
+) -> Vec<usize> {
+    let min_size = chunk_size.saturating_sub(tolerance);
+    let max_size = chunk_size + tolerance;
+
+    let mut cuts = vec![];
+    let mut start = 0;
+    let end = profile.len();
+
+    while profile.shard_size(start, end) > max_size {
+        let mut legal_boundaries = (start + 1..end).filter(|&j| profile.legal[j]);
+
+        let cut = legal_boundaries
+            .clone()
+            .take_while(|&j| profile.shard_size(start, j) <= max_size)
+            .filter(|&j| profile.shard_size(start, j) >= min_size)
+            .min_by_key(|&j| (profile.state_sizes[j], Reverse(j)))
+            .or_else(|| legal_boundaries.find(|&j| profile.shard_size(start, j) > max_size));
+
+        match cut {
+            Some(cut) => {
+                cuts.push(cut);
+                start = cut;
             }
+            // There are no legal boundaries left, so the rest is the last shard
+            None => break,
         }
+    }
+
+    cuts
+}
+
+/// Splits the given script into shards at the given instruction indices. Each cut `k`
+/// closes the current shard right before the `k`-th instruction of the script.
+///
+/// Cuts must be strictly increasing and lie inside the script. Panics otherwise, see
+/// [`try_split_at_instructions`] for the non-panicking version.
+pub fn split_at_instructions(script: &Script, cuts: &[usize]) -> Vec<Script> {
+    try_split_at_instructions(script, cuts)
+        .unwrap_or_else(|err| panic!("failed to split the script: {:?}", err))
+}
 
-        // If the current shard is too big AND number of ifs and endifs
-        // is the same, we need to create a new one
-        if current_shard_size >= chunk_size && if_count == endif_count {
+/// Splits the given script into shards at the given instruction indices, which returns
+/// an error if the script is corrupted or some cut is not strictly increasing or does
+/// not lie inside the script.
+pub fn try_split_at_instructions(
+    script: &Script,
+    cuts: &[usize],
+) -> Result<Vec<Script>, SplitError> {
+    let mut shards: Vec<Script> = vec![Script::new()];
+    let mut cuts = cuts.iter().peekable();
+
+    for (instruction_id, instruction) in script.instructions().enumerate() {
+        let instruction = instruction?;
+
+        // Closing the current shard if the cut is placed right before this instruction
+        if instruction_id > 0 && cuts.next_if_eq(&&instruction_id).is_some() {
             shards.push(Script::new());
-            if_count = 0;
-            endif_count = 0;
+        }
+        // The next cut must come after this instruction
+        if let Some(&&cut) = cuts.peek().filter(|&&&cut| cut <= instruction_id) {
+            return Err(SplitError::InvalidCut { cut });
         }
 
-        // Checking that the total size has not exceeded the maximum size
-        assert!(
-            current_shard_size <= MAX_SCRIPT_SIZE,
-            "Script size has exceeded the maximum size"
-        );
+        shards
+            .last_mut()
+            .expect("shards should not be empty")
+            .push_instruction(instruction);
     }
 
-    shards
+    // The cuts left lie beyond the last instruction
+    match cuts.next() {
+        Some(&cut) => Err(SplitError::InvalidCut { cut }),
+        None => Ok(shards),
+    }
 }
 
 /// Fuzzy split of the script into smaller parts by searching for the optimal size
-/// by checking various script sizes
-pub fn fuzzy_split(input: Script, script: Script, split_type: SplitType) -> SplitResult {
+/// by checking various script sizes. Splits are compared according to the given `metric`.
+///
+/// Candidate chunk sizes are checked in parallel by a pool of worker threads, while the
+/// `observer` is notified about the progress and the best candidate found so far from
+/// the calling thread. Once the observer cancels the search, the best split found
+/// so far is returned. Among the candidates of the same cost, the smallest chunk size wins,
+/// so the result does not depend on the order in which the workers finish.
+/// The candidates whose split fails are skipped, being reported to the observer as failed.
+pub fn fuzzy_split(
+    input: Script,
+    script: impl Into<AnnotatedScript>,
+    split_type: SplitType,
+    metric: &impl SplitMetric,
+    observer: &mut impl SplitObserver,
+) -> Result<SplitResult, SplitError> {
+    let script = script.into();
+
     // Define the limits
     const MIN_CHUNK_SIZE: usize = 100;
     const MAX_CHUNK_SIZE: usize = MAX_SCRIPT_SIZE;
     const STEP_SIZE: usize = 20;
 
-    // Defining the final result
-    let mut resultant_split = SplitResult::new(vec![], vec![]);
-    let mut resultant_complexity = usize::MAX;
-
-    // Now, displaying the progress bar
-    let total_progress = (MAX_CHUNK_SIZE - MIN_CHUNK_SIZE) / STEP_SIZE;
-    let bar = ProgressBar::new(total_progress as u64);
-
-    // Trying to find the optimal size of the chunk by checking
-    // each size from MIN_CHUNK_SIZE to MAX_CHUNK_SIZE
-    for chunk_size in (MIN_CHUNK_SIZE..MAX_CHUNK_SIZE).step_by(STEP_SIZE) {
-        // Incrementing the progress bar
-        bar.inc(1);
-        // We are using panic::catch_unwind to catch any panics that might occur
-        // during the splitting process. If a panic occurs, we just skip the current
-        // chunk size and continue with the next one.
-        let current_split_result = panic::catch_unwind(|| {
-            naive_split(input.clone(), script.clone(), split_type, chunk_size)
-        });
-        if let Ok(split_result) = current_split_result {
-            let current_complexity = split_result.complexity_index();
+    let chunk_sizes: Vec<usize> = (MIN_CHUNK_SIZE..MAX_CHUNK_SIZE)
+        .step_by(STEP_SIZE)
+        .collect();
+    let total = chunk_sizes.len();
+    let workers_number = thread::available_parallelism().map_or(1, |n| n.get());
+
+    // Index of the next candidate to be picked by the workers
+    let next_candidate = AtomicUsize::new(0);
+    let cancelled = AtomicBool::new(false);
+
+    // Best candidate in the form of (cost, chunk_size)
+    let mut best: Option<(usize, usize)> = None;
+
+    observer.on_start(total);
+    thread::scope(|scope| {
+        // Workers send back only the cost of the candidate since
+        // the split result itself cannot be sent between threads
+        let (sender, receiver) = mpsc::channel::<(usize, Result<usize, SplitError>)>();
+
+        for _ in 0..workers_number {
+            let sender = sender.clone();
+            let split_type = split_type.clone();
+            let (input, script) = (&input, &script);
+            let (next_candidate, cancelled, chunk_sizes) =
+                (&next_candidate, &cancelled, &chunk_sizes);
+
+            scope.spawn(move || {
+                while !cancelled.load(Ordering::Relaxed) {
+                    let Some(&chunk_size) =
+                        chunk_sizes.get(next_candidate.fetch_add(1, Ordering::Relaxed))
+                    else {
+                        break;
+                    };
+
+                    let cost = try_naive_split(
+                        input.clone(),
+                        script.clone(),
+                        split_type.clone(),
+                        chunk_size,
+                    )
+                    .map(|split_result| metric.evaluate(&split_result));
+                    if sender.send((chunk_size, cost)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (processed, (chunk_size, cost)) in receiver.iter().enumerate() {
+            match cost {
+                Ok(cost) => {
+                    let is_better = match best {
+                        Some(best) => (cost, chunk_size) < best,
+                        None => true,
+                    };
+                    if is_better {
+                        best = Some((cost, chunk_size));
+                        observer.on_new_best(chunk_size, cost);
+                    }
+                }
+                Err(err) => observer.on_candidate_failed(chunk_size, &err),
+            }
 
-            if current_complexity < resultant_complexity {
-                resultant_complexity = current_complexity;
-                resultant_split = split_result;
+            observer.on_progress(processed + 1, total);
+            if observer.is_cancelled() {
+                cancelled.store(true, Ordering::Relaxed);
+                break;
             }
         }
+    });
+    observer.on_finish();
+
+    match best {
+        // Split results are not kept during the search, so we repeat the best one
+        Some((_, chunk_size)) => try_naive_split(input, script, split_type, chunk_size),
+        None if cancelled.load(Ordering::Relaxed) => Err(SplitError::Cancelled),
+        None => Err(SplitError::NoValidSplit),
     }
-
-    bar.finish();
-    resultant_split
 }
 
 /// Default split of the script into smaller parts with the hard-coded optimal size
-pub fn default_split(input: Script, script: Script, split_type: SplitType) -> SplitResult {
+pub fn default_split(
+    input: Script,
+    script: impl Into<AnnotatedScript>,
+    split_type: SplitType,
+) -> SplitResult {
     naive_split(input, script, split_type, DEFAULT_SCRIPT_SIZE)
 }
 
@@ -156,20 +336,35 @@ pub fn default_split(input: Script, script: Script, split_type: SplitType) -> Sp
 /// 2. We execute each shard with the input
 /// 3. Save intermediate results
 /// 4. Return all the shards and intermediate results in the form of [`SplitResult`]
+///
+/// Panics if the script cannot be split, see [`try_naive_split`] for the
+/// non-panicking version.
 pub fn naive_split(
     input: Script,
-    script: Script,
+    script: impl Into<AnnotatedScript>,
     split_type: SplitType,
     chunk_size: usize,
 ) -> SplitResult {
-    let shards = split_into_shards(&script, chunk_size, split_type);
-    let intermediate_states: Vec<IntermediateState> =
-        form_states_from_shards(shards.clone(), input);
+    try_naive_split(input, script, split_type, chunk_size)
+        .unwrap_or_else(|err| panic!("failed to split the script: {:?}", err))
+}
+
+/// Naive split of the script into smaller parts, which returns an
+/// error instead of panicking if the script cannot be split or some
+/// shard fails on the given input.
+pub fn try_naive_split(
+    input: Script,
+    script: impl Into<AnnotatedScript>,
+    split_type: SplitType,
+    chunk_size: usize,
+) -> Result<SplitResult, SplitError> {
+    let shards = try_split_into_shards_with_input(&input, script, chunk_size, split_type)?;
+    let intermediate_states = try_form_states_incrementally(&shards, input)?;
 
-    SplitResult {
+    Ok(SplitResult {
         shards,
         intermediate_states,
-    }
+    })
 }
 
 /// Given an array of shards and input, creates the vector of intermediate states
@@ -204,3 +399,90 @@ pub fn form_states_from_shards(shards: Vec<Script>, input: Script) -> Vec<Interm
 
     intermediate_states
 }
+
+/// Given an array of shards and input, creates the vector of intermediate states
+/// in a single pass: instead of injecting each state back into the script and
+/// executing the next shard, we execute all the shards at once and take the
+/// snapshot of the stack and altstack at each shard boundary.
+///
+/// The result is the same as of [`form_states_from_shards`]. In particular, if
+/// some shard fails, the state after it is the stack at the moment of the failure,
+/// and the execution continues from the next shard.
+pub fn form_states_incrementally(shards: &[Script], input: Script) -> Vec<IntermediateState> {
+    let mut intermediate_states: Vec<IntermediateState> = Vec::with_capacity(shards.len());
+    let mut current_state = IntermediateState::from_inject_script(&input);
+
+    while intermediate_states.len() < shards.len() {
+        // Executing all the remaining shards starting from the current state
+        let remaining_shards = &shards[intermediate_states.len()..];
+        let remaining_script = Script::from_bytes(
+            remaining_shards
+                .iter()
+                .flat_map(|shard| shard.as_bytes().iter().copied())
+                .collect(),
+        );
+        let mut exec = script_exec_with_stack(
+            remaining_script,
+            current_state.stack,
+            current_state.altstack,
+        );
+
+        for shard in remaining_shards {
+            let mut failed = false;
+            for _ in shard.instructions() {
+                if exec.exec_next().is_err() {
+                    failed = true;
+                    break;
+                }
+            }
+
+            // Taking the snapshot of the state at the end of the shard
+            current_state = IntermediateState {
+                stack: exec.stack().clone(),
+                altstack: exec.altstack().clone(),
+            };
+            intermediate_states.push(current_state.clone());
+
+            // The execution body cannot proceed after the failure, so we
+            // start a new one from the next shard
+            if failed {
+                break;
+            }
+        }
+    }
+
+    intermediate_states
+}
+
+/// Given an array of shards and input, creates the vector of intermediate states in
+/// a single pass like [`form_states_incrementally`], but returns an error if some shard
+/// fails instead of continuing from the state at the moment of the failure.
+pub fn try_form_states_incrementally(
+    shards: &[Script],
+    input: Script,
+) -> Result<Vec<IntermediateState>, SplitError> {
+    let initial_state = IntermediateState::from_inject_script(&input);
+    let script = Script::from_bytes(
+        shards
+            .iter()
+            .flat_map(|shard| shard.as_bytes().iter().copied())
+            .collect(),
+    );
+    let mut exec = script_exec_with_stack(script, initial_state.stack, initial_state.altstack);
+
+    shards
+        .iter()
+        .enumerate()
+        .map(|(shard_id, shard)| {
+            for _ in shard.instructions() {
+                exec.exec_next()
+                    .map_err(|_| SplitError::ExecutionFailed { shard_id })?;
+            }
+
+            Ok(IntermediateState {
+                stack: exec.stack().clone(),
+                altstack: exec.altstack().clone(),
+            })
+        })
+        .collect()
+}
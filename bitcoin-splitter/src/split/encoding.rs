@@ -0,0 +1,212 @@
+//! Module containing the encoding of the stack elements as u32 words.
+//!
+//! The elements of the intermediate states are committed word by word, and each
+//! word must fit into 31 bits. Most of the elements are small non-negative numbers,
+//! which are represented by a single word equal to the number itself. Small negative
+//! numbers (such as borrows in the big integer arithmetic) are represented by a single
+//! word `TAG_FLAG | NEGATIVE_FLAG | magnitude`. Any other element (for instance, a 20-
+//! or 32-byte hash) is represented by a header word `TAG_FLAG | length`, followed by
+//! the little-endian limbs of [`LIMB_BYTES`] bytes each.
+//!
+//! Off-chain, such encoding lets us restore the exact original element from its words.
+//! The disprove script, however, cannot concatenate the limbs: `OP_CAT` is disabled, and
+//! in Tapscript its opcode is `OP_SUCCESS126`, making any leaf containing it spendable by
+//! anyone. Thus, in the script single words are just pushed (negating them if needed),
+//! wide script numbers are combined from their limbs arithmetically, and any other
+//! element (such as a hash) cannot be restored, see [`decode_element_script`].
+
+use bitcoin::script::scriptint_vec;
+use bitcoin_utils::treepp::*;
+
+use super::error::EncodingError;
+
+/// Flag marking the header word of the element encoded by limbs
+pub const TAG_FLAG: u32 = 1 << 30;
+
+/// Flag marking the word encoding the negative number
+pub const NEGATIVE_FLAG: u32 = 1 << 29;
+
+/// Number of bytes stored in a single limb
+pub const LIMB_BYTES: usize = 3;
+
+/// Returns the length of the element encoded by limbs if the given word is its header
+pub fn wide_element_length(header: u32) -> Option<usize> {
+    match header & (TAG_FLAG | NEGATIVE_FLAG) {
+        TAG_FLAG => Some((header & !TAG_FLAG) as usize),
+        _ => None,
+    }
+}
+
+/// Returns the magnitude of the negative number if the given word encodes it
+pub fn negative_magnitude(word: u32) -> Option<u32> {
+    match word & (TAG_FLAG | NEGATIVE_FLAG) {
+        flags if flags == TAG_FLAG | NEGATIVE_FLAG => Some(word & !flags),
+        _ => None,
+    }
+}
+
+/// Interprets the element as the script number, returning `None` if the element
+/// is longer than 4 bytes or is not minimally encoded
+pub fn read_script_number(element: &[u8]) -> Option<i64> {
+    let Some(&last) = element.last() else {
+        return Some(0);
+    };
+    if element.len() > 4 {
+        return None;
+    }
+
+    // Script numbers are little-endian, with the sign stored in the most significant bit
+    let sign_bit = 0x80 << (8 * (element.len() - 1));
+    let value = element
+        .iter()
+        .enumerate()
+        .fold(0i64, |acc, (i, &byte)| acc | ((byte as i64) << (8 * i)));
+    let number = match last & 0x80 {
+        0 => value,
+        _ => -(value & !sign_bit),
+    };
+
+    (scriptint_vec(number) == element).then_some(number)
+}
+
+/// Returns the widths (in bytes) of the limbs of the element with the given length
+pub fn limb_widths(length: usize) -> impl Iterator<Item = usize> {
+    (0..length)
+        .step_by(LIMB_BYTES)
+        .map(move |start| LIMB_BYTES.min(length - start))
+}
+
+/// Encodes a single stack element as a sequence of u32 words
+pub fn encode_element(element: &[u8]) -> Vec<u32> {
+    // Small numbers in the minimal encoding are committed as a single word
+    match read_script_number(element) {
+        Some(number) if (0..TAG_FLAG as i64).contains(&number) => return vec![number as u32],
+        Some(number) if (1..NEGATIVE_FLAG as i64).contains(&-number) => {
+            return vec![TAG_FLAG | NEGATIVE_FLAG | (-number) as u32]
+        }
+        _ => {}
+    }
+
+    std::iter::once(TAG_FLAG | element.len() as u32)
+        .chain(element.chunks(LIMB_BYTES).map(|limb| {
+            let mut padded = [0u8; 4];
+            padded[..limb.len()].copy_from_slice(limb);
+            u32::from_le_bytes(padded)
+        }))
+        .collect()
+}
+
+/// Encodes the given elements, concatenating their words
+pub fn encode_elements(elements: impl IntoIterator<Item = Vec<u8>>) -> Vec<u32> {
+    elements
+        .into_iter()
+        .flat_map(|element| encode_element(&element))
+        .collect()
+}
+
+/// Splits the sequence of words into the groups, each encoding a single element
+pub fn group_words(words: &[u32]) -> Vec<&[u32]> {
+    let mut groups = vec![];
+    let mut rest = words;
+
+    while let Some(&header) = rest.first() {
+        let words_number = match wide_element_length(header) {
+            Some(length) => 1 + limb_widths(length).count(),
+            None => 1,
+        };
+        assert!(
+            words_number <= rest.len(),
+            "encoded element is missing its limbs"
+        );
+
+        let (group, tail) = rest.split_at(words_number);
+        groups.push(group);
+        rest = tail;
+    }
+
+    groups
+}
+
+/// Decodes the element from the words produced by [`encode_element`]
+pub fn decode_element(words: &[u32]) -> Vec<u8> {
+    if let Some(magnitude) = negative_magnitude(words[0]) {
+        return scriptint_vec(-(magnitude as i64));
+    }
+    let Some(length) = wide_element_length(words[0]) else {
+        return scriptint_vec(words[0] as i64);
+    };
+
+    words[1..]
+        .iter()
+        .zip(limb_widths(length))
+        .flat_map(|(limb, width)| limb.to_le_bytes().into_iter().take(width))
+        .collect()
+}
+
+/// Decodes all elements from the concatenation of their words
+pub fn decode_elements(words: &[u32]) -> Vec<Vec<u8>> {
+    group_words(words).into_iter().map(decode_element).collect()
+}
+
+/// Script restoring the element from its words. The words are taken either from
+/// the altstack (the header being on top), or from the mainstack (the header being
+/// on top as well). In both cases, the restored element is left on the mainstack.
+///
+/// Returns an error if the element is wide and is not a script number, since restoring
+/// it would need `OP_CAT`.
+pub fn decode_element_script(words: &[u32], from_altstack: bool) -> Result<Script, EncodingError> {
+    let Some(length) = wide_element_length(words[0]) else {
+        // Single words are the numbers themselves, up to the sign
+        return Ok(script! {
+            if from_altstack {
+                OP_FROMALTSTACK
+            }
+            if negative_magnitude(words[0]).is_some() {
+                { TAG_FLAG | NEGATIVE_FLAG } OP_SUB OP_NEGATE
+            }
+        });
+    };
+    if read_script_number(&decode_element(words)).is_none() {
+        return Err(EncodingError::UnrestorableElement { length });
+    }
+
+    // Script numbers take at most 4 bytes, that is, at most two limbs
+    let widths: Vec<usize> = limb_widths(length).collect();
+    let sign_bit = 1u32 << (8 * widths[widths.len() - 1] - 1);
+
+    Ok(script! {
+        if from_altstack {
+            OP_FROMALTSTACK
+        }
+        { words[0] } OP_EQUALVERIFY
+
+        // Taking the limbs, so that the most significant one is on top
+        for i in 0..widths.len() {
+            if from_altstack {
+                OP_FROMALTSTACK
+            } else if i > 0 {
+                OP_SWAP
+            }
+        }
+
+        // The sign is stored in the most significant bit of the last byte
+        OP_DUP { sign_bit } OP_GREATERTHANOREQUAL
+        OP_DUP OP_TOALTSTACK
+        OP_IF
+            { sign_bit } OP_SUB
+        OP_ENDIF
+
+        // Shifting the most significant limb by the width of the lower one
+        if widths.len() > 1 {
+            for _ in 0..8 * widths[0] {
+                OP_DUP OP_ADD
+            }
+            OP_ADD
+        }
+
+        OP_FROMALTSTACK
+        OP_IF
+            OP_NEGATE
+        OP_ENDIF
+    })
+}
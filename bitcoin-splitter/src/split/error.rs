@@ -0,0 +1,179 @@
+//! Module containing the errors that can occur while splitting the script
+
+use std::io;
+
+use bitcoin::script;
+
+use super::{checkpoints::Checkpoint, conditional::ConditionError};
+
+/// Error of the script splitting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SplitError {
+    /// The script cannot be parsed into instructions
+    InvalidScript(script::Error),
+    /// The shard with the given index exceeds the maximum script size
+    ShardTooLarge { shard_id: usize, size: usize },
+    /// The shard with the given index has malformed conditional blocks at the given byte position
+    MalformedShard {
+        shard_id: usize,
+        position: usize,
+        error: ConditionError,
+    },
+    /// Split hints refer to the given boundary, which does not lie inside the script
+    /// or breaks the order of the hints
+    InvalidHints { boundary: usize },
+    /// The cut does not come after the previous one or does not lie inside the script
+    InvalidCut { cut: usize },
+    /// The shard with the given index fails on the given input
+    ExecutionFailed { shard_id: usize },
+    /// The split type needs to execute the script, but no input was given
+    InputRequired,
+    /// The splitter does not support the given split type, for instance, the streaming
+    /// splitter cannot use the split types that need the profile of the whole script
+    UnsupportedSplitType,
+    /// None of the candidates checked by the search gave a valid split, or the script
+    /// cannot be split into the shards below the maximum script size at all
+    NoValidSplit,
+    /// The search was cancelled by the observer before any valid split was found
+    Cancelled,
+}
+
+impl From<script::Error> for SplitError {
+    fn from(error: script::Error) -> Self {
+        SplitError::InvalidScript(error)
+    }
+}
+
+/// Error of the streaming split written to disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamError {
+    /// The script cannot be split
+    Split(SplitError),
+    /// The shard cannot be written
+    Io(io::ErrorKind),
+}
+
+impl From<SplitError> for StreamError {
+    fn from(error: SplitError) -> Self {
+        StreamError::Split(error)
+    }
+}
+
+impl From<io::Error> for StreamError {
+    fn from(error: io::Error) -> Self {
+        StreamError::Io(error.kind())
+    }
+}
+
+/// Error of the split verification, which names the first place where the split is not sound
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SplitVerificationError {
+    /// The number of shards differs from the number of intermediate states
+    LengthMismatch { shards: usize, states: usize },
+    /// Concatenated shards differ from the original script starting from the given byte
+    ScriptMismatch { position: usize },
+    /// The state after the shard with the given index differs from the result of its execution
+    StateMismatch { shard_id: usize },
+    /// The last state differs from the expected output
+    OutputMismatch,
+    /// Hints of the shards differ from the hints of the script
+    HintMismatch,
+}
+
+/// Error of the composition of two programs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompositionError {
+    /// The output size of the first program differs from the input size of the second one
+    SizeMismatch {
+        output_size: usize,
+        input_size: usize,
+    },
+}
+
+/// Error of the split at the checkpoints pinned by the user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckpointError {
+    /// The script cannot be split at the resolved checkpoints
+    Split(SplitError),
+    /// The checkpoint lies at the start of the script, at its end or beyond it
+    OutOfRange(Checkpoint),
+    /// The checkpoint does not come after the previous one
+    NotIncreasing(Checkpoint),
+    /// The byte offset of the checkpoint is not the start of any instruction
+    NotInstructionBoundary(Checkpoint),
+    /// The checkpoint lies inside the conditional block or the atomic region
+    IllegalCut(Checkpoint),
+    /// The script has no legal cut to move the checkpoint to
+    NoLegalCut(Checkpoint),
+}
+
+impl From<SplitError> for CheckpointError {
+    fn from(error: SplitError) -> Self {
+        CheckpointError::Split(error)
+    }
+}
+
+/// Resource limit of the disprove script, see [`super::constraints::SplitConstraints`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Constraint {
+    LeafSize,
+    StackItems,
+    WitnessSize,
+    TxWeight,
+}
+
+/// Error of the split under the resource limits of the disprove scripts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConstraintError {
+    /// The script cannot be split
+    Split(SplitError),
+    /// No fitting shard covers the region between the given instruction boundaries:
+    /// the shortest shard spanning it needs `required` of the constraint, which exceeds `limit`
+    Blocked {
+        start: usize,
+        end: usize,
+        constraint: Constraint,
+        required: usize,
+        limit: usize,
+    },
+    /// Some state of the split cannot be restored by the disprove script
+    Encoding(EncodingError),
+}
+
+impl From<SplitError> for ConstraintError {
+    fn from(error: SplitError) -> Self {
+        ConstraintError::Split(error)
+    }
+}
+
+impl From<EncodingError> for ConstraintError {
+    fn from(error: EncodingError) -> Self {
+        ConstraintError::Encoding(error)
+    }
+}
+
+/// Error of the split of the script consuming hints, see [`super::hinted`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HintError {
+    /// The script cannot be split
+    Split(SplitError),
+    /// The number of the hint elements differs from the number the script consumes
+    CountMismatch { provided: usize, consumed: usize },
+    /// The hint taken starting from the given instruction is not checked in the same shard,
+    /// since the instructions taking it are not followed by the rest of the atomic region
+    UnguardedConsumption { instruction: usize },
+}
+
+impl From<SplitError> for HintError {
+    fn from(error: SplitError) -> Self {
+        HintError::Split(error)
+    }
+}
+
+/// Error of restoring the stack element in the script, see [`super::encoding`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodingError {
+    /// The wide element of the given length is not a script number, so restoring
+    /// it from the limbs needs `OP_CAT`, which Tapscript does not have
+    UnrestorableElement { length: usize },
+}
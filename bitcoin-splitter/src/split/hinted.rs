@@ -0,0 +1,334 @@
+//! Module containing the split of the scripts consuming hints.
+//!
+//! Hinted gadgets (for instance, the hinted field arithmetic of BN254) do not compute
+//! the expensive values themselves, but take them from the witness and only check them.
+//! The hints (not to be confused with the split hints of [`super::hints`]) are pushed
+//! below the input, and the script takes them one element at a time from the bottom of
+//! the stack with `OP_DEPTH OP_1SUB OP_ROLL`, in the order they were pushed.
+//!
+//! When such a script is split, the hints must not be a part of the intermediate states,
+//! since they are not computed by the previous shards. Instead, each shard gets its own
+//! hints, namely the elements it takes from the bottom of the stack, which are pushed
+//! below the state before it.
+//!
+//! Unlike the states, the hints are not signed, so in the disprove script they are chosen
+//! by whoever spends it. Thus, the shard taking the hint must also check it: otherwise the
+//! unchecked hint becomes a part of the signed state after the shard, and the wrong hint
+//! makes the honest state look incorrect. For this reason, each gadget consuming the hints
+//! must be wrapped into the atomic region (see [`super::hints::atomic`]) together with the
+//! instructions checking them, and [`hinted_split`] rejects the scripts taking the hints
+//! outside of such regions.
+
+use bitcoin::{
+    opcodes::{
+        all::{OP_1SUB, OP_DEPTH, OP_ROLL},
+        Opcode,
+    },
+    script::Instruction,
+};
+use bitcoin_utils::treepp::*;
+
+use super::{
+    core::{try_split_into_shards_with_input, SplitType, DEFAULT_SCRIPT_SIZE},
+    error::{HintError, SplitVerificationError},
+    hints::AnnotatedScript,
+    intermediate_state::IntermediateState,
+    script::{verify_output, SplitResult},
+};
+
+/// Instructions taking the next hint element from the bottom of the stack
+const HINT_CONSUMPTION: [Opcode; 3] = [OP_DEPTH, OP_1SUB, OP_ROLL];
+
+/// Script together with the hints it consumes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HintedScript {
+    /// Main logic (f) of the script together with the atomic regions of its gadgets
+    pub script: AnnotatedScript,
+    /// Scripts pushing each hint, in the order the script consumes them
+    pub hints: Vec<Script>,
+}
+
+impl HintedScript {
+    /// Creates the hinted script
+    pub fn new(script: impl Into<AnnotatedScript>, hints: Vec<Script>) -> Self {
+        Self {
+            script: script.into(),
+            hints,
+        }
+    }
+
+    /// Returns the script pushing all hints
+    pub fn hints_script(&self) -> Script {
+        script! {
+            for hint in self.hints.iter() {
+                { hint.clone() }
+            }
+        }
+    }
+
+    /// Returns the script pushing the hints below the given input
+    pub fn input_with_hints(&self, input: &Script) -> Script {
+        script! {
+            { self.hints_script() }
+            { input.clone() }
+        }
+    }
+
+    /// Returns the number of hint elements the script consumes
+    pub fn consumed_elements(&self) -> usize {
+        hint_consumptions(self.script.script())
+    }
+}
+
+/// Pair of input and output scripts together with the hints the script consumes
+/// on this input. Same as [`super::script::IOPair`], but for the [`HintedSplitableScript`].
+pub struct HintedIOPair<const INPUT_SIZE: usize, const OUTPUT_SIZE: usize> {
+    /// Input script containing the elements which will be fed to the main script
+    pub input: Script,
+    /// Output script containing the elements which will be compared to the output of the main script
+    pub output: Script,
+    /// Scripts pushing each hint, in the order the script consumes them
+    pub hints: Vec<Script>,
+}
+
+/// Result of the split of the hinted script
+pub struct HintedSplitResult {
+    /// Shards and the intermediate states, which do not contain the hints
+    pub split_result: SplitResult,
+    /// Script pushing the hints consumed by each shard, empty if the shard consumes none
+    pub shard_hints: Vec<Script>,
+}
+
+impl HintedSplitResult {
+    /// Returns the number of shards
+    pub fn len(&self) -> usize {
+        self.split_result.len()
+    }
+
+    /// Returns whether the split has no shards
+    pub fn is_empty(&self) -> bool {
+        self.split_result.is_empty()
+    }
+
+    /// Returns the input of the shard with the given index: its hints pushed
+    /// below the state before it
+    pub fn shard_input(&self, input: &Script, shard_id: usize) -> Script {
+        let state = match shard_id {
+            0 => input.clone(),
+            _ => self.split_result.intermediate_states[shard_id - 1].inject_script(),
+        };
+
+        script! {
+            { self.shard_hints[shard_id].clone() }
+            { state }
+        }
+    }
+
+    /// Verifies that the split of the hinted script executed with the `input` is sound,
+    /// similarly to [`SplitResult::verify`]. Additionally, the hints of the shards must
+    /// be exactly the hints of the script, and each shard is executed with its own hints.
+    pub fn verify(
+        &self,
+        input: &Script,
+        hinted_script: &HintedScript,
+        expected_output: &Script,
+    ) -> Result<(), SplitVerificationError> {
+        let split_result = &self.split_result;
+        if self.shard_hints.len() != split_result.len() {
+            return Err(SplitVerificationError::LengthMismatch {
+                shards: self.shard_hints.len(),
+                states: split_result.len(),
+            });
+        }
+
+        // The shards must consume all hints in the original order
+        let all_hints = script! {
+            for hints in self.shard_hints.iter() {
+                { hints.clone() }
+            }
+        };
+        if IntermediateState::from_inject_script(&all_hints)
+            != IntermediateState::from_inject_script(&hinted_script.hints_script())
+        {
+            return Err(SplitVerificationError::HintMismatch);
+        }
+
+        split_result.verify_with_inputs(
+            hinted_script.script.script(),
+            expected_output,
+            |shard_id| self.shard_input(input, shard_id),
+        )
+    }
+}
+
+/// Trait that any script consuming hints that can be split should implement.
+/// Same as [`super::script::SplitableScript`], but the hints depend on the input,
+/// so they are generated together with it.
+pub trait HintedSplitableScript<const INPUT_SIZE: usize, const OUTPUT_SIZE: usize> {
+    const INPUT_SIZE: usize = INPUT_SIZE;
+    const OUTPUT_SIZE: usize = OUTPUT_SIZE;
+
+    /// Returns the main logic (f) of the script
+    fn script() -> Script;
+
+    /// Returns the script together with its split hints. Each gadget consuming
+    /// the hints must lie inside the atomic region, see [`hinted_split`].
+    fn annotated_script() -> AnnotatedScript {
+        Self::script().into()
+    }
+
+    /// Generates a random valid input for the script together with its hints
+    fn generate_valid_io_pair() -> HintedIOPair<INPUT_SIZE, OUTPUT_SIZE>;
+
+    /// Generates invalid input for the script together with its hints
+    fn generate_invalid_io_pair() -> HintedIOPair<INPUT_SIZE, OUTPUT_SIZE>;
+
+    /// Returns the script together with the hints of the given input
+    fn hinted_script(io_pair: &HintedIOPair<INPUT_SIZE, OUTPUT_SIZE>) -> HintedScript {
+        HintedScript::new(Self::annotated_script(), io_pair.hints.clone())
+    }
+
+    /// Verifies that the input is valid for the script
+    fn verify(io_pair: &HintedIOPair<INPUT_SIZE, OUTPUT_SIZE>) -> bool {
+        let hinted_script = Self::hinted_script(io_pair);
+        verify_output(
+            hinted_script.input_with_hints(&io_pair.input),
+            hinted_script.script.into_script(),
+            io_pair.output.clone(),
+            OUTPUT_SIZE,
+        )
+    }
+
+    /// Verifies that the input is valid for the script with random input and output
+    fn verify_random() -> bool {
+        Self::verify(&Self::generate_valid_io_pair())
+    }
+
+    /// Splits the script into smaller parts
+    fn default_split(
+        io_pair: &HintedIOPair<INPUT_SIZE, OUTPUT_SIZE>,
+        split_type: SplitType,
+    ) -> HintedSplitResult {
+        Self::split(io_pair, split_type, DEFAULT_SCRIPT_SIZE)
+    }
+
+    /// Splits the script into smaller parts with the specified chunk size
+    fn split(
+        io_pair: &HintedIOPair<INPUT_SIZE, OUTPUT_SIZE>,
+        split_type: SplitType,
+        chunk_size: usize,
+    ) -> HintedSplitResult {
+        hinted_split(
+            io_pair.input.clone(),
+            &Self::hinted_script(io_pair),
+            split_type,
+            chunk_size,
+        )
+        .unwrap_or_else(|err| panic!("failed to split the script: {:?}", err))
+    }
+}
+
+/// Naive split of the hinted script, routing each hint element to the shard taking it:
+/// 1. We check that each hint is taken inside the atomic region, so the gadget consuming
+///    it is never cut and the hint never gets into the intermediate state unchecked
+/// 2. We split the script into shards and count the hint elements each shard takes
+/// 3. We execute each shard with its hints pushed below the previous state
+pub fn hinted_split(
+    input: Script,
+    hinted_script: &HintedScript,
+    split_type: SplitType,
+    chunk_size: usize,
+) -> Result<HintedSplitResult, HintError> {
+    let hint_elements: Vec<Vec<u8>> =
+        IntermediateState::from_inject_script(&hinted_script.hints_script())
+            .stack
+            .iter_str()
+            .collect();
+
+    let consumed = hinted_script.consumed_elements();
+    if consumed != hint_elements.len() {
+        return Err(HintError::CountMismatch {
+            provided: hint_elements.len(),
+            consumed,
+        });
+    }
+
+    // The region must continue after the instructions taking the hint,
+    // since the hint has to be checked in the same shard
+    let hints = hinted_script.script.checked_hints()?;
+    if let Some(start) = consumption_starts(hinted_script.script.script())
+        .into_iter()
+        .find(|start| {
+            (start + 1..=start + HINT_CONSUMPTION.len()).any(|boundary| !hints.is_atomic(boundary))
+        })
+    {
+        return Err(HintError::UnguardedConsumption { instruction: start });
+    }
+
+    // Profiling for the stack-size split needs the hints to be in place
+    let shards = try_split_into_shards_with_input(
+        &hinted_script.input_with_hints(&input),
+        &hinted_script.script,
+        chunk_size,
+        split_type,
+    )?;
+
+    let mut remaining_elements = hint_elements.into_iter();
+    let shard_hints: Vec<Script> = shards
+        .iter()
+        .map(|shard| {
+            let elements = remaining_elements.by_ref().take(hint_consumptions(shard));
+            script! {
+                for element in elements {
+                    { element }
+                }
+            }
+        })
+        .collect();
+
+    let mut intermediate_states: Vec<IntermediateState> = Vec::with_capacity(shards.len());
+    for (shard, hints) in shards.iter().zip(shard_hints.iter()) {
+        let state = match intermediate_states.last() {
+            Some(state) => state.inject_script(),
+            None => input.clone(),
+        };
+        let shard_input = script! {
+            { hints.clone() }
+            { state }
+        };
+        intermediate_states.push(IntermediateState::from_input_script(&shard_input, shard));
+    }
+
+    Ok(HintedSplitResult {
+        split_result: SplitResult::new(shards, intermediate_states),
+        shard_hints,
+    })
+}
+
+/// Returns the indices of the instructions at which the script starts taking the hint element
+fn consumption_starts(script: &Script) -> Vec<usize> {
+    let opcodes: Vec<Option<Opcode>> = script
+        .instructions()
+        .map(|instruction| match instruction {
+            Ok(Instruction::Op(op)) => Some(op),
+            _ => None,
+        })
+        .collect();
+
+    opcodes
+        .windows(HINT_CONSUMPTION.len())
+        .enumerate()
+        .filter(|(_, window)| {
+            window
+                .iter()
+                .zip(HINT_CONSUMPTION.iter())
+                .all(|(op, expected)| *op == Some(*expected))
+        })
+        .map(|(start, _)| start)
+        .collect()
+}
+
+/// Returns the number of times the script takes the hint element
+fn hint_consumptions(script: &Script) -> usize {
+    consumption_starts(script).len()
+}
@@ -0,0 +1,207 @@
+//! Module containing the split hints, which allow the author of the script to mark
+//! the preferred cut points and the regions that must never be cut.
+//!
+//! Hints are carried next to the script in the [`AnnotatedScript`] rather than inside it,
+//! so the script itself is never changed: every opcode keeps its meaning, and the shards
+//! consist of the instructions of the script only. Annotated scripts are composed
+//! with [`AnnotatedScript::push`], similarly to the blocks of `script!`:
+//! - [`atomic`] wraps the script into the atomic region, inside which the script cannot be cut.
+//!   Regions can be nested.
+//! - [`split_here`] marks the preferred cut point. Once the script has at least one such
+//!   point, the splitter cuts only at the marked points.
+
+use std::ops::Range;
+
+use bitcoin_utils::treepp::*;
+
+use super::{error::SplitError, profile::ScriptProfile};
+
+/// Returns the empty script marking the preferred cut point
+pub fn split_here() -> AnnotatedScript {
+    AnnotatedScript::new(
+        Script::new(),
+        SplitHints {
+            preferred: vec![0],
+            atomic: vec![],
+        },
+    )
+}
+
+/// Wraps the given script into the atomic region, so it is never cut in the middle
+pub fn atomic(script: impl Into<AnnotatedScript>) -> AnnotatedScript {
+    let mut script = script.into();
+    script.hints.atomic.push(0..script.instructions_number);
+    script
+}
+
+/// Hints of the script. Similarly to [`ScriptProfile`], boundary `k`
+/// is the point right before the `k`-th instruction of the script.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SplitHints {
+    /// Boundaries marked as the preferred cut points in the increasing order
+    pub preferred: Vec<usize>,
+    /// Atomic regions given by the boundaries at which they start and end.
+    /// The script cannot be cut at the boundaries strictly inside a region.
+    pub atomic: Vec<Range<usize>>,
+}
+
+impl SplitHints {
+    /// Returns whether the script has any hints
+    pub fn is_empty(&self) -> bool {
+        self.preferred.is_empty() && self.atomic.is_empty()
+    }
+
+    /// Returns whether the boundary lies strictly inside some atomic region
+    pub fn is_atomic(&self, boundary: usize) -> bool {
+        self.atomic
+            .iter()
+            .any(|region| region.start < boundary && boundary < region.end)
+    }
+
+    /// Returns whether the script can be cut at the given boundary
+    pub fn allows_cut(&self, boundary: usize) -> bool {
+        if self.is_atomic(boundary) {
+            return false;
+        }
+
+        self.preferred.is_empty() || self.preferred.binary_search(&boundary).is_ok()
+    }
+
+    /// Marks the boundaries at which the cut is not allowed as illegal in the profile
+    /// of the script. The start and the end of the script are left intact.
+    pub fn restrict(&self, profile: &mut ScriptProfile) {
+        for boundary in 1..profile.len() {
+            profile.legal[boundary] = profile.legal[boundary] && self.allows_cut(boundary);
+        }
+    }
+}
+
+/// Script together with its split hints
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AnnotatedScript {
+    /// Script itself, which never contains the hints
+    script: Script,
+    /// Hints referring to the instruction boundaries of the script
+    hints: SplitHints,
+    /// Number of instructions in the script, kept so that the composition takes linear time
+    instructions_number: usize,
+}
+
+impl From<Script> for AnnotatedScript {
+    fn from(script: Script) -> Self {
+        Self::new(script, SplitHints::default())
+    }
+}
+
+impl From<&Script> for AnnotatedScript {
+    fn from(script: &Script) -> Self {
+        script.clone().into()
+    }
+}
+
+impl From<&AnnotatedScript> for AnnotatedScript {
+    fn from(script: &AnnotatedScript) -> Self {
+        script.clone()
+    }
+}
+
+impl AnnotatedScript {
+    /// Creates the script with the given hints
+    pub fn new(script: Script, hints: SplitHints) -> Self {
+        Self {
+            instructions_number: script.instructions().count(),
+            script,
+            hints,
+        }
+    }
+
+    /// Returns the script itself
+    pub fn script(&self) -> &Script {
+        &self.script
+    }
+
+    /// Returns the hints of the script as they were given, see [`AnnotatedScript::checked_hints`]
+    pub fn hints(&self) -> &SplitHints {
+        &self.hints
+    }
+
+    /// Returns the script, dropping the hints
+    pub fn into_script(self) -> Script {
+        self.script
+    }
+
+    /// Returns the number of instructions in the script
+    pub fn instructions_number(&self) -> usize {
+        self.instructions_number
+    }
+
+    /// Appends the given script together with its hints
+    pub fn push(&mut self, other: impl Into<AnnotatedScript>) -> &mut Self {
+        let other = other.into();
+        let shift = self.instructions_number;
+
+        let mut bytes = std::mem::take(&mut self.script).into_bytes();
+        bytes.extend_from_slice(other.script.as_bytes());
+        self.script = Script::from_bytes(bytes);
+        self.instructions_number += other.instructions_number;
+
+        for boundary in other.hints.preferred {
+            if self.hints.preferred.last() != Some(&(boundary + shift)) {
+                self.hints.preferred.push(boundary + shift);
+            }
+        }
+        self.hints.atomic.extend(
+            other
+                .hints
+                .atomic
+                .into_iter()
+                .map(|region| region.start + shift..region.end + shift),
+        );
+
+        self
+    }
+
+    /// Checks that the script is not corrupted and the hints lie inside it, returning
+    /// the hints the splitter works with. The preferred cut points at the start or at the
+    /// end of the script are dropped, since the cuts there give empty shards.
+    pub fn checked_hints(&self) -> Result<SplitHints, SplitError> {
+        for instruction in self.script.instructions() {
+            instruction?;
+        }
+
+        let out_of_range = self
+            .hints
+            .preferred
+            .iter()
+            .copied()
+            .chain(self.hints.atomic.iter().map(|region| region.end))
+            .find(|&boundary| boundary > self.instructions_number);
+        if let Some(boundary) = out_of_range {
+            return Err(SplitError::InvalidHints { boundary });
+        }
+        if let Some(region) = self
+            .hints
+            .atomic
+            .iter()
+            .find(|region| region.start > region.end)
+        {
+            return Err(SplitError::InvalidHints {
+                boundary: region.start,
+            });
+        }
+        if let Some(pair) = self
+            .hints
+            .preferred
+            .windows(2)
+            .find(|pair| pair[0] >= pair[1])
+        {
+            return Err(SplitError::InvalidHints { boundary: pair[1] });
+        }
+
+        let mut hints = self.hints.clone();
+        hints
+            .preferred
+            .retain(|&boundary| boundary != 0 && boundary != self.instructions_number);
+        Ok(hints)
+    }
+}
@@ -6,6 +6,8 @@ use core::fmt;
 use bitcoin_scriptexec::Stack;
 use bitcoin_utils::{stack_to_script, treepp::*};
 
+use super::encoding::{decode_elements, encode_elements};
+
 /// Structure that represents the intermediate state.
 /// It contains the stack and altstack after the execution of the
 /// corresponding shard.
@@ -16,7 +18,8 @@ pub struct IntermediateState {
 }
 
 /// Structure that represents the [`IntermediateState`] where stack and altstack are
-/// serialized to bytes.
+/// serialized to bytes. Each element is encoded as one or more u32 words
+/// (see [`super::encoding`]), stored in the little-endian order.
 pub struct IntermediateStateAsBytes {
     pub stack: Vec<u8>,
     pub altstack: Vec<u8>,
@@ -29,6 +32,17 @@ impl fmt::Debug for IntermediateState {
     }
 }
 
+impl PartialEq for IntermediateState {
+    /// States are compared by the byte representation of their elements, so
+    /// the same element is equal regardless of whether it was pushed or computed.
+    fn eq(&self, other: &Self) -> bool {
+        self.stack.iter_str().eq(other.stack.iter_str())
+            && self.altstack.iter_str().eq(other.altstack.iter_str())
+    }
+}
+
+impl Eq for IntermediateState {}
+
 impl IntermediateState {
     /// Returns the script that pushes all elements (stack and altstack) to the
     /// corresponding stacks
@@ -88,8 +102,8 @@ impl IntermediateState {
     /// Converts the stack to bytes
     pub fn to_bytes(&self) -> IntermediateStateAsBytes {
         IntermediateStateAsBytes {
-            stack: self.stack.clone().serialize_to_bytes(),
-            altstack: self.altstack.clone().serialize_to_bytes(),
+            stack: stack_to_bytes(&self.stack),
+            altstack: stack_to_bytes(&self.altstack),
         }
     }
 
@@ -100,7 +114,7 @@ impl IntermediateState {
 
     /// Converts the stack to a vector of u32 values
     pub fn interpret_as_u32_array(&self) -> Vec<u8> {
-        self.stack.clone().serialize_to_bytes()
+        stack_to_bytes(&self.stack)
     }
 }
 
@@ -115,19 +129,22 @@ impl IntermediateStateAsBytes {
         bytes_to_u32_array(&self.altstack)
     }
 
-    /// Injects the stack and altstack into the script
+    /// Injects the stack and altstack into the script, restoring
+    /// the original elements from their words
     pub fn inject_script(&self) -> Script {
+        let altstack = decode_elements(&self.altstack_as_u32());
+
         script! {
             // Inject the stack
-            for stack_element in self.stack_as_u32() {
+            for stack_element in decode_elements(&self.stack_as_u32()) {
                 { stack_element }
             }
 
             // Inject the altstack
-            for altstack_element in self.altstack_as_u32() {
-                { altstack_element }
+            for altstack_element in altstack.iter() {
+                { altstack_element.clone() }
             }
-            for i in (0..self.altstack_as_u32().len()).rev() {
+            for i in (0..altstack.len()).rev() {
                 { i } OP_ROLL
                 OP_TOALTSTACK
             }
@@ -135,24 +152,26 @@ impl IntermediateStateAsBytes {
     }
 }
 
-/// Converts a slice of bytes to a vector of u32 values.
-pub(super) fn bytes_to_u32_array(bytes: &[u8]) -> Vec<u32> {
-    let mut u32_array = Vec::with_capacity((bytes.len() + 3) / 4); // Ceiling division to account for partial chunks.
-
-    for chunk in bytes.chunks(4) {
-        // Handle chunks with fewer than 4 bytes
-        let padded_chunk = match chunk.len() {
-            4 => [chunk[0], chunk[1], chunk[2], chunk[3]],
-            3 => [chunk[0], chunk[1], chunk[2], 0],
-            2 => [chunk[0], chunk[1], 0, 0],
-            1 => [chunk[0], 0, 0, 0],
-            _ => unreachable!(),
-        };
-
-        // Convert the padded chunk to a u32 value
-        let value = u32::from_le_bytes(padded_chunk);
-        u32_array.push(value);
-    }
+/// Encodes the elements of the stack and serializes the resultant words to bytes
+fn stack_to_bytes(stack: &Stack) -> Vec<u8> {
+    encode_elements(stack.iter_str())
+        .into_iter()
+        .flat_map(u32::to_le_bytes)
+        .collect()
+}
 
-    u32_array
+/// Converts a slice of bytes to a vector of u32 values. The bytes are expected
+/// to be produced by [`IntermediateState::to_bytes`], that is, to consist of the
+/// little-endian words, so no bytes are padded (which would change the sign or the
+/// value of the script number stored in them).
+pub(super) fn bytes_to_u32_array(bytes: &[u8]) -> Vec<u32> {
+    assert!(
+        bytes.len() % 4 == 0,
+        "serialized state must consist of whole u32 words"
+    );
+
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
 }
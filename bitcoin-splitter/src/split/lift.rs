@@ -0,0 +1,196 @@
+//! Module containing the transformation that lifts the branch selector of the long
+//! conditional blocks into the intermediate state, so that the splitter can cut them.
+//!
+//! The top-level block `OP_IF A OP_ELSE B OP_ENDIF`, whose branches are cut into
+//! segments `A = A[1] A[2] ... A[m]` and `B = B[1] B[2] ... B[m]` (the shorter
+//! branch is padded with the empty segments), is rewritten as:
+//!
+//! ```text
+//! OP_TOALTSTACK
+//! OP_FROMALTSTACK OP_DUP OP_TOALTSTACK OP_IF A[1] OP_ELSE B[1] OP_ENDIF
+//! ...
+//! OP_FROMALTSTACK OP_DUP OP_TOALTSTACK OP_IF A[m] OP_ELSE B[m] OP_ENDIF
+//! OP_FROMALTSTACK OP_DROP
+//! ```
+//!
+//! The selector stays on top of the altstack between the segments, so it is committed
+//! in the intermediate state together with the rest of the stack, while each segment
+//! re-checks it. Since the branches are cut only where they have not touched the
+//! altstack below its initial size, the result of the script does not change.
+
+use bitcoin::{
+    opcodes::{
+        all::{
+            OP_DROP, OP_DUP, OP_ELSE, OP_ENDIF, OP_FROMALTSTACK, OP_IF, OP_NOTIF, OP_TOALTSTACK,
+        },
+        Opcode,
+    },
+    script::Instruction,
+};
+use bitcoin_utils::treepp::*;
+
+use super::{conditional::validate_shard, error::SplitError};
+
+/// Rewrites every top-level conditional block longer than `segment_size` bytes so that
+/// its branches are cut into segments of about `segment_size` bytes each, which can
+/// then be placed into different shards.
+///
+/// The block is left intact if it cannot be lifted safely: if it has more than
+/// one `OP_ELSE`, if some branch pops the altstack below its initial size or if
+/// the altstack is used inside the nested conditional blocks of the branch.
+pub fn lift_conditionals(script: &Script, segment_size: usize) -> Result<Script, SplitError> {
+    let instructions = script
+        .instructions()
+        .collect::<Result<Vec<Instruction>, _>>()?;
+    validate_shard(script).map_err(|(position, error)| SplitError::MalformedShard {
+        shard_id: 0,
+        position,
+        error,
+    })?;
+
+    let mut lifted_script = Script::new();
+    let mut instruction_id = 0;
+
+    while instruction_id < instructions.len() {
+        let instruction = instructions[instruction_id];
+        let Instruction::Op(op @ (OP_IF | OP_NOTIF)) = instruction else {
+            lifted_script.push_instruction(instruction);
+            instruction_id += 1;
+            continue;
+        };
+
+        // Since the script is well-formed, the block must be closed
+        let endif_id = matching_endif(&instructions, instruction_id);
+        let block = &instructions[instruction_id..=endif_id];
+
+        match lift_block(op, &block[1..block.len() - 1], segment_size) {
+            Some(lifted_block) => {
+                lifted_script = Script::from_bytes(
+                    [lifted_script.as_bytes(), lifted_block.as_bytes()].concat(),
+                );
+            }
+            None => {
+                for instruction in block {
+                    lifted_script.push_instruction(*instruction);
+                }
+            }
+        }
+
+        instruction_id = endif_id + 1;
+    }
+
+    Ok(lifted_script)
+}
+
+/// Returns the index of `OP_ENDIF` closing the block opened at `if_id`
+fn matching_endif(instructions: &[Instruction], if_id: usize) -> usize {
+    let mut depth = 0usize;
+
+    for (instruction_id, instruction) in instructions.iter().enumerate().skip(if_id) {
+        match instruction {
+            Instruction::Op(OP_IF | OP_NOTIF) => depth += 1,
+            Instruction::Op(OP_ENDIF) => {
+                depth -= 1;
+                if depth == 0 {
+                    return instruction_id;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    unreachable!("script is well-formed, so each block is closed")
+}
+
+/// Lifts the block with the given opening opcode and body (without the opening
+/// and closing opcodes). Returns `None` if the block should be left intact.
+fn lift_block(op: Opcode, body: &[Instruction], segment_size: usize) -> Option<Script> {
+    if instructions_size(body) <= segment_size {
+        return None;
+    }
+
+    // Looking for the OP_ELSE of the block itself
+    let mut depth = 0usize;
+    let mut else_ids = vec![];
+    for (instruction_id, instruction) in body.iter().enumerate() {
+        match instruction {
+            Instruction::Op(OP_IF | OP_NOTIF) => depth += 1,
+            Instruction::Op(OP_ENDIF) => depth -= 1,
+            Instruction::Op(OP_ELSE) if depth == 0 => else_ids.push(instruction_id),
+            _ => {}
+        }
+    }
+
+    let (then_branch, else_branch) = match else_ids[..] {
+        [] => (body, &body[body.len()..]),
+        [else_id] => (&body[..else_id], &body[else_id + 1..]),
+        _ => return None,
+    };
+
+    let mut then_segments = branch_segments(then_branch, segment_size)?;
+    let mut else_segments = branch_segments(else_branch, segment_size)?;
+    let segments_number = then_segments.len().max(else_segments.len());
+    then_segments.resize(segments_number, Script::new());
+    else_segments.resize(segments_number, Script::new());
+
+    let mut bytes = vec![OP_TOALTSTACK.to_u8()];
+    for (then_segment, else_segment) in then_segments.iter().zip(else_segments.iter()) {
+        bytes.extend([
+            OP_FROMALTSTACK.to_u8(),
+            OP_DUP.to_u8(),
+            OP_TOALTSTACK.to_u8(),
+            op.to_u8(),
+        ]);
+        bytes.extend_from_slice(then_segment.as_bytes());
+        bytes.push(OP_ELSE.to_u8());
+        bytes.extend_from_slice(else_segment.as_bytes());
+        bytes.push(OP_ENDIF.to_u8());
+    }
+    bytes.extend([OP_FROMALTSTACK.to_u8(), OP_DROP.to_u8()]);
+
+    Some(Script::from_bytes(bytes))
+}
+
+/// Cuts the branch into segments of about `segment_size` bytes. The segment can be closed
+/// only outside of the nested blocks and where the altstack has its initial size, so that
+/// the selector is on top of it. Returns `None` if the branch cannot be lifted.
+fn branch_segments(branch: &[Instruction], segment_size: usize) -> Option<Vec<Script>> {
+    let mut segments = vec![Script::new()];
+    let mut depth = 0usize;
+    let mut altstack_delta = 0usize;
+
+    for instruction in branch {
+        match instruction {
+            Instruction::Op(OP_IF | OP_NOTIF) => depth += 1,
+            Instruction::Op(OP_ENDIF) => depth -= 1,
+            // Nested blocks may touch the altstack differently in their branches
+            Instruction::Op(OP_TOALTSTACK | OP_FROMALTSTACK) if depth > 0 => return None,
+            Instruction::Op(OP_TOALTSTACK) => altstack_delta += 1,
+            // Popping the element below would take the selector
+            Instruction::Op(OP_FROMALTSTACK) => altstack_delta = altstack_delta.checked_sub(1)?,
+            _ => {}
+        }
+
+        let segment = segments.last_mut().expect("segments should not be empty");
+        segment.push_instruction(*instruction);
+
+        if segment.len() >= segment_size && depth == 0 && altstack_delta == 0 {
+            segments.push(Script::new());
+        }
+    }
+
+    if segments.len() > 1 && segments.last().is_some_and(|segment| segment.is_empty()) {
+        segments.pop();
+    }
+
+    Some(segments)
+}
+
+/// Returns the size in bytes of the given instructions
+fn instructions_size(instructions: &[Instruction]) -> usize {
+    let mut script = Script::new();
+    for instruction in instructions {
+        script.push_instruction(*instruction);
+    }
+    script.len()
+}
@@ -0,0 +1,129 @@
+//! Module containing the metrics used to compare different splits of the same script.
+//!
+//! As described in the "Fuzzy Search" section of the paper, the cost of disproving
+//! the transition `z[j+1] = f[j](z[j])` is approximately `alpha*(|z[j]| + |z[j+1]|) + |f[j]|`,
+//! where `alpha` is the factor of how much a single state element contributes to the
+//! disprove script. The metric then decides how to fold such costs over all shards.
+
+use super::{core::STACK_SIZE_INDEX, script::SplitResult};
+
+/// Way the metric folds the costs of the shards into the cost of the whole split
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    /// The cost of the split is the largest cost of its shards
+    Max,
+    /// The cost of the split is the average cost of its shards (rounded down)
+    Average,
+}
+
+/// Metric according to which the search-based splitters compare the splits.
+/// The lower the value, the better the split.
+///
+/// Metrics are shared between the worker threads of the fuzzy search, hence the `Sync` bound.
+pub trait SplitMetric: Sync {
+    /// Returns the weight of a single state element (`alpha` in the paper)
+    fn alpha(&self) -> usize;
+
+    /// Returns the approximate cost of the disprove script for the shard of the given
+    /// size, together with the sizes of the states before and after the shard.
+    fn shard_cost(&self, shard_size: usize, from_state_size: usize, to_state_size: usize) -> usize {
+        shard_size + self.alpha() * (from_state_size + to_state_size)
+    }
+
+    /// Returns how the costs of the shards are folded. The optimal splitter
+    /// runs the exact search for the given way (see [`super::optimal::optimal_split`]).
+    fn aggregation(&self) -> Aggregation;
+
+    /// Folds the costs of all shards into the cost of the whole split
+    fn aggregate(&self, shard_costs: &[usize]) -> usize {
+        if shard_costs.is_empty() {
+            return 0;
+        }
+
+        match self.aggregation() {
+            Aggregation::Max => shard_costs.iter().copied().max().unwrap_or(0),
+            Aggregation::Average => shard_costs.iter().sum::<usize>() / shard_costs.len(),
+        }
+    }
+
+    /// Evaluates the given split
+    fn evaluate(&self, split_result: &SplitResult) -> usize {
+        let shard_costs: Vec<usize> = (0..split_result.len())
+            .map(|i| {
+                // Similarly to the paper, the state before the first shard is not counted
+                let from_state_size = match i {
+                    0 => 0,
+                    _ => split_result.intermediate_states[i - 1].size(),
+                };
+
+                self.shard_cost(
+                    split_result.shards[i].len(),
+                    from_state_size,
+                    split_result.intermediate_states[i].size(),
+                )
+            })
+            .collect();
+
+        self.aggregate(&shard_costs)
+    }
+}
+
+/// Metric that bounds the worst case: the cost of the split is the
+/// maximal cost of the disprove script over all shards.
+#[derive(Debug, Clone, Copy)]
+pub struct MaxDisproveCost {
+    pub alpha: usize,
+}
+
+impl MaxDisproveCost {
+    /// Creates a new instance of the metric with the given `alpha`
+    pub fn new(alpha: usize) -> Self {
+        Self { alpha }
+    }
+}
+
+impl Default for MaxDisproveCost {
+    fn default() -> Self {
+        Self::new(STACK_SIZE_INDEX)
+    }
+}
+
+impl SplitMetric for MaxDisproveCost {
+    fn alpha(&self) -> usize {
+        self.alpha
+    }
+
+    fn aggregation(&self) -> Aggregation {
+        Aggregation::Max
+    }
+}
+
+/// Metric that makes disproofs cheap on average: the cost of the split is the
+/// average cost of the disprove script over all shards.
+#[derive(Debug, Clone, Copy)]
+pub struct AverageDisproveCost {
+    pub alpha: usize,
+}
+
+impl AverageDisproveCost {
+    /// Creates a new instance of the metric with the given `alpha`
+    pub fn new(alpha: usize) -> Self {
+        Self { alpha }
+    }
+}
+
+impl Default for AverageDisproveCost {
+    fn default() -> Self {
+        Self::new(STACK_SIZE_INDEX)
+    }
+}
+
+impl SplitMetric for AverageDisproveCost {
+    fn alpha(&self) -> usize {
+        self.alpha
+    }
+
+    fn aggregation(&self) -> Aggregation {
+        Aggregation::Average
+    }
+}
@@ -1,9 +1,30 @@
 //! Module that contains the implementation of the splitter
 //! together with all auxiliary functions and data structures.
 
+pub mod artifact;
+pub mod checkpoints;
+pub mod classes;
+pub mod compose;
+pub mod conditional;
+pub mod constraints;
 pub mod core;
+pub mod encoding;
+pub mod error;
+pub mod hinted;
+pub mod hints;
 pub mod intermediate_state;
+pub mod lift;
+pub mod metric;
+pub mod observer;
+pub mod optimal;
+pub mod periodic;
+pub mod profile;
+pub mod program;
+pub mod report;
+pub mod robustness;
 pub mod script;
+pub mod stream;
+pub mod weights;
 
 #[cfg(test)]
 pub mod tests;
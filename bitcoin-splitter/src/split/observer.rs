@@ -0,0 +1,62 @@
+//! Module containing the [`SplitObserver`] trait, which allows the caller to
+//! follow and control the search-based splitters without any terminal output.
+
+use indicatif::ProgressBar;
+
+use super::error::SplitError;
+
+/// Observer of the search over the split candidates. All methods have
+/// the default no-op implementations, so the observer can implement only
+/// the hooks it is interested in.
+pub trait SplitObserver {
+    /// Called once before the search with the total number of candidates
+    fn on_start(&mut self, _total: usize) {}
+
+    /// Called after each processed candidate
+    fn on_progress(&mut self, _processed: usize, _total: usize) {}
+
+    /// Called when the candidate with the given chunk size fails to split the script
+    fn on_candidate_failed(&mut self, _chunk_size: usize, _error: &SplitError) {}
+
+    /// Called when the candidate with the given chunk size becomes the best one so far
+    fn on_new_best(&mut self, _chunk_size: usize, _cost: usize) {}
+
+    /// Returns whether the search should be stopped. It is checked after
+    /// each processed candidate.
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+
+    /// Called once after the search is finished (or cancelled)
+    fn on_finish(&mut self) {}
+}
+
+/// Observer that does nothing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SilentObserver;
+
+impl SplitObserver for SilentObserver {}
+
+/// Observer that displays the progress of the search in the terminal
+#[derive(Debug, Default)]
+pub struct ProgressBarObserver {
+    bar: Option<ProgressBar>,
+}
+
+impl SplitObserver for ProgressBarObserver {
+    fn on_start(&mut self, total: usize) {
+        self.bar = Some(ProgressBar::new(total as u64));
+    }
+
+    fn on_progress(&mut self, _processed: usize, _total: usize) {
+        if let Some(bar) = &self.bar {
+            bar.inc(1);
+        }
+    }
+
+    fn on_finish(&mut self) {
+        if let Some(bar) = &self.bar {
+            bar.finish();
+        }
+    }
+}
@@ -0,0 +1,329 @@
+//! Module containing the optimal splitter, which treats every instruction
+//! boundary as a candidate cut and picks the cuts with the lowest disprove
+//! script cost according to the given metric.
+
+use std::collections::VecDeque;
+
+use bitcoin_utils::treepp::*;
+
+use super::{
+    conditional::validate_shard,
+    core::{try_form_states_incrementally, try_split_at_instructions, MAX_SCRIPT_SIZE},
+    error::SplitError,
+    hints::AnnotatedScript,
+    metric::{Aggregation, SplitMetric},
+    profile::ScriptProfile,
+    script::SplitResult,
+};
+
+/// Optimal split of the script. It works as follows:
+/// 1. We execute the script once and record the state size at each instruction boundary
+/// 2. Using the dynamic programming over the boundaries, we find the cuts minimizing
+///    the metric of the shard costs `|f[i]| + alpha * (|z[i-1]| + |z[i]|)`: their maximum
+///    (for the default `alpha`, this is the [`SplitResult::complexity_index`] of the split)
+///    or their average, depending on the [`Aggregation`] of the metric
+/// 3. We execute each shard with the input and return the [`SplitResult`]
+///
+/// For both aggregations the result is the exact optimum.
+/// Cuts are placed only at the boundaries allowed by the split hints of the script.
+///
+/// Panics if the script cannot be split, see [`try_optimal_split`] for the
+/// non-panicking version.
+pub fn optimal_split(
+    input: Script,
+    script: impl Into<AnnotatedScript>,
+    metric: &impl SplitMetric,
+) -> SplitResult {
+    try_optimal_split(input, script, metric)
+        .unwrap_or_else(|err| panic!("failed to split the script: {:?}", err))
+}
+
+/// Optimal split of the script, which returns an error instead of panicking if the script
+/// has invalid split hints or malformed conditional blocks, some shard fails on the given
+/// input, or the script cannot be split into shards below the maximum script size
+/// ([`SplitError::NoValidSplit`]).
+pub fn try_optimal_split(
+    input: Script,
+    script: impl Into<AnnotatedScript>,
+    metric: &impl SplitMetric,
+) -> Result<SplitResult, SplitError> {
+    split_with_profile(input, script.into(), metric, ScriptProfile::execute)
+}
+
+/// Same as [`optimal_split`], but the state sizes are estimated by the static analysis
+/// of the script (see [`ScriptProfile::analyze`]) instead of its execution. Thus, the script
+/// is executed only once the cuts are picked, to form the intermediate states. Cuts are
+/// never placed at the boundaries where the state size depends on the values.
+///
+/// Panics if the script cannot be split, see [`try_static_optimal_split`] for the
+/// non-panicking version.
+pub fn static_optimal_split(
+    input: Script,
+    script: impl Into<AnnotatedScript>,
+    metric: &impl SplitMetric,
+) -> SplitResult {
+    try_static_optimal_split(input, script, metric)
+        .unwrap_or_else(|err| panic!("failed to split the script: {:?}", err))
+}
+
+/// Same as [`try_optimal_split`], but the state sizes are estimated by the static analysis
+/// of the script, see [`static_optimal_split`].
+pub fn try_static_optimal_split(
+    input: Script,
+    script: impl Into<AnnotatedScript>,
+    metric: &impl SplitMetric,
+) -> Result<SplitResult, SplitError> {
+    split_with_profile(input, script.into(), metric, ScriptProfile::analyze)
+}
+
+/// Optimal split of the script using the profile built by the given function
+fn split_with_profile(
+    input: Script,
+    script: AnnotatedScript,
+    metric: &impl SplitMetric,
+    build_profile: fn(&Script, &Script) -> ScriptProfile,
+) -> Result<SplitResult, SplitError> {
+    let hints = script.checked_hints()?;
+    let script = script.into_script();
+    validate_shard(&script).map_err(|(position, error)| SplitError::MalformedShard {
+        shard_id: 0,
+        position,
+        error,
+    })?;
+    let mut profile = build_profile(&input, &script);
+    hints.restrict(&mut profile);
+    let cuts = optimal_cuts(&profile, metric).ok_or(SplitError::NoValidSplit)?;
+
+    let shards = try_split_at_instructions(&script, &cuts)?;
+    let intermediate_states = try_form_states_incrementally(&shards, input)?;
+
+    Ok(SplitResult::new(shards, intermediate_states))
+}
+
+/// Finds the cuts with the lowest cost according to the metric.
+///
+/// We binary search over the threshold value and check whether the script can be split
+/// so that each shard cost does not exceed it (see [`cuts_within`]). The minimal feasible
+/// threshold is the lowest possible worst shard cost, so its split is the optimum for the
+/// worst-case metric. For the average metric, the split is then improved by
+/// [`average_optimal_cuts`]. Returns `None` if the script cannot be split into shards
+/// below [`MAX_SCRIPT_SIZE`].
+pub(super) fn optimal_cuts(
+    profile: &ScriptProfile,
+    metric: &impl SplitMetric,
+) -> Option<Vec<usize>> {
+    let max_state_size = profile.state_sizes.iter().copied().max().unwrap_or(0);
+
+    // Any split with shards fitting into MAX_SCRIPT_SIZE has the cost below this value
+    let mut low = 0;
+    let mut high = MAX_SCRIPT_SIZE + 2 * max_state_size * metric.alpha();
+    let mut best_cuts = cuts_within(profile, metric.alpha(), high)?;
+
+    while low < high {
+        let threshold = low + (high - low) / 2;
+        match cuts_within(profile, metric.alpha(), threshold) {
+            Some(cuts) => {
+                high = threshold;
+                best_cuts = cuts;
+            }
+            None => low = threshold + 1,
+        }
+    }
+
+    Some(match metric.aggregation() {
+        Aggregation::Max => best_cuts,
+        Aggregation::Average => average_optimal_cuts(profile, metric, best_cuts),
+    })
+}
+
+/// Returns the costs of the shards of the split given by the cuts without executing them
+fn shard_costs(profile: &ScriptProfile, cuts: &[usize], metric: &impl SplitMetric) -> Vec<usize> {
+    let boundaries: Vec<usize> = std::iter::once(0)
+        .chain(cuts.iter().copied())
+        .chain(std::iter::once(profile.len()))
+        .collect();
+
+    boundaries
+        .windows(2)
+        .map(|shard| {
+            // Similarly to the paper, the state before the first shard is not counted
+            let from_state_size = match shard[0] {
+                0 => 0,
+                boundary => profile.state_sizes[boundary],
+            };
+
+            metric.shard_cost(
+                profile.shard_size(shard[0], shard[1]),
+                from_state_size,
+                profile.state_sizes[shard[1]],
+            )
+        })
+        .collect()
+}
+
+/// Finds the cuts with the lowest average shard cost, starting from the given split.
+///
+/// The average cost of the shards `c[1], ..., c[m]` is below the average `S/K` of the
+/// current split iff `sum(K * c[s] - S) < 0`. Unlike the average, the latter sum is additive
+/// over the shards, so the split minimizing it is found by the dynamic programming
+/// (see [`cuts_below_average`]). If the sum is negative, the found split is better and
+/// becomes the current one, otherwise the current split is optimal (this is Dinkelbach's
+/// method, which takes a few iterations in practice).
+fn average_optimal_cuts(
+    profile: &ScriptProfile,
+    metric: &impl SplitMetric,
+    mut cuts: Vec<usize>,
+) -> Vec<usize> {
+    loop {
+        let costs = shard_costs(profile, &cuts, metric);
+        match cuts_below_average(profile, metric.alpha(), costs.iter().sum(), costs.len()) {
+            Some(better_cuts) => cuts = better_cuts,
+            None => return cuts,
+        }
+    }
+}
+
+/// Returns the cuts minimizing `sum(shards_number * c[s] - total_cost)` over the shards
+/// of the split if the minimum is negative, that is, if the average shard cost of the split
+/// is below `total_cost / shards_number`, or `None` otherwise.
+///
+/// Denoting by `best[i]` the minimal sum over the shards up to boundary `i`, we have
+/// `best[j] = K * (offset[j] + alpha * z[j]) - S + min(best[i] + K * (alpha * z[i] - offset[i]))`
+/// over the reachable boundaries `i` within the last [`MAX_SCRIPT_SIZE`] bytes,
+/// so the minimum is kept with the monotonic queue, similarly to [`cuts_within`].
+fn cuts_below_average(
+    profile: &ScriptProfile,
+    alpha: usize,
+    total_cost: usize,
+    shards_number: usize,
+) -> Option<Vec<usize>> {
+    // Sums may exceed i64 for the long scripts split into many shards
+    let (total_cost, shards_number) = (total_cost as i128, shards_number as i128);
+    let key = |best: i128, boundary: usize, state_size: usize| -> i128 {
+        best + shards_number * ((state_size * alpha) as i128 - profile.offsets[boundary] as i128)
+    };
+
+    let boundaries_number = profile.len() + 1;
+    let mut predecessors: Vec<Option<usize>> = vec![None; boundaries_number];
+    let mut best = 0;
+
+    // Queue of reachable boundaries together with their keys. Keys are strictly
+    // increasing from the front to the back. The state before the first shard
+    // is not counted.
+    let mut queue: VecDeque<(usize, i128)> = VecDeque::from([(0, key(0, 0, 0))]);
+
+    for j in 1..boundaries_number {
+        // Dropping the boundaries whose shards would exceed the maximum script size
+        while queue
+            .front()
+            .is_some_and(|&(i, _)| profile.shard_size(i, j) > MAX_SCRIPT_SIZE)
+        {
+            queue.pop_front();
+        }
+
+        if !profile.legal[j] {
+            continue;
+        }
+
+        let Some(&(i, min_key)) = queue.front() else {
+            continue;
+        };
+
+        let state_size = profile.state_sizes[j];
+        best = shards_number * (profile.offsets[j] + state_size * alpha) as i128 - total_cost
+            + min_key;
+        predecessors[j] = Some(i);
+
+        let current_key = key(best, j, state_size);
+        while queue.back().is_some_and(|&(_, k)| k >= current_key) {
+            queue.pop_back();
+        }
+        queue.push_back((j, current_key));
+    }
+
+    // The sum for the end of the script is the last one computed
+    if predecessors[boundaries_number - 1].is_none() || best >= 0 {
+        return None;
+    }
+
+    // Restoring the cuts by going back from the end of the script
+    let mut cuts = vec![];
+    let mut boundary = boundaries_number - 1;
+    while boundary != 0 {
+        boundary = predecessors[boundary].expect("reachable boundaries have predecessors");
+        if boundary != 0 {
+            cuts.push(boundary);
+        }
+    }
+    cuts.reverse();
+
+    Some(cuts)
+}
+
+/// Returns the cuts such that the cost of each shard does not exceed the given
+/// threshold, or `None` if there are no such cuts.
+///
+/// The shard spanning from boundary `i` to boundary `j` costs
+/// `offset[j] - offset[i] + alpha * (z[i] + z[j])`, so the boundary `j`
+/// is reachable if there is a reachable boundary `i` with
+/// `offset[i] - alpha * z[i] >= offset[j] + alpha * z[j] - threshold`.
+/// Thus, it suffices to keep the maximum of the left-hand side over the reachable boundaries
+/// within the last [`MAX_SCRIPT_SIZE`] bytes, which we do with the monotonic queue.
+fn cuts_within(profile: &ScriptProfile, alpha: usize, threshold: usize) -> Option<Vec<usize>> {
+    let key = |boundary: usize, state_size: usize| -> i64 {
+        profile.offsets[boundary] as i64 - (state_size * alpha) as i64
+    };
+
+    let boundaries_number = profile.len() + 1;
+    let mut predecessors: Vec<Option<usize>> = vec![None; boundaries_number];
+
+    // Queue of reachable boundaries together with their keys. Keys are strictly
+    // decreasing from the front to the back. Note that, similarly to the
+    // complexity index, the state before the first shard is not counted.
+    let mut queue: VecDeque<(usize, i64)> = VecDeque::from([(0, key(0, 0))]);
+
+    for j in 1..boundaries_number {
+        // Dropping the boundaries whose shards would exceed the maximum script size
+        while queue
+            .front()
+            .is_some_and(|&(i, _)| profile.shard_size(i, j) > MAX_SCRIPT_SIZE)
+        {
+            queue.pop_front();
+        }
+
+        if !profile.legal[j] {
+            continue;
+        }
+
+        let Some(&(i, best_key)) = queue.front() else {
+            continue;
+        };
+
+        let state_size = profile.state_sizes[j];
+        let required_key = (profile.offsets[j] + state_size * alpha) as i64 - threshold as i64;
+        if best_key < required_key {
+            continue;
+        }
+
+        predecessors[j] = Some(i);
+
+        let current_key = key(j, state_size);
+        while queue.back().is_some_and(|&(_, k)| k <= current_key) {
+            queue.pop_back();
+        }
+        queue.push_back((j, current_key));
+    }
+
+    // Restoring the cuts by going back from the end of the script
+    let mut cuts = vec![];
+    let mut boundary = boundaries_number - 1;
+    while boundary != 0 {
+        boundary = predecessors[boundary]?;
+        if boundary != 0 {
+            cuts.push(boundary);
+        }
+    }
+    cuts.reverse();
+
+    Some(cuts)
+}
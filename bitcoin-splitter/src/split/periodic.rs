@@ -0,0 +1,260 @@
+//! Module containing the detection of the periodic structure of the script.
+//!
+//! Scripts built with `for` loops inside `script!` (steps of the square Fibonacci sequence,
+//! rounds of SHA-256, windows of the multiplication) unroll into the same block of
+//! instructions repeated many times. Cutting such a script at the iteration boundaries
+//! gives the shards that are byte-identical and have the same stack shape, exactly as
+//! the hand-written [`split_here`](super::hints::split_here) points after each iteration do.
+//!
+//! The detection works in two passes:
+//! 1. The rolling hash of every window of [`WINDOW`] instructions is computed, and the
+//!    distances between the repeated windows give the candidate periods.
+//! 2. For each candidate period `p`, the maximal runs where the instruction `i` equals
+//!    the instruction `i + p` give the regions consisting of at least two iterations.
+//!
+//! The regions covering the most instructions are taken greedily, so the outer loops win
+//! over the loops nested into their iterations.
+
+use std::collections::HashMap;
+
+use bitcoin_utils::treepp::*;
+
+use super::{
+    core::{try_naive_split, SplitType},
+    error::SplitError,
+    hints::{AnnotatedScript, SplitHints},
+    script::SplitResult,
+};
+
+/// Number of instructions in the window hashed to find the candidate periods
+pub const WINDOW: usize = 16;
+/// Maximal number of candidate periods checked against the script
+pub const MAX_CANDIDATES: usize = 16;
+/// Default minimal period: shorter loops would give too many cut points
+pub const DEFAULT_MIN_PERIOD: usize = 8;
+
+/// Base of the polynomial rolling hash
+const HASH_BASE: u64 = 1_000_003;
+
+/// Region of the script consisting of the same block of instructions repeated
+/// several times. Similarly to [`ScriptProfile`](super::profile::ScriptProfile),
+/// positions are the instruction boundaries of the script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeriodicRegion {
+    /// Boundary at which the first iteration starts
+    pub start: usize,
+    /// Number of instructions in a single iteration
+    pub period: usize,
+    /// Number of complete iterations
+    pub iterations: usize,
+}
+
+impl PeriodicRegion {
+    /// Returns the boundary at which the last iteration ends
+    pub fn end(&self) -> usize {
+        self.start + self.period * self.iterations
+    }
+
+    /// Returns the number of instructions covered by the region
+    pub fn covered(&self) -> usize {
+        self.period * self.iterations
+    }
+
+    /// Returns the boundaries between the iterations, including the start
+    /// of the first iteration and the end of the last one
+    pub fn boundaries(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..=self.iterations).map(|i| self.start + i * self.period)
+    }
+}
+
+/// Periodic regions found in the script, in the increasing order of their starts
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoopStructure {
+    pub regions: Vec<PeriodicRegion>,
+}
+
+impl LoopStructure {
+    /// Finds the non-overlapping periodic regions with the period of at least `min_period`
+    /// instructions
+    pub fn detect(script: &Script, min_period: usize) -> Result<Self, SplitError> {
+        let tokens = tokenize(script)?;
+        let min_period = min_period.max(1);
+
+        let mut regions: Vec<PeriodicRegion> = candidate_periods(&tokens, min_period)
+            .into_iter()
+            .flat_map(|period| periodic_regions(&tokens, period))
+            .collect();
+
+        // Largest regions first, and the shorter period among the ones covering the same
+        // instructions, since the multiples of the period always cover them as well
+        regions.sort_by_key(|region| {
+            (
+                std::cmp::Reverse(region.covered()),
+                region.period,
+                region.start,
+            )
+        });
+
+        let mut selected: Vec<PeriodicRegion> = vec![];
+        for region in regions {
+            let overlaps = selected
+                .iter()
+                .any(|other| region.start < other.end() && other.start < region.end());
+            if !overlaps {
+                selected.push(region);
+            }
+        }
+        selected.sort_by_key(|region| region.start);
+
+        Ok(Self { regions: selected })
+    }
+
+    /// Returns whether no periodic regions were found
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// Returns the iteration boundaries of all regions in the increasing order
+    pub fn boundaries(&self) -> Vec<usize> {
+        let mut boundaries: Vec<usize> = self
+            .regions
+            .iter()
+            .flat_map(|region| region.boundaries())
+            .collect();
+        boundaries.dedup();
+        boundaries
+    }
+
+    /// Returns the script with each iteration boundary marked as the preferred cut point
+    pub fn mark(&self, script: &Script) -> AnnotatedScript {
+        AnnotatedScript::new(
+            script.clone(),
+            SplitHints {
+                preferred: self.boundaries(),
+                atomic: vec![],
+            },
+        )
+    }
+}
+
+/// Marks the iteration boundaries of the loops found in the script as the preferred
+/// cut points. The script that already has hints is returned intact, since the
+/// hand-written hints are assumed to know the structure better.
+///
+/// Note that once the script has the preferred cut points, the splitter never cuts
+/// outside of them, so the parts of the script outside the loops are kept whole.
+pub fn with_loop_hints(script: impl Into<AnnotatedScript>) -> Result<AnnotatedScript, SplitError> {
+    let script = script.into();
+    if !script.checked_hints()?.is_empty() {
+        return Ok(script);
+    }
+
+    let loops = LoopStructure::detect(script.script(), DEFAULT_MIN_PERIOD)?;
+    if loops.is_empty() {
+        return Ok(script);
+    }
+
+    Ok(loops.mark(script.script()))
+}
+
+/// Naive split of the script cut only at the iteration boundaries of its loops,
+/// see [`with_loop_hints`]. The chunk size of `1` gives one iteration per shard.
+pub fn periodic_split(
+    input: Script,
+    script: impl Into<AnnotatedScript>,
+    split_type: SplitType,
+    chunk_size: usize,
+) -> Result<SplitResult, SplitError> {
+    try_naive_split(input, with_loop_hints(script)?, split_type, chunk_size)
+}
+
+/// Maps each instruction of the script to the identifier of its byte encoding
+fn tokenize(script: &Script) -> Result<Vec<usize>, SplitError> {
+    let mut offsets = vec![];
+    for (offset, instruction) in script.instruction_indices() {
+        instruction?;
+        offsets.push(offset);
+    }
+    offsets.push(script.len());
+
+    let mut ids: HashMap<&[u8], usize> = HashMap::new();
+    Ok(offsets
+        .windows(2)
+        .map(|bounds| {
+            let next_id = ids.len();
+            *ids.entry(&script.as_bytes()[bounds[0]..bounds[1]])
+                .or_insert(next_id)
+        })
+        .collect())
+}
+
+/// Returns the most frequent distances of at least `min_period` between
+/// the neighbouring occurrences of the same window of instructions
+fn candidate_periods(tokens: &[usize], min_period: usize) -> Vec<usize> {
+    if tokens.len() < WINDOW {
+        return vec![];
+    }
+
+    let token_hash = |token: usize| token as u64 + 1;
+    let highest_power = (1..WINDOW).fold(1u64, |power, _| power.wrapping_mul(HASH_BASE));
+
+    let mut hash = tokens[..WINDOW].iter().fold(0u64, |hash, &token| {
+        hash.wrapping_mul(HASH_BASE).wrapping_add(token_hash(token))
+    });
+    let mut last_occurrence: HashMap<u64, usize> = HashMap::new();
+    let mut distances: HashMap<usize, usize> = HashMap::new();
+
+    for start in 0..=tokens.len() - WINDOW {
+        if start > 0 {
+            hash = hash
+                .wrapping_sub(token_hash(tokens[start - 1]).wrapping_mul(highest_power))
+                .wrapping_mul(HASH_BASE)
+                .wrapping_add(token_hash(tokens[start + WINDOW - 1]));
+        }
+
+        if let Some(previous) = last_occurrence.insert(hash, start) {
+            let distance = start - previous;
+            if distance >= min_period {
+                *distances.entry(distance).or_default() += 1;
+            }
+        }
+    }
+
+    // Hash collisions only add the candidates, which are then checked exactly
+    let mut distances: Vec<(usize, usize)> = distances.into_iter().collect();
+    distances.sort_by_key(|&(distance, count)| (std::cmp::Reverse(count), distance));
+    distances
+        .into_iter()
+        .take(MAX_CANDIDATES)
+        .map(|(distance, _)| distance)
+        .collect()
+}
+
+/// Returns the maximal regions with the given period consisting of at least two iterations
+fn periodic_regions(tokens: &[usize], period: usize) -> Vec<PeriodicRegion> {
+    let mut regions = vec![];
+    let mut i = 0;
+
+    while i + period < tokens.len() {
+        if tokens[i] != tokens[i + period] {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        while i + period < tokens.len() && tokens[i] == tokens[i + period] {
+            i += 1;
+        }
+
+        let iterations = (i - start + period) / period;
+        if iterations >= 2 {
+            regions.push(PeriodicRegion {
+                start,
+                period,
+                iterations,
+            });
+        }
+    }
+
+    regions
+}
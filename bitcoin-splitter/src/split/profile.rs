@@ -0,0 +1,130 @@
+//! This module contains the [`ScriptProfile`] struct, which describes how the
+//! state (stack and altstack) evolves at every instruction boundary of the script.
+
+use bitcoin_utils::{debug::script_exec_with_stack, stack_effect::StackAnalysis, treepp::*};
+
+use super::{
+    conditional::ConditionTracker, error::SplitError, intermediate_state::IntermediateState,
+};
+
+/// Profile of the script execution. Boundary `k` is the point right before
+/// the `k`-th instruction, so the script with `n` instructions has `n+1` boundaries:
+/// the first one is the start of the script and the last one is its end.
+#[derive(Debug, Clone)]
+pub struct ScriptProfile {
+    /// Byte offset of each boundary in the script
+    pub offsets: Vec<usize>,
+    /// Size of the state (stack + altstack) at each boundary
+    pub state_sizes: Vec<usize>,
+    /// Whether the script can be cut at the given boundary, that is,
+    /// whether the boundary does not lie inside the `OP_IF ... OP_ENDIF` block.
+    /// Once the conditional nesting of the script breaks, no further boundary is legal.
+    pub legal: Vec<bool>,
+}
+
+impl ScriptProfile {
+    /// Executes the script once with the given input and records the state
+    /// size at every instruction boundary.
+    pub fn execute(input: &Script, script: &Script) -> Self {
+        let initial_state = IntermediateState::from_inject_script(input);
+        let (offsets, legal) = boundaries(script).expect("script is most likely corrupted");
+
+        // Now, executing the script and saving the state size after each instruction.
+        // If the execution stops earlier (for instance, due to the failed OP_VERIFY),
+        // the remaining boundaries keep the size of the last state.
+        let mut exec = script_exec_with_stack(
+            script.clone(),
+            initial_state.stack.clone(),
+            initial_state.altstack.clone(),
+        );
+        let mut state_sizes = vec![initial_state.size()];
+        let mut current_size = initial_state.size();
+        for _ in 1..offsets.len() {
+            if exec.exec_next().is_ok() {
+                current_size = exec.stack().len() + exec.altstack().len();
+            }
+            state_sizes.push(current_size);
+        }
+
+        Self {
+            offsets,
+            state_sizes,
+            legal,
+        }
+    }
+
+    /// Estimates the profile without executing the script, computing the state sizes
+    /// by the static analysis of its stack effect (see [`StackAnalysis`]). Only the input
+    /// is executed to know the initial state. The boundaries at which the state size
+    /// depends on the values are illegal and keep the size of the last known state.
+    pub fn analyze(input: &Script, script: &Script) -> Self {
+        let initial_state = IntermediateState::from_inject_script(input);
+        let (offsets, mut legal) = boundaries(script).expect("script is most likely corrupted");
+
+        let analysis = StackAnalysis::analyze(script).expect("script is most likely corrupted");
+        let mut state_sizes = vec![];
+        let mut current_size = initial_state.size();
+
+        let known_sizes =
+            analysis.state_sizes(initial_state.stack.len(), initial_state.altstack.len());
+        for (boundary, size) in known_sizes.into_iter().enumerate() {
+            match size {
+                Some(size) => current_size = size,
+                None => legal[boundary] = false,
+            }
+            state_sizes.push(current_size);
+        }
+
+        Self {
+            offsets,
+            state_sizes,
+            legal,
+        }
+    }
+
+    /// Returns the number of instructions in the profiled script
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    /// Returns whether the profiled script is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the size of the shard that spans from boundary `from` to boundary `to`
+    pub fn shard_size(&self, from: usize, to: usize) -> usize {
+        self.offsets[to] - self.offsets[from]
+    }
+
+    /// Returns the legal boundaries inside the script from the cheapest to cut to the
+    /// most expensive one, that is, in the increasing order of the state size at them
+    pub fn ranked_cuts(&self) -> Vec<usize> {
+        let mut cuts: Vec<usize> = (1..self.len())
+            .filter(|&boundary| self.legal[boundary])
+            .collect();
+        cuts.sort_by_key(|&boundary| self.state_sizes[boundary]);
+        cuts
+    }
+}
+
+/// Returns the byte offset of each boundary of the script and whether
+/// the boundary does not lie inside the conditional block, or an error
+/// if the script is corrupted
+pub(super) fn boundaries(script: &Script) -> Result<(Vec<usize>, Vec<bool>), SplitError> {
+    let mut offsets = vec![];
+    let mut legal = vec![true];
+    let mut conditions = ConditionTracker::new();
+    let mut malformed = false;
+
+    for (offset, instruction) in script.instruction_indices() {
+        let instruction = instruction?;
+        malformed = malformed || conditions.step(&instruction).is_err();
+
+        offsets.push(offset);
+        legal.push(!malformed && conditions.is_balanced());
+    }
+    offsets.push(script.len());
+
+    Ok((offsets, legal))
+}
@@ -0,0 +1,182 @@
+//! Module containing the object-safe counterpart of [`SplitableScript`].
+//!
+//! [`SplitableScript`] fixes the input and output sizes in const generics and consists of
+//! associated functions, so it cannot be used as a trait object. [`SplitProgram`] has the
+//! sizes known at runtime only, so the programs of different kinds (including the ones
+//! loaded from files) can be stored together, for instance, in the [`ProgramRegistry`].
+
+use std::{collections::BTreeMap, marker::PhantomData};
+
+use bitcoin_utils::treepp::*;
+
+use super::{
+    core::{default_split, naive_split, SplitType},
+    hints::AnnotatedScript,
+    script::{verify_output, IOPair, SplitResult, SplitableScript},
+};
+
+/// Pair of input and output scripts of the [`SplitProgram`]. Same as [`IOPair`],
+/// but without the sizes fixed at compile time.
+pub struct ProgramIOPair {
+    /// Input script containing the elements which will be fed to the main script
+    pub input: Script,
+    /// Output script containing the elements which will be compared to the output of the main script
+    pub output: Script,
+}
+
+impl<const INPUT_SIZE: usize, const OUTPUT_SIZE: usize> From<IOPair<INPUT_SIZE, OUTPUT_SIZE>>
+    for ProgramIOPair
+{
+    fn from(io_pair: IOPair<INPUT_SIZE, OUTPUT_SIZE>) -> Self {
+        Self {
+            input: io_pair.input,
+            output: io_pair.output,
+        }
+    }
+}
+
+/// Object-safe trait of the program that can be split, with the input and
+/// output sizes known at runtime
+pub trait SplitProgram {
+    /// Returns the number of elements in the input
+    fn input_size(&self) -> usize;
+
+    /// Returns the number of elements in the output
+    fn output_size(&self) -> usize;
+
+    /// Returns the main logic (f) of the program
+    fn script(&self) -> Script;
+
+    /// Returns the script together with its split hints, see [`SplitableScript::annotated_script`]
+    fn annotated_script(&self) -> AnnotatedScript {
+        self.script().into()
+    }
+
+    /// Generates a random valid input for the program
+    fn generate_valid_io_pair(&self) -> ProgramIOPair;
+
+    /// Generates invalid input for the program
+    fn generate_invalid_io_pair(&self) -> ProgramIOPair;
+
+    /// Verifies that the input is valid for the program
+    fn verify(&self, input: Script, output: Script) -> bool {
+        verify_output(input, self.script(), output, self.output_size())
+    }
+
+    /// Verifies that the input is valid for the program with random input and output
+    fn verify_random(&self) -> bool {
+        let ProgramIOPair { input, output } = self.generate_valid_io_pair();
+        self.verify(input, output)
+    }
+
+    /// Splits the program into smaller parts
+    fn default_split(&self, input: Script, split_type: SplitType) -> SplitResult {
+        default_split(input, self.annotated_script(), split_type)
+    }
+
+    /// Splits the program into smaller parts with the specified chunk size
+    fn split(&self, input: Script, split_type: SplitType, chunk_size: usize) -> SplitResult {
+        naive_split(input, self.annotated_script(), split_type, chunk_size)
+    }
+}
+
+/// Adapter implementing [`SplitProgram`] for any [`SplitableScript`],
+/// see [`SplitableScript::program`]
+pub struct ScriptProgram<S, const INPUT_SIZE: usize, const OUTPUT_SIZE: usize> {
+    _script: PhantomData<S>,
+}
+
+impl<S, const INPUT_SIZE: usize, const OUTPUT_SIZE: usize> Default
+    for ScriptProgram<S, INPUT_SIZE, OUTPUT_SIZE>
+{
+    fn default() -> Self {
+        Self {
+            _script: PhantomData,
+        }
+    }
+}
+
+impl<S, const INPUT_SIZE: usize, const OUTPUT_SIZE: usize> SplitProgram
+    for ScriptProgram<S, INPUT_SIZE, OUTPUT_SIZE>
+where
+    S: SplitableScript<INPUT_SIZE, OUTPUT_SIZE>,
+{
+    fn input_size(&self) -> usize {
+        INPUT_SIZE
+    }
+
+    fn output_size(&self) -> usize {
+        OUTPUT_SIZE
+    }
+
+    fn script(&self) -> Script {
+        S::script()
+    }
+
+    fn annotated_script(&self) -> AnnotatedScript {
+        S::annotated_script()
+    }
+
+    fn generate_valid_io_pair(&self) -> ProgramIOPair {
+        S::generate_valid_io_pair().into()
+    }
+
+    fn generate_invalid_io_pair(&self) -> ProgramIOPair {
+        S::generate_invalid_io_pair().into()
+    }
+
+    fn verify(&self, input: Script, output: Script) -> bool {
+        S::verify(input, output)
+    }
+
+    fn default_split(&self, input: Script, split_type: SplitType) -> SplitResult {
+        S::default_split(input, split_type)
+    }
+
+    fn split(&self, input: Script, split_type: SplitType, chunk_size: usize) -> SplitResult {
+        S::split(input, split_type, chunk_size)
+    }
+}
+
+/// Collection of the programs of different kinds, accessible by their names
+#[derive(Default)]
+pub struct ProgramRegistry {
+    programs: BTreeMap<String, Box<dyn SplitProgram>>,
+}
+
+impl ProgramRegistry {
+    /// Creates an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the program under the given name, returning the program
+    /// previously registered under it, if any
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        program: Box<dyn SplitProgram>,
+    ) -> Option<Box<dyn SplitProgram>> {
+        self.programs.insert(name.into(), program)
+    }
+
+    /// Returns the program registered under the given name
+    pub fn get(&self, name: &str) -> Option<&dyn SplitProgram> {
+        self.programs.get(name).map(|program| program.as_ref())
+    }
+
+    /// Returns the names of the registered programs in the alphabetical order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.programs.keys().map(String::as_str)
+    }
+
+    /// Returns the number of the registered programs
+    pub fn len(&self) -> usize {
+        self.programs.len()
+    }
+
+    /// Returns whether the registry is empty
+    pub fn is_empty(&self) -> bool {
+        self.programs.is_empty()
+    }
+}
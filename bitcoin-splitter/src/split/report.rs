@@ -0,0 +1,185 @@
+//! Module containing the [`SplitReport`], which collects the per-shard statistics
+//! of the split into a single table that can be rendered as Markdown, CSV or JSON.
+
+use std::fmt::Write;
+
+use bitcoin_utils::{debug::script_exec_with_stack, treepp::*};
+use serde::Serialize;
+
+use super::{intermediate_state::IntermediateState, metric::SplitMetric, script::SplitResult};
+
+/// Statistics of a single shard
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ShardReport {
+    /// Size of the shard in bytes
+    pub bytes: usize,
+    /// Number of instructions in the shard
+    pub instructions: usize,
+    /// Size of the stack before the shard
+    pub stack_before: usize,
+    /// Size of the altstack before the shard
+    pub altstack_before: usize,
+    /// Size of the stack after the shard
+    pub stack_after: usize,
+    /// Size of the altstack after the shard
+    pub altstack_after: usize,
+    /// Maximal size of the stack and altstack together during the shard execution
+    pub max_depth: usize,
+    /// Cost of the shard according to the metric, see [`SplitMetric::shard_cost`]
+    pub cost: usize,
+}
+
+/// Report of the split with one row per shard
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SplitReport {
+    /// Statistics of each shard
+    pub shards: Vec<ShardReport>,
+    /// Total size of the states, see [`SplitResult::total_states_size`]
+    pub total_states_size: usize,
+    /// Maximal size of two adjacent states, see [`SplitResult::max_adjacent_states_size`]
+    pub max_adjacent_states_size: usize,
+    /// Cost of the split according to the metric
+    pub cost: usize,
+    /// Number of distinct shards, see [`SplitResult::shard_classes`]
+    pub unique_shards: usize,
+    /// Total size of the distinct shards in bytes
+    pub unique_shards_size: usize,
+}
+
+impl SplitReport {
+    /// Builds the report for the split of the script executed with the given input.
+    /// Similarly to [`SplitMetric::evaluate`], the cost of the first shard
+    /// does not count the state before it.
+    pub fn new(input: &Script, split_result: &SplitResult, metric: &impl SplitMetric) -> Self {
+        let input_state = IntermediateState::from_inject_script(input);
+
+        let shards = split_result
+            .shards
+            .iter()
+            .zip(split_result.intermediate_states.iter())
+            .enumerate()
+            .map(|(i, (shard, state_after))| {
+                let state_before = match i {
+                    0 => &input_state,
+                    _ => &split_result.intermediate_states[i - 1],
+                };
+                let counted_state_before_size = match i {
+                    0 => 0,
+                    _ => state_before.size(),
+                };
+
+                ShardReport {
+                    bytes: shard.len(),
+                    instructions: shard.instructions().count(),
+                    stack_before: state_before.stack.len(),
+                    altstack_before: state_before.altstack.len(),
+                    stack_after: state_after.stack.len(),
+                    altstack_after: state_after.altstack.len(),
+                    max_depth: max_depth(state_before, shard),
+                    cost: metric.shard_cost(
+                        shard.len(),
+                        counted_state_before_size,
+                        state_after.size(),
+                    ),
+                }
+            })
+            .collect();
+
+        let classes = split_result.shard_classes();
+
+        Self {
+            shards,
+            total_states_size: split_result.total_states_size(),
+            max_adjacent_states_size: split_result.max_adjacent_states_size(),
+            cost: metric.evaluate(split_result),
+            unique_shards: classes.len(),
+            unique_shards_size: classes
+                .representatives()
+                .iter()
+                .map(|&shard_id| split_result.shards[shard_id].len())
+                .sum(),
+        }
+    }
+
+    /// Renders the report as the Markdown table followed by the summary
+    pub fn to_markdown(&self) -> String {
+        let mut result = String::new();
+        writeln!(
+            result,
+            "| Shard | Bytes | Instructions | Stack before | Altstack before | Stack after | Altstack after | Max depth | Cost |"
+        )
+        .unwrap();
+        writeln!(result, "|---:|---:|---:|---:|---:|---:|---:|---:|---:|").unwrap();
+        for (i, row) in self.rows() {
+            writeln!(result, "| {} | {} |", i, row.join(" | ")).unwrap();
+        }
+
+        writeln!(result).unwrap();
+        writeln!(result, "- Total states size: {}", self.total_states_size).unwrap();
+        writeln!(
+            result,
+            "- Max adjacent states size: {}",
+            self.max_adjacent_states_size
+        )
+        .unwrap();
+        writeln!(result, "- Cost: {}", self.cost).unwrap();
+        writeln!(
+            result,
+            "- Unique shards: {} ({} bytes)",
+            self.unique_shards, self.unique_shards_size
+        )
+        .unwrap();
+
+        result
+    }
+
+    /// Renders the shard statistics as CSV
+    pub fn to_csv(&self) -> String {
+        let mut result = String::from(
+            "shard,bytes,instructions,stack_before,altstack_before,stack_after,altstack_after,max_depth,cost\n",
+        );
+        for (i, row) in self.rows() {
+            writeln!(result, "{},{}", i, row.join(",")).unwrap();
+        }
+
+        result
+    }
+
+    /// Renders the report as JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("report is always serializable")
+    }
+
+    /// Returns the statistics of each shard as strings together with the shard index
+    fn rows(&self) -> impl Iterator<Item = (usize, [String; 8])> + '_ {
+        self.shards.iter().enumerate().map(|(i, shard)| {
+            let row = [
+                shard.bytes,
+                shard.instructions,
+                shard.stack_before,
+                shard.altstack_before,
+                shard.stack_after,
+                shard.altstack_after,
+                shard.max_depth,
+                shard.cost,
+            ]
+            .map(|value| value.to_string());
+
+            (i, row)
+        })
+    }
+}
+
+/// Executes the shard starting from the given state and returns the maximal
+/// size of the stack and altstack together during the execution
+fn max_depth(state: &IntermediateState, shard: &Script) -> usize {
+    let mut exec =
+        script_exec_with_stack(shard.clone(), state.stack.clone(), state.altstack.clone());
+
+    let mut max_depth = state.size();
+    while exec.exec_next().is_ok() {
+        max_depth = max_depth.max(exec.stack().len() + exec.altstack().len());
+    }
+
+    max_depth
+}
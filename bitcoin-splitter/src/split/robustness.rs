@@ -0,0 +1,118 @@
+//! Module containing the robustness check of the split.
+//!
+//! The split is computed for a single input, but the shards are fixed on-chain and later
+//! executed on whatever states the operator commits. If the stack shape of the script depends
+//! on the input, the split that is valid for one input can be invalid for another one. Thus,
+//! we replay the same shards on other inputs and check that the states keep their shapes.
+
+use bitcoin_scriptexec::Stack;
+
+use super::{
+    core::form_states_incrementally,
+    encoding::encode_element,
+    intermediate_state::IntermediateState,
+    script::{IOPair, SplitResult},
+};
+
+/// Shape of the intermediate state, that is, the number of words encoding each element
+/// of the stack and altstack (see [`encode_element`]). The disprove script restores the
+/// state word by word, so the states of different shapes need different scripts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateShape {
+    pub stack: Vec<usize>,
+    pub altstack: Vec<usize>,
+}
+
+impl StateShape {
+    /// Returns the shape of the given state
+    pub fn of(state: &IntermediateState) -> Self {
+        let words_lengths = |stack: &Stack| {
+            stack
+                .iter_str()
+                .map(|element| encode_element(&element).len())
+                .collect()
+        };
+
+        Self {
+            stack: words_lengths(&state.stack),
+            altstack: words_lengths(&state.altstack),
+        }
+    }
+}
+
+/// Divergence of the shards replayed on some input from the reference split
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SplitDivergence {
+    /// The state after the shard has a different shape than for the reference input
+    ShapeMismatch {
+        input_id: usize,
+        shard_id: usize,
+        expected: StateShape,
+        actual: StateShape,
+    },
+    /// The states keep their shapes, but the last one differs from the expected output
+    OutputMismatch { input_id: usize },
+}
+
+/// Result of replaying the split on multiple inputs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RobustnessReport {
+    /// Number of inputs the split was replayed on
+    pub inputs_checked: usize,
+    /// The first divergence for each input on which the split diverged
+    pub divergences: Vec<SplitDivergence>,
+}
+
+impl RobustnessReport {
+    /// Returns whether the split behaved the same way on all inputs
+    pub fn is_robust(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+/// Replays the shards of the split on each of the given inputs and reports the inputs on
+/// which the intermediate states change their shapes compared to the states of the split,
+/// or the last state differs from the expected output.
+pub fn check_split_robustness<const INPUT_SIZE: usize, const OUTPUT_SIZE: usize>(
+    split_result: &SplitResult,
+    io_pairs: impl IntoIterator<Item = IOPair<INPUT_SIZE, OUTPUT_SIZE>>,
+) -> RobustnessReport {
+    let mut report = RobustnessReport {
+        inputs_checked: 0,
+        divergences: vec![],
+    };
+
+    for (input_id, IOPair { input, output }) in io_pairs.into_iter().enumerate() {
+        report.inputs_checked += 1;
+
+        let states = form_states_incrementally(&split_result.shards, input);
+        let shape_mismatch = split_result
+            .intermediate_states
+            .iter()
+            .zip(states.iter())
+            .enumerate()
+            .find_map(|(shard_id, (expected, actual))| {
+                let (expected, actual) = (StateShape::of(expected), StateShape::of(actual));
+                (expected != actual).then_some(SplitDivergence::ShapeMismatch {
+                    input_id,
+                    shard_id,
+                    expected,
+                    actual,
+                })
+            });
+
+        if let Some(divergence) = shape_mismatch {
+            report.divergences.push(divergence);
+            continue;
+        }
+
+        let expected_state = IntermediateState::from_inject_script(&output);
+        if states.last() != Some(&expected_state) {
+            report
+                .divergences
+                .push(SplitDivergence::OutputMismatch { input_id });
+        }
+    }
+
+    report
+}
@@ -3,8 +3,16 @@
 use core::fmt;
 
 use super::{
-    core::{default_split, fuzzy_split, naive_split, SplitType, STACK_SIZE_INDEX},
+    classes::ShardClasses,
+    core::{default_split, fuzzy_split, naive_split, SplitType},
+    error::{SplitError, SplitVerificationError},
+    hints::AnnotatedScript,
     intermediate_state::IntermediateState,
+    metric::{MaxDisproveCost, SplitMetric},
+    observer::SplitObserver,
+    optimal::optimal_split,
+    program::{ScriptProgram, SplitProgram},
+    robustness::{check_split_robustness, RobustnessReport},
 };
 use bitcoin_utils::treepp::*;
 
@@ -36,6 +44,14 @@ impl fmt::Debug for SplitResult {
         for (i, shard) in self.shards.iter().enumerate() {
             const MAX_CHARACTERS_TO_SHOW: usize = 100;
             let s = shard.to_asm_string();
+
+            // Short shards are shown as a whole
+            if s.len() <= 2 * MAX_CHARACTERS_TO_SHOW {
+                writeln!(f, "Shard {}: {}", i, s)?;
+                continue;
+            }
+
+            // ASM string consists of ASCII characters only, so slicing it is safe
             let first_opcodes = &s[..MAX_CHARACTERS_TO_SHOW];
             let last_opcodes = &s[s.len() - MAX_CHARACTERS_TO_SHOW..];
 
@@ -71,6 +87,36 @@ impl SplitResult {
             .expect("Intermediate states should not be empty")
     }
 
+    /// Returns the cut points of the split, that is, the instruction index (in the whole
+    /// script) at which each shard but the first one starts. Cuts at the end of the
+    /// script, which give the empty shards, are omitted. The result can be passed (possibly
+    /// adjusted) to [`split_at_checkpoints`](super::checkpoints::split_at_checkpoints).
+    pub fn cuts(&self) -> Vec<usize> {
+        let total_instructions: usize = self
+            .shards
+            .iter()
+            .map(|shard| shard.instructions().count())
+            .sum();
+
+        let mut cuts: Vec<usize> = self
+            .shards
+            .iter()
+            .scan(0, |start, shard| {
+                *start += shard.instructions().count();
+                Some(*start)
+            })
+            .filter(|&cut| cut != 0 && cut != total_instructions)
+            .collect();
+        cuts.dedup();
+        cuts
+    }
+
+    /// Groups the shards into classes of byte-identical ones, so that the
+    /// work done for a shard can be reused for all shards of its class
+    pub fn shard_classes(&self) -> ShardClasses {
+        ShardClasses::new(&self.shards)
+    }
+
     /// Returns the total size of the states (stack + altstack)
     pub fn total_states_size(&self) -> usize {
         self.intermediate_states
@@ -101,30 +147,122 @@ impl SplitResult {
     /// The complexity index is the approximate worst number of opcodes
     /// it takes to form the disprove script.
     pub fn complexity_index(&self) -> usize {
-        let mut resultant_complexity = 0;
-
-        for i in 0..self.len() {
-            // Calculating sizes of the shards and states.
-            // Namely, since z[i] = f[i](z[i-1]), we need to calculate
-            // the size (|z[i]| + |z[i-1]|) * STACK_SIZE_INDEX + |f[i]|
-            resultant_complexity = resultant_complexity.max({
-                let shard_size = self.shards[i].len();
-                let current_state_size = self.intermediate_states[i].size();
-                let previous_state_size = if i > 0 {
-                    self.intermediate_states[i - 1].size()
-                } else {
-                    0
-                };
-
-                shard_size + (current_state_size + previous_state_size) * STACK_SIZE_INDEX
+        self.cost(&MaxDisproveCost::default())
+    }
+
+    /// Returns the cost of the script splitting according to the given metric
+    pub fn cost(&self, metric: &impl SplitMetric) -> usize {
+        metric.evaluate(self)
+    }
+
+    /// Verifies that the split of the `script` executed with the `input` is sound:
+    /// 1. The concatenated shards equal the script byte for byte
+    /// 2. Each intermediate state is the result of executing the shard on the previous state
+    /// 3. The last state equals the state given by the `expected_output`
+    ///
+    /// Each shard is executed separately with the previous state injected, so the check
+    /// does not rely on the way the states were formed. The first failure is returned.
+    pub fn verify(
+        &self,
+        input: &Script,
+        script: &Script,
+        expected_output: &Script,
+    ) -> Result<(), SplitVerificationError> {
+        self.verify_with_inputs(script, expected_output, |shard_id| match shard_id {
+            0 => input.clone(),
+            _ => self.intermediate_states[shard_id - 1].inject_script(),
+        })
+    }
+
+    /// Same as [`SplitResult::verify`], but each shard is executed with the input
+    /// given by `shard_input` for its index
+    pub(super) fn verify_with_inputs(
+        &self,
+        script: &Script,
+        expected_output: &Script,
+        shard_input: impl Fn(usize) -> Script,
+    ) -> Result<(), SplitVerificationError> {
+        if self.shards.len() != self.intermediate_states.len() {
+            return Err(SplitVerificationError::LengthMismatch {
+                shards: self.shards.len(),
+                states: self.intermediate_states.len(),
             });
         }
 
-        resultant_complexity
+        // Checking the shards against the script
+        let concatenated_shards: Vec<u8> = self
+            .shards
+            .iter()
+            .flat_map(|shard| shard.as_bytes().iter().copied())
+            .collect();
+        if concatenated_shards != script.as_bytes() {
+            let position = concatenated_shards
+                .iter()
+                .zip(script.as_bytes())
+                .position(|(left, right)| left != right)
+                .unwrap_or(concatenated_shards.len().min(script.len()));
+            return Err(SplitVerificationError::ScriptMismatch { position });
+        }
+
+        // Checking each state by executing the shard on the previous one
+        for (shard_id, (shard, state)) in self
+            .shards
+            .iter()
+            .zip(self.intermediate_states.iter())
+            .enumerate()
+        {
+            let expected_state =
+                IntermediateState::from_input_script(&shard_input(shard_id), shard);
+            if *state != expected_state {
+                return Err(SplitVerificationError::StateMismatch { shard_id });
+            }
+        }
+
+        // Finally, checking the output
+        let expected_state = IntermediateState::from_inject_script(expected_output);
+        if self.intermediate_states.last() != Some(&expected_state) {
+            return Err(SplitVerificationError::OutputMismatch);
+        }
+
+        Ok(())
     }
 }
 
-/// Trait that any script that can be split should implement
+/// Checks that the script executed with the input gives the output of the given size
+pub(super) fn verify_output(
+    input: Script,
+    script: Script,
+    output: Script,
+    output_size: usize,
+) -> bool {
+    let script = script! {
+        { input }
+        { script }
+        { output }
+
+        // Now, we need to verify that the output is correct.
+        // Since the output is not necessarily a single element, we check
+        // elements one by one
+        for i in (0..output_size).rev() {
+            // { <a_1> <a_2> ... <a_n> <b_1> <b_2> ... <b_n> } <- we need to push element <a_n> to the top of the stack
+            { i+1 } OP_ROLL
+            OP_EQUALVERIFY
+        }
+
+        // If everything was verified correctly, we return true to mark the script as successful
+        OP_TRUE
+    };
+
+    execute_script(script).success
+}
+
+/// Trait that any script that can be split should implement.
+///
+/// The intermediate states of the split are signed word by word, and the disprove script
+/// restores their elements from the words. Without `OP_CAT` only the script numbers can be
+/// restored, so the transitions over the states holding other wide elements (for instance,
+/// 20- or 32-byte hashes) cannot be disproved: building their disprove scripts returns
+/// [`EncodingError::UnrestorableElement`](super::error::EncodingError::UnrestorableElement).
 pub trait SplitableScript<const INPUT_SIZE: usize, const OUTPUT_SIZE: usize> {
     const INPUT_SIZE: usize = INPUT_SIZE;
     const OUTPUT_SIZE: usize = OUTPUT_SIZE;
@@ -132,6 +270,12 @@ pub trait SplitableScript<const INPUT_SIZE: usize, const OUTPUT_SIZE: usize> {
     /// Returns the main logic (f) of the script
     fn script() -> Script;
 
+    /// Returns the script together with its split hints (see [`super::hints`]),
+    /// which the splitters follow. By default, the script has no hints.
+    fn annotated_script() -> AnnotatedScript {
+        Self::script().into()
+    }
+
     /// Generates a random valid input for the script
     fn generate_valid_io_pair() -> IOPair<INPUT_SIZE, OUTPUT_SIZE>;
 
@@ -143,25 +287,7 @@ pub trait SplitableScript<const INPUT_SIZE: usize, const OUTPUT_SIZE: usize> {
 
     /// Verifies that the input is valid for the script
     fn verify(input: Script, output: Script) -> bool {
-        let script = script! {
-            { input }
-            { Self::script() }
-            { output }
-
-            // Now, we need to verify that the output is correct.
-            // Since the output is not necessarily a single element, we check
-            // elements one by one
-            for i in (0..OUTPUT_SIZE).rev() {
-                // { <a_1> <a_2> ... <a_n> <b_1> <b_2> ... <b_n> } <- we need to push element <a_n> to the top of the stack
-                { i+1 } OP_ROLL
-                OP_EQUALVERIFY
-            }
-
-            // If everything was verified correctly, we return true to mark the script as successful
-            OP_TRUE
-        };
-
-        execute_script(script).success
+        verify_output(input, Self::script(), output, OUTPUT_SIZE)
     }
 
     /// Verifies that the input is valid for the script with random input and output
@@ -172,16 +298,56 @@ pub trait SplitableScript<const INPUT_SIZE: usize, const OUTPUT_SIZE: usize> {
 
     /// Splits the script into smaller parts
     fn default_split(input: Script, split_type: SplitType) -> SplitResult {
-        default_split(input, Self::script(), split_type)
+        default_split(input, Self::annotated_script(), split_type)
     }
 
     /// Splits the script into smaller parts with the specified chunk size
     fn split(input: Script, split_type: SplitType, chunk_size: usize) -> SplitResult {
-        naive_split(input, Self::script(), split_type, chunk_size)
+        naive_split(input, Self::annotated_script(), split_type, chunk_size)
+    }
+
+    /// Splits the script into smaller parts with the fuzzy split, reporting
+    /// the progress of the search to the given observer
+    fn fuzzy_split(
+        input: Script,
+        split_type: SplitType,
+        metric: &impl SplitMetric,
+        observer: &mut impl SplitObserver,
+    ) -> Result<SplitResult, SplitError> {
+        fuzzy_split(
+            input,
+            Self::annotated_script(),
+            split_type,
+            metric,
+            observer,
+        )
+    }
+
+    /// Splits the script into smaller parts with the optimal split, which
+    /// searches over all possible cuts for the split with the lowest cost
+    fn optimal_split(input: Script, metric: &impl SplitMetric) -> SplitResult {
+        optimal_split(input, Self::annotated_script(), metric)
+    }
+
+    /// Splits the script once for a random valid input and replays the same shards
+    /// on `samples` fresh valid inputs, reporting the inputs on which the intermediate
+    /// states change their shapes or the split gives the wrong output
+    fn check_robustness(split_type: SplitType, samples: usize) -> RobustnessReport {
+        let IOPair { input, output: _ } = Self::generate_valid_io_pair();
+        let split_result = Self::default_split(input, split_type);
+
+        check_split_robustness(
+            &split_result,
+            (0..samples).map(|_| Self::generate_valid_io_pair()),
+        )
     }
 
-    /// Splits the script into smaller parts with the fuzzy split
-    fn fuzzy_split(input: Script, split_type: SplitType) -> SplitResult {
-        fuzzy_split(input, Self::script(), split_type)
+    /// Returns the script as the [`SplitProgram`] trait object, so it can be
+    /// used together with the programs of other sizes
+    fn program() -> Box<dyn SplitProgram>
+    where
+        Self: Sized + 'static,
+    {
+        Box::new(ScriptProgram::<Self, INPUT_SIZE, OUTPUT_SIZE>::default())
     }
 }
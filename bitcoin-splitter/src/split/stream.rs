@@ -0,0 +1,290 @@
+//! Module containing the streaming splitter.
+//!
+//! Scripts of the real programs take tens of megabytes, while [`naive_split`](super::core::naive_split)
+//! keeps every shard and intermediate state in memory. [`Shards`] cuts the script lazily, reading
+//! the instructions one by one and checking the hints (see [`super::hints`]) on the fly, and
+//! [`ShardStream`] executes each shard as soon as it is cut. Thus, only the current shard and
+//! the state before it are kept in memory, and [`split_to_directory`] writes them to disk
+//! right away.
+
+use std::{fs, path::Path};
+
+use bitcoin::script::{Instruction, Instructions};
+use bitcoin_utils::{debug::script_exec_with_stack, treepp::*};
+
+use super::{
+    artifact::ShardArtifact,
+    conditional::{ConditionError, ConditionTracker},
+    core::{SplitType, MAX_SCRIPT_SIZE},
+    error::{SplitError, StreamError},
+    hints::{AnnotatedScript, SplitHints},
+    intermediate_state::IntermediateState,
+};
+
+/// Iterator over the shards of the script, giving the same shards as
+/// [`try_split_into_shards`](super::core::try_split_into_shards) one at a time.
+/// Once an error is returned, the iteration stops.
+pub struct Shards<'a> {
+    instructions: Instructions<'a>,
+    chunk_size: usize,
+    split_type: SplitType,
+    /// Hints of the script, checked before the iteration
+    hints: SplitHints,
+    /// Nesting of the conditional blocks, the shard is continued until all of them are closed
+    conditions: ConditionTracker,
+    /// Index of the next instruction, that is, the boundary before it
+    boundary: usize,
+    /// Whether the current shard is big enough to be closed before the next instruction
+    pending_cut: bool,
+    shard_id: usize,
+    current_shard: Script,
+    current_shard_weight: usize,
+    /// Whether the empty shard is still to be returned after the last one
+    trailing_shard: bool,
+    finished: bool,
+}
+
+impl<'a> Shards<'a> {
+    /// Creates the iterator over the shards of the script. Since [`SplitType::ByStackSize`]
+    /// needs to execute the script, it is not supported.
+    pub fn new(
+        script: &'a AnnotatedScript,
+        chunk_size: usize,
+        split_type: SplitType,
+    ) -> Result<Self, SplitError> {
+        if matches!(split_type, SplitType::ByStackSize { .. }) {
+            return Err(SplitError::InputRequired);
+        }
+
+        Ok(Self {
+            instructions: script.script().instructions(),
+            chunk_size,
+            split_type,
+            hints: script.checked_hints()?,
+            conditions: ConditionTracker::new(),
+            boundary: 0,
+            pending_cut: false,
+            shard_id: 0,
+            current_shard: Script::new(),
+            current_shard_weight: 0,
+            trailing_shard: false,
+            finished: false,
+        })
+    }
+
+    /// Stops the iteration with the given error
+    fn fail(&mut self, error: SplitError) -> Option<Result<Script, SplitError>> {
+        self.finished = true;
+        Some(Err(error))
+    }
+
+    /// Closes the current shard, returning it
+    fn close_shard(&mut self) -> Script {
+        self.shard_id += 1;
+        self.current_shard_weight = 0;
+        std::mem::take(&mut self.current_shard)
+    }
+
+    /// Pushes the instruction to the current shard and checks whether
+    /// the shard can be closed after it
+    fn push(&mut self, instruction: Instruction) -> Result<(), SplitError> {
+        let position = self.current_shard.len();
+        self.current_shard.push_instruction(instruction);
+
+        self.conditions
+            .step(&instruction)
+            .map_err(|error| SplitError::MalformedShard {
+                shard_id: self.shard_id,
+                position,
+                error,
+            })?;
+
+        // Instructions are counted over the whole script, so with the shard continued
+        // past the chunk size (for example, to close the conditional block), the next cut
+        // is considered only at the next multiple of the chunk size
+        let current_shard_size = match &self.split_type {
+            SplitType::ByInstructions => self.boundary % self.chunk_size + 1,
+            SplitType::ByBytes => self.current_shard.len(),
+            SplitType::ByStackSize { .. } => unreachable!("checked in the constructor"),
+            SplitType::ByWeight(weights) => {
+                self.current_shard_weight += weights.instruction_weight(&instruction);
+                self.current_shard_weight
+            }
+        };
+
+        // Weights are not related to the size in bytes, so for them we check the script length
+        let current_script_size = match &self.split_type {
+            SplitType::ByWeight(_) => self.current_shard.len(),
+            _ => current_shard_size,
+        };
+
+        if current_script_size > MAX_SCRIPT_SIZE {
+            return Err(SplitError::ShardTooLarge {
+                shard_id: self.shard_id,
+                size: current_script_size,
+            });
+        }
+
+        self.pending_cut = current_shard_size >= self.chunk_size && self.conditions.is_balanced();
+        self.boundary += 1;
+
+        Ok(())
+    }
+}
+
+impl Iterator for Shards<'_> {
+    type Item = Result<Script, SplitError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.trailing_shard {
+            self.trailing_shard = false;
+            return Some(Ok(Script::new()));
+        }
+        if self.finished {
+            return None;
+        }
+
+        loop {
+            let instruction = match self.instructions.next() {
+                Some(Ok(instruction)) => instruction,
+                Some(Err(error)) => return self.fail(error.into()),
+                None => {
+                    self.finished = true;
+
+                    // The last shard must close all the blocks as well
+                    if !self.conditions.is_balanced() {
+                        let error = SplitError::MalformedShard {
+                            shard_id: self.shard_id,
+                            position: self.current_shard.len(),
+                            error: ConditionError::UnclosedBlock,
+                        };
+                        return self.fail(error);
+                    }
+
+                    // Preferred cut points never include the end of the script
+                    self.trailing_shard = self.pending_cut && self.hints.preferred.is_empty();
+                    return Some(Ok(self.close_shard()));
+                }
+            };
+
+            let cut = self.pending_cut && self.hints.allows_cut(self.boundary);
+            let shard = cut.then(|| self.close_shard());
+
+            if let Err(error) = self.push(instruction) {
+                return self.fail(error);
+            }
+            if let Some(shard) = shard {
+                return Some(Ok(shard));
+            }
+        }
+    }
+}
+
+/// Shard cut by the [`ShardStream`] together with the state after it
+#[derive(Debug, Clone)]
+pub struct StreamedShard {
+    /// Index of the shard in the split
+    pub shard_id: usize,
+    /// Shard script
+    pub shard: Script,
+    /// State after the execution of the shard
+    pub state: IntermediateState,
+}
+
+/// Iterator over the shards of the script together with the intermediate states,
+/// giving the same split as [`try_naive_split`](super::core::try_naive_split) one shard
+/// at a time. Similarly to [`form_states_incrementally`](super::core::form_states_incrementally),
+/// if some shard fails, the state after it is the stack at the moment of the failure.
+pub struct ShardStream<'a> {
+    shards: Shards<'a>,
+    shard_id: usize,
+    state: IntermediateState,
+}
+
+impl<'a> ShardStream<'a> {
+    /// Creates the stream of the shards of the script executed on the given input.
+    /// Since [`SplitType::ByStackSize`] needs the profile of the whole script, it is not supported.
+    pub fn new(
+        input: &Script,
+        script: &'a AnnotatedScript,
+        split_type: SplitType,
+        chunk_size: usize,
+    ) -> Result<Self, SplitError> {
+        if matches!(split_type, SplitType::ByStackSize { .. }) {
+            return Err(SplitError::UnsupportedSplitType);
+        }
+
+        Ok(Self {
+            shards: Shards::new(script, chunk_size, split_type)?,
+            shard_id: 0,
+            state: IntermediateState::from_inject_script(input),
+        })
+    }
+}
+
+impl Iterator for ShardStream<'_> {
+    type Item = Result<StreamedShard, SplitError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let shard = match self.shards.next()? {
+            Ok(shard) => shard,
+            Err(error) => return Some(Err(error)),
+        };
+
+        let mut exec = script_exec_with_stack(
+            shard.clone(),
+            self.state.stack.clone(),
+            self.state.altstack.clone(),
+        );
+        while exec.exec_next().is_ok() {}
+        self.state = IntermediateState {
+            stack: exec.stack().clone(),
+            altstack: exec.altstack().clone(),
+        };
+
+        let shard_id = self.shard_id;
+        self.shard_id += 1;
+
+        Some(Ok(StreamedShard {
+            shard_id,
+            shard,
+            state: self.state.clone(),
+        }))
+    }
+}
+
+/// Splits the script executed on the given input, writing each shard together with
+/// the state after it to `shard_XXXX.json` (see [`ShardArtifact`]) in the given directory
+/// as soon as it is cut. Returns the number of written shards.
+pub fn split_to_directory(
+    input: &Script,
+    script: &AnnotatedScript,
+    split_type: SplitType,
+    chunk_size: usize,
+    directory: impl AsRef<Path>,
+) -> Result<usize, StreamError> {
+    let directory = directory.as_ref();
+    fs::create_dir_all(directory)?;
+
+    let mut shards_number = 0;
+    for streamed_shard in ShardStream::new(input, script, split_type, chunk_size)? {
+        let StreamedShard {
+            shard_id,
+            shard,
+            state,
+        } = streamed_shard?;
+
+        fs::write(
+            directory.join(shard_file_name(shard_id)),
+            ShardArtifact::new(&shard, &state).to_json(),
+        )?;
+        shards_number += 1;
+    }
+
+    Ok(shards_number)
+}
+
+/// Returns the name of the file with the given shard written by [`split_to_directory`]
+pub fn shard_file_name(shard_id: usize) -> String {
+    format!("shard_{:04}.json", shard_id)
+}
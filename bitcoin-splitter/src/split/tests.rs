@@ -1,6 +1,43 @@
-use super::{core::split_into_shards, intermediate_state::IntermediateState};
+use std::sync::Arc;
+
+use super::{
+    artifact::{ArtifactError, ArtifactFormat, ShardArtifact, SplitArtifact, SplitParams},
+    checkpoints::{resolve_checkpoints, split_at_checkpoints, Checkpoint, CheckpointFallback},
+    compose::ComposedProgram,
+    conditional::{validate_shard, validate_shards, ConditionError},
+    constraints::{constrained_split, DisproveModel, SplitConstraints},
+    core::{
+        form_states_from_shards, form_states_incrementally, fuzzy_split, naive_split,
+        split_at_instructions, split_into_shards, try_naive_split, try_split_at_instructions,
+        try_split_into_shards, MAX_SCRIPT_SIZE,
+    },
+    encoding::{
+        decode_element, decode_element_script, decode_elements, encode_element, encode_elements,
+        read_script_number, NEGATIVE_FLAG, TAG_FLAG,
+    },
+    error::{
+        CheckpointError, CompositionError, Constraint, ConstraintError, EncodingError, HintError,
+        SplitError, SplitVerificationError,
+    },
+    hinted::{hinted_split, HintedScript},
+    hints::{atomic, split_here, AnnotatedScript, SplitHints},
+    intermediate_state::IntermediateState,
+    lift::lift_conditionals,
+    metric::{AverageDisproveCost, MaxDisproveCost, SplitMetric},
+    observer::{SilentObserver, SplitObserver},
+    optimal::{optimal_split, static_optimal_split, try_optimal_split, try_static_optimal_split},
+    periodic::{periodic_split, with_loop_hints, LoopStructure, PeriodicRegion},
+    profile::ScriptProfile,
+    program::{ProgramIOPair, ProgramRegistry, SplitProgram},
+    report::SplitReport,
+    robustness::{check_split_robustness, SplitDivergence, StateShape},
+    script::{IOPair, SplitResult},
+    stream::{shard_file_name, split_to_directory, ShardStream},
+    weights::OpcodeWeights,
+};
 use crate::split::core::SplitType;
-use bitcoin_utils::{stack_to_script, treepp::*};
+use bitcoin::{opcodes::all::OP_ADD, script::scriptint_vec};
+use bitcoin_utils::{debug::execute_script_without_op_cat, stack_to_script, treepp::*};
 
 /// Tests whether splitting the script into subprograms (shards)
 /// works properly for the most basic script
@@ -275,3 +312,1855 @@ fn test_state_from_state() {
         "z3 altstack should be empty at this point"
     );
 }
+
+#[test]
+fn test_split_at_instructions() {
+    let script = script! {
+        OP_1 OP_2 OP_ADD OP_3 OP_ADD OP_6 OP_EQUAL
+    };
+
+    let shards = split_at_instructions(&script, &[2, 5]);
+    assert_eq!(shards.len(), 3, "there should be three shards");
+    assert_eq!(shards[0], script! { OP_1 OP_2 });
+    assert_eq!(shards[1], script! { OP_ADD OP_3 OP_ADD });
+    assert_eq!(shards[2], script! { OP_6 OP_EQUAL });
+
+    for cuts in [&[0][..], &[2, 2], &[5, 2], &[2, 100]] {
+        assert!(matches!(
+            try_split_at_instructions(&script, cuts),
+            Err(SplitError::InvalidCut { .. })
+        ));
+    }
+}
+
+#[test]
+fn test_optimal_split_basic() {
+    let input_script = script! {
+        { 10 } { 20 } { 30 }
+    };
+    let main_script = script! {
+        OP_ADD OP_ADD
+        OP_1 OP_TOALTSTACK OP_2 OP_TOALTSTACK OP_3 OP_TOALTSTACK
+        OP_FROMALTSTACK OP_FROMALTSTACK OP_FROMALTSTACK
+        OP_ADD OP_ADD OP_ADD
+        OP_DUP { 66 } OP_EQUAL
+        OP_IF
+            OP_DROP OP_TRUE
+        OP_ELSE
+            OP_DROP OP_FALSE
+        OP_ENDIF
+    };
+
+    let optimal_result = optimal_split(
+        input_script.clone(),
+        main_script.clone(),
+        &MaxDisproveCost::default(),
+    );
+
+    // The optimal split cannot be worse than any naive one
+    for chunk_size in 1..10 {
+        let naive_result = naive_split(
+            input_script.clone(),
+            main_script.clone(),
+            SplitType::ByInstructions,
+            chunk_size,
+        );
+        assert!(
+            optimal_result.complexity_index() <= naive_result.complexity_index(),
+            "optimal split is worse than the naive one with chunk size {}",
+            chunk_size
+        );
+    }
+
+    // Concatenating the shards must give the original script
+    let concatenated_script = script! {
+        for shard in optimal_result.shards.iter() {
+            { shard.clone() }
+        }
+    };
+    assert_eq!(
+        concatenated_script, main_script,
+        "shards do not form the script"
+    );
+
+    // The last state must be the result of the script
+    let verify_script = script! {
+        { stack_to_script(&optimal_result.must_last_state().stack) }
+    };
+    assert!(
+        execute_script(verify_script).success,
+        "last state is incorrect"
+    );
+}
+
+/// Tests that the optimal split for the average metric is the exact optimum,
+/// comparing it with every possible split of the short script
+#[test]
+fn test_optimal_split_average() {
+    const ALPHA: usize = 3;
+
+    let input_script = script! {
+        { 1 } { 2 } { 3 } { 4 }
+    };
+    let main_script = script! {
+        OP_ADD OP_ADD OP_ADD
+        OP_DUP OP_DUP OP_DUP OP_ADD OP_ADD OP_ADD
+        { 40 } OP_EQUAL
+    };
+    let output_script = script! { OP_TRUE };
+
+    let metric = AverageDisproveCost::new(ALPHA);
+    let instructions_number = main_script.instructions().count();
+    let best_cost = (0..1usize << (instructions_number - 1))
+        .map(|mask| {
+            let cuts: Vec<usize> = (1..instructions_number)
+                .filter(|cut| mask & (1 << (cut - 1)) != 0)
+                .collect();
+            let shards = split_at_instructions(&main_script, &cuts);
+            let intermediate_states = form_states_incrementally(&shards, input_script.clone());
+            metric.evaluate(&SplitResult::new(shards, intermediate_states))
+        })
+        .min()
+        .unwrap();
+
+    let split_result = optimal_split(input_script.clone(), main_script.clone(), &metric);
+    assert_eq!(metric.evaluate(&split_result), best_cost);
+    assert_eq!(
+        split_result.verify(&input_script, &main_script, &output_script),
+        Ok(())
+    );
+
+    // The split optimal for the worst case cannot be better on average
+    let max_result = optimal_split(input_script, main_script, &MaxDisproveCost::new(ALPHA));
+    assert!(metric.evaluate(&max_result) >= best_cost);
+}
+
+#[test]
+fn test_optimal_split_errors() {
+    let input_script = script! { OP_1 };
+    let metric = MaxDisproveCost::default();
+
+    // The conditional block cannot be cut, but it does not fit into a single shard
+    let main_script = script! {
+        OP_IF
+            for _ in 0..MAX_SCRIPT_SIZE {
+                OP_NOP
+            }
+        OP_ENDIF
+    };
+    assert_eq!(
+        try_optimal_split(input_script.clone(), main_script.clone(), &metric).err(),
+        Some(SplitError::NoValidSplit)
+    );
+    assert_eq!(
+        try_static_optimal_split(input_script.clone(), main_script, &metric).err(),
+        Some(SplitError::NoValidSplit)
+    );
+
+    let main_script = script! { OP_IF OP_1 };
+    assert!(matches!(
+        try_optimal_split(input_script.clone(), main_script, &metric),
+        Err(SplitError::MalformedShard {
+            error: ConditionError::UnclosedBlock,
+            ..
+        })
+    ));
+
+    let main_script = AnnotatedScript::new(
+        script! { OP_1ADD OP_1ADD },
+        SplitHints {
+            preferred: vec![4],
+            atomic: vec![],
+        },
+    );
+    assert_eq!(
+        try_optimal_split(input_script.clone(), main_script, &metric).err(),
+        Some(SplitError::InvalidHints { boundary: 4 })
+    );
+
+    let main_script = script! { OP_1ADD OP_0 OP_VERIFY };
+    assert!(matches!(
+        try_optimal_split(input_script, main_script, &metric),
+        Err(SplitError::ExecutionFailed { .. })
+    ));
+}
+
+#[test]
+fn test_split_metrics() {
+    const ALPHA: usize = 10;
+
+    let input_script = script! {
+        { 10 } { 20 } { 30 }
+    };
+    let main_script = script! {
+        OP_ADD OP_ADD { 60 } OP_EQUAL
+    };
+
+    let split_result = naive_split(input_script, main_script, SplitType::ByInstructions, 3);
+    assert_eq!(split_result.len(), 2, "there should be two shards");
+
+    // Shard costs are |f[i]| + alpha * (|z[i-1]| + |z[i]|), while the
+    // state before the first shard is not counted. The first shard
+    // leaves { 60, 60 } on the stack, while the second one leaves { 1 }.
+    let first_cost = split_result.shards[0].len() + ALPHA * 2;
+    let second_cost = split_result.shards[1].len() + ALPHA * (2 + 1);
+
+    assert_eq!(
+        MaxDisproveCost::new(ALPHA).evaluate(&split_result),
+        first_cost.max(second_cost)
+    );
+    assert_eq!(
+        AverageDisproveCost::new(ALPHA).evaluate(&split_result),
+        (first_cost + second_cost) / 2
+    );
+    assert_eq!(
+        split_result.complexity_index(),
+        split_result.cost(&MaxDisproveCost::default())
+    );
+}
+
+#[test]
+fn test_incremental_states() {
+    const CHUNK_SIZE: usize = 3;
+
+    let input_script = script! {
+        { 10 } { 20 }
+    };
+    let main_script = script! {
+        OP_1 OP_TOALTSTACK OP_1 OP_TOALTSTACK OP_0 OP_TOALTSTACK
+        OP_FROMALTSTACK OP_FROMALTSTACK OP_FROMALTSTACK
+        OP_ADD OP_ADD OP_ADD
+        OP_DUP { 32 } OP_EQUAL
+        OP_IF
+            { 2 } OP_ADD
+        OP_ENDIF
+    };
+
+    let shards = split_into_shards(&main_script, CHUNK_SIZE, SplitType::ByInstructions);
+    assert_eq!(
+        form_states_incrementally(&shards, input_script.clone()),
+        form_states_from_shards(shards, input_script),
+        "incremental states differ from the reinjected ones"
+    );
+}
+
+#[test]
+fn test_incremental_states_with_failure() {
+    let input_script = script! {
+        { 10 } { 20 }
+    };
+    let shards = vec![
+        script! { OP_ADD OP_5 },
+        // This shard fails on OP_VERIFY, so its state is the stack at the moment of failure
+        script! { OP_0 OP_VERIFY OP_DROP OP_DROP },
+        script! { OP_DROP OP_3 },
+    ];
+
+    let incremental_states = form_states_incrementally(&shards, input_script.clone());
+    assert_eq!(
+        incremental_states,
+        form_states_from_shards(shards, input_script),
+        "incremental states differ from the reinjected ones"
+    );
+    // OP_VERIFY leaves { 30, 5, 0 } on failure, so the last shard gives { 30, 5, 3 }
+    assert_eq!(incremental_states[2].stack.len(), 3);
+}
+
+/// Observer that records the calls and cancels the search after the given number of candidates
+#[derive(Default)]
+struct RecordingObserver {
+    total: usize,
+    processed: usize,
+    failed: usize,
+    best_costs: Vec<usize>,
+    cancel_after: Option<usize>,
+    finished: bool,
+}
+
+impl SplitObserver for RecordingObserver {
+    fn on_start(&mut self, total: usize) {
+        self.total = total;
+    }
+
+    fn on_progress(&mut self, processed: usize, _total: usize) {
+        self.processed = processed;
+    }
+
+    fn on_candidate_failed(&mut self, _chunk_size: usize, _error: &SplitError) {
+        self.failed += 1;
+    }
+
+    fn on_new_best(&mut self, _chunk_size: usize, cost: usize) {
+        self.best_costs.push(cost);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel_after
+            .is_some_and(|cancel_after| self.processed >= cancel_after)
+    }
+
+    fn on_finish(&mut self) {
+        self.finished = true;
+    }
+}
+
+#[test]
+fn test_fuzzy_split_observer() {
+    let input_script = script! {
+        { 10 } { 20 } { 30 }
+    };
+    let main_script = script! {
+        OP_ADD OP_ADD { 60 } OP_EQUAL
+    };
+
+    let mut observer = RecordingObserver::default();
+    let split_result = fuzzy_split(
+        input_script.clone(),
+        main_script.clone(),
+        SplitType::ByInstructions,
+        &MaxDisproveCost::default(),
+        &mut observer,
+    )
+    .expect("fuzzy split should succeed");
+
+    assert!(
+        observer.finished,
+        "observer was not notified about the finish"
+    );
+    assert_eq!(
+        observer.processed, observer.total,
+        "not all candidates were processed"
+    );
+    assert_eq!(observer.failed, 0, "no candidate should fail");
+    assert!(
+        observer
+            .best_costs
+            .windows(2)
+            .all(|costs| costs[0] >= costs[1]),
+        "best costs should not increase"
+    );
+
+    // Every chunk size covers the whole script, so the result is a single shard
+    let expected = naive_split(input_script, main_script, SplitType::ByInstructions, 100);
+    assert_eq!(split_result.shards, expected.shards);
+    assert_eq!(
+        split_result.intermediate_states,
+        expected.intermediate_states
+    );
+    assert_eq!(
+        observer.best_costs.last().copied(),
+        Some(MaxDisproveCost::default().evaluate(&split_result))
+    );
+}
+
+#[test]
+fn test_fuzzy_split_failing_candidates() {
+    let input_script = script! {
+        { 10 } { 20 }
+    };
+    // The last shard of every candidate fails on OP_VERIFY
+    let main_script = script! {
+        OP_ADD { 31 } OP_EQUALVERIFY
+    };
+
+    assert_eq!(
+        try_naive_split(
+            input_script.clone(),
+            main_script.clone(),
+            SplitType::ByInstructions,
+            2
+        )
+        .err(),
+        Some(SplitError::ExecutionFailed { shard_id: 1 })
+    );
+
+    let mut observer = RecordingObserver::default();
+    let result = fuzzy_split(
+        input_script,
+        main_script,
+        SplitType::ByInstructions,
+        &MaxDisproveCost::default(),
+        &mut observer,
+    );
+
+    assert_eq!(result.err(), Some(SplitError::NoValidSplit));
+    assert_eq!(observer.failed, observer.total);
+    assert_eq!(observer.processed, observer.total);
+    assert!(observer.finished);
+}
+
+#[test]
+fn test_fuzzy_split_cancellation() {
+    let input_script = script! {
+        { 10 } { 20 } { 30 }
+    };
+    let main_script = script! {
+        OP_ADD OP_ADD { 60 } OP_EQUAL
+    };
+
+    let mut observer = RecordingObserver {
+        cancel_after: Some(5),
+        ..Default::default()
+    };
+    let split_result = fuzzy_split(
+        input_script,
+        main_script,
+        SplitType::ByInstructions,
+        &MaxDisproveCost::default(),
+        &mut observer,
+    );
+
+    // The best split found before the cancellation is returned
+    assert!(split_result.is_ok(), "best split so far should be returned");
+    assert_eq!(
+        observer.processed, 5,
+        "search should stop right after the cancellation"
+    );
+    assert!(observer.processed < observer.total);
+    assert!(observer.finished);
+}
+
+#[test]
+fn test_split_errors() {
+    // OP_PUSHDATA1 without the length byte
+    let corrupted_script = Script::from_bytes(vec![0x4c]);
+
+    let result = try_naive_split(
+        script! {},
+        corrupted_script.clone(),
+        SplitType::ByInstructions,
+        3,
+    );
+    assert!(matches!(result, Err(SplitError::InvalidScript(_))));
+
+    let result = fuzzy_split(
+        script! {},
+        corrupted_script,
+        SplitType::ByInstructions,
+        &MaxDisproveCost::default(),
+        &mut SilentObserver,
+    );
+    assert_eq!(result.err(), Some(SplitError::NoValidSplit));
+}
+
+#[test]
+fn test_stack_size_split() {
+    const CHUNK_SIZE: usize = 4;
+    const TOLERANCE: usize = 2;
+
+    let input_script = script! {
+        { 1 } { 2 } { 3 } { 4 }
+    };
+    // Every opcode takes a single byte. The state sizes at the boundaries are
+    // 4, 3, 2, 1, 2, 3, 4, 3, 2, 1, so within the window of 2..=6 bytes
+    // the smallest state is right after the third instruction.
+    let main_script = script! {
+        OP_ADD OP_ADD OP_ADD
+        OP_DUP OP_DUP OP_DUP
+        OP_DROP OP_DROP OP_DROP
+    };
+
+    let split_type = SplitType::ByStackSize {
+        tolerance: TOLERANCE,
+    };
+    let split_result = naive_split(input_script, main_script.clone(), split_type, CHUNK_SIZE);
+
+    assert_eq!(split_result.len(), 2, "there should be two shards");
+    assert_eq!(split_result.shards[0].len(), 3);
+    assert_eq!(split_result.shards[1].len(), 6);
+    assert_eq!(split_result.intermediate_states[0].size(), 1);
+    assert_eq!(split_result.must_last_state().size(), 1);
+
+    // The split type cannot be used without the input
+    assert_eq!(
+        try_split_into_shards(&main_script, CHUNK_SIZE, split_type),
+        Err(SplitError::InputRequired)
+    );
+}
+
+#[test]
+fn test_weight_split() {
+    const CHUNK_SIZE: usize = 6;
+
+    let input_script = script! {
+        { 1 }
+    };
+    let main_script = script! {
+        { 2 } OP_ADD { 3 } OP_ADD { 4 } OP_ADD { 10 } OP_EQUAL
+    };
+
+    // OP_ADD weighs as much as five other instructions, so each of the first three
+    // shards is closed right after OP_ADD, while the last shard stays light
+    let weights = OpcodeWeights::uniform(1).with_op(OP_ADD, 5);
+    assert_eq!(weights.op_weight(OP_ADD), 5);
+
+    let split_type = SplitType::ByWeight(Arc::new(weights));
+    let split_result = naive_split(input_script, main_script, split_type, CHUNK_SIZE);
+
+    let shard_lengths: Vec<usize> = split_result
+        .shards
+        .iter()
+        .map(|shard| shard.instructions().count())
+        .collect();
+    assert_eq!(shard_lengths, vec![2, 2, 2, 2]);
+    assert_eq!(
+        split_result.must_last_state().stack.len(),
+        1,
+        "the script should end with a single element"
+    );
+}
+
+#[test]
+fn test_split_hints() {
+    let mut main_script = AnnotatedScript::from(script! { OP_ADD });
+    main_script
+        .push(atomic(script! { OP_DUP OP_ADD OP_DUP }))
+        .push(script! { OP_ADD })
+        .push(split_here())
+        .push(script! { OP_DUP OP_ADD });
+
+    // The script itself is left intact
+    assert_eq!(
+        main_script.script(),
+        &script! { OP_ADD OP_DUP OP_ADD OP_DUP OP_ADD OP_DUP OP_ADD }
+    );
+    let hints = main_script.checked_hints().unwrap();
+    assert_eq!(hints.preferred, vec![5]);
+    assert_eq!(hints.atomic, vec![1..4]);
+
+    // Only the preferred cut point is allowed
+    assert!(!hints.allows_cut(1));
+    assert!(!hints.allows_cut(2));
+    assert!(hints.allows_cut(5));
+
+    // Even though every chunk is a single instruction, the script is cut only once
+    let shards = split_into_shards(&main_script, 1, SplitType::ByInstructions);
+    assert_eq!(
+        shards,
+        vec![
+            script! { OP_ADD OP_DUP OP_ADD OP_DUP OP_ADD },
+            script! { OP_DUP OP_ADD },
+        ]
+    );
+
+    // Opcodes which used to be the markers are the ordinary instructions now
+    let nop_script = script! { OP_1 OP_NOP8 OP_NOP9 OP_NOP10 OP_1 OP_ADD };
+    let shards = split_into_shards(&nop_script, 1, SplitType::ByInstructions);
+    assert_eq!(shards.len(), 6);
+    assert_eq!(shards[1], script! { OP_NOP8 });
+}
+
+#[test]
+fn test_atomic_hints() {
+    let mut main_script = AnnotatedScript::from(script! { OP_1 OP_ADD });
+    main_script
+        .push(atomic(script! { OP_DUP OP_ADD OP_DUP OP_ADD }))
+        .push(script! { OP_1 OP_ADD });
+
+    // Without preferred points, the script can be cut anywhere but inside the atomic region
+    let shards = split_into_shards(&main_script, 3, SplitType::ByInstructions);
+    assert_eq!(
+        shards,
+        vec![
+            script! { OP_1 OP_ADD OP_DUP OP_ADD OP_DUP OP_ADD },
+            script! { OP_1 OP_ADD },
+        ]
+    );
+
+    let input_script = script! { { 2 } };
+    let split_result = optimal_split(input_script, &main_script, &MaxDisproveCost::default());
+    let mut cut = 0;
+    for shard in &split_result.shards[..split_result.len() - 1] {
+        cut += shard.instructions().count();
+        assert!(
+            !(3..=5).contains(&cut),
+            "optimal split cuts the atomic region"
+        );
+    }
+
+    // Hints must lie inside the script
+    let mut invalid_script = AnnotatedScript::from(script! { OP_ADD });
+    invalid_script.push(atomic(script! { OP_DUP OP_ADD }));
+    let invalid_script = AnnotatedScript::new(
+        invalid_script.script().clone(),
+        SplitHints {
+            preferred: vec![4],
+            atomic: invalid_script.hints().atomic.clone(),
+        },
+    );
+    assert_eq!(
+        try_split_into_shards(&invalid_script, 1, SplitType::ByInstructions),
+        Err(SplitError::InvalidHints { boundary: 4 })
+    );
+}
+
+#[test]
+fn test_nested_conditionals_split() {
+    let main_script = script! {
+        OP_1
+        OP_IF
+            OP_1
+            OP_IF
+                OP_2
+            OP_ELSE
+                OP_3
+            OP_ENDIF
+        OP_ELSE
+            OP_4
+        OP_ENDIF
+        OP_5 OP_6
+    };
+
+    // The first shard continues until the outer block is closed,
+    // and then until the next multiple of the chunk size
+    let shards = split_into_shards(&main_script, 2, SplitType::ByInstructions);
+    assert_eq!(shards.len(), 2, "there should be two shards");
+    assert_eq!(shards[1], script! { OP_6 });
+    assert_eq!(validate_shards(&shards), Ok(()));
+
+    // Cutting inside the block gives the malformed shards
+    let shards = split_at_instructions(&main_script, &[3, 8]);
+    assert_eq!(
+        validate_shards(&shards),
+        Err(SplitError::MalformedShard {
+            shard_id: 0,
+            position: 3,
+            error: ConditionError::UnclosedBlock,
+        })
+    );
+    assert_eq!(validate_shard(&shards[1]), Ok(()));
+    assert_eq!(
+        validate_shard(&shards[2]),
+        Err((0, ConditionError::UnexpectedElse))
+    );
+}
+
+#[test]
+fn test_malformed_conditionals() {
+    let stray_else_script = script! {
+        OP_1 OP_ELSE OP_2
+    };
+    assert_eq!(
+        try_split_into_shards(&stray_else_script, 1, SplitType::ByInstructions),
+        Err(SplitError::MalformedShard {
+            shard_id: 1,
+            position: 0,
+            error: ConditionError::UnexpectedElse,
+        })
+    );
+
+    let unclosed_script = script! {
+        OP_1 OP_IF OP_2
+    };
+    assert_eq!(
+        try_split_into_shards(&unclosed_script, 1, SplitType::ByInstructions),
+        Err(SplitError::MalformedShard {
+            shard_id: 1,
+            position: 2,
+            error: ConditionError::UnclosedBlock,
+        })
+    );
+
+    assert_eq!(
+        validate_shard(&script! { OP_ENDIF OP_1 OP_IF }),
+        Err((0, ConditionError::UnexpectedEndif))
+    );
+}
+
+#[test]
+fn test_lift_conditionals() {
+    const SEGMENT_SIZE: usize = 10;
+
+    // Putting the second element to the altstack, so that the branches have to keep it
+    let main_script = script! {
+        OP_SWAP OP_TOALTSTACK
+        OP_IF
+            for _ in 0..20 {
+                OP_DUP OP_ADD
+            }
+        OP_ELSE
+            for _ in 0..10 {
+                OP_1ADD OP_TOALTSTACK OP_1 OP_FROMALTSTACK OP_ADD
+            }
+        OP_ENDIF
+    };
+    let lifted_script = lift_conditionals(&main_script, SEGMENT_SIZE).unwrap();
+    assert_ne!(lifted_script, main_script, "long block should be lifted");
+
+    for selector in [0, 1] {
+        let input_script = script! {
+            { 5 } { 3 } { selector }
+        };
+
+        // The lifted script gives the same result for both branches
+        assert_eq!(
+            IntermediateState::from_input_script(&input_script, &lifted_script),
+            IntermediateState::from_input_script(&input_script, &main_script),
+        );
+
+        // Now, the script can be cut inside the block, and the selector is
+        // carried on top of the altstack between the shards
+        let split_result = naive_split(
+            input_script.clone(),
+            lifted_script.clone(),
+            SplitType::ByBytes,
+            SEGMENT_SIZE,
+        );
+        assert!(split_result.len() > 2, "lifted block should be split");
+        assert!(split_result
+            .shards
+            .iter()
+            .all(|shard| shard.len() < main_script.len()));
+        assert_eq!(
+            split_result.must_last_state(),
+            &IntermediateState::from_input_script(&input_script, &main_script),
+        );
+    }
+
+    // Short blocks are left intact
+    assert_eq!(
+        lift_conditionals(&main_script, main_script.len()).unwrap(),
+        main_script
+    );
+
+    // The block popping the altstack below its initial size cannot be lifted
+    let unsafe_script = script! {
+        OP_IF
+            for _ in 0..20 {
+                OP_DUP OP_ADD
+            }
+            OP_FROMALTSTACK OP_ADD
+        OP_ENDIF
+    };
+    assert_eq!(
+        lift_conditionals(&unsafe_script, SEGMENT_SIZE).unwrap(),
+        unsafe_script
+    );
+}
+
+#[test]
+fn test_verify_split() {
+    let input_script = script! {
+        { 25 } { 30 }
+    };
+    let main_script = script! {
+        OP_ADD OP_TOALTSTACK { 5 } OP_FROMALTSTACK OP_ADD { 60 } OP_EQUAL
+    };
+    let output_script = script! { OP_TRUE };
+
+    let split_result = naive_split(
+        input_script.clone(),
+        main_script.clone(),
+        SplitType::ByInstructions,
+        2,
+    );
+    assert_eq!(
+        split_result.verify(&input_script, &main_script, &output_script),
+        Ok(())
+    );
+
+    // Wrong output
+    assert_eq!(
+        split_result.verify(&input_script, &main_script, &script! { OP_FALSE }),
+        Err(SplitVerificationError::OutputMismatch)
+    );
+
+    // Different script
+    let other_script = script! {
+        OP_ADD OP_TOALTSTACK { 6 } OP_FROMALTSTACK OP_ADD { 60 } OP_EQUAL
+    };
+    assert_eq!(
+        split_result.verify(&input_script, &other_script, &output_script),
+        Err(SplitVerificationError::ScriptMismatch { position: 2 })
+    );
+
+    // Tampered state
+    let mut tampered_result = naive_split(
+        input_script.clone(),
+        main_script.clone(),
+        SplitType::ByInstructions,
+        2,
+    );
+    tampered_result.intermediate_states[1] = tampered_result.intermediate_states[0].clone();
+    assert_eq!(
+        tampered_result.verify(&input_script, &main_script, &output_script),
+        Err(SplitVerificationError::StateMismatch { shard_id: 1 })
+    );
+
+    // Missing state
+    tampered_result.intermediate_states.pop();
+    assert_eq!(
+        tampered_result.verify(&input_script, &main_script, &output_script),
+        Err(SplitVerificationError::LengthMismatch {
+            shards: 4,
+            states: 3
+        })
+    );
+}
+
+#[test]
+fn test_split_artifact() {
+    let input_script = script! {
+        { 25 } { 30 }
+    };
+    let main_script = script! {
+        OP_ADD OP_TOALTSTACK { 100000 } OP_FROMALTSTACK OP_ADD { 100055 } OP_EQUAL
+    };
+
+    let split_result = naive_split(
+        input_script.clone(),
+        main_script.clone(),
+        SplitType::ByInstructions,
+        2,
+    );
+    let params = SplitParams {
+        splitter: "naive".to_string(),
+        split_type: Some(format!("{:?}", SplitType::ByInstructions)),
+        chunk_size: Some(2),
+    };
+    let artifact = SplitArtifact::new(&split_result, &main_script, params);
+    assert_eq!(artifact.check_script(&main_script), Ok(()));
+    assert_eq!(
+        artifact.check_script(&script! { OP_TRUE }),
+        Err(ArtifactError::ScriptHashMismatch)
+    );
+
+    // Both formats give the same artifact back
+    let json_artifact = SplitArtifact::from_json(&artifact.to_json()).unwrap();
+    let binary_artifact = SplitArtifact::from_bytes(&artifact.to_bytes().unwrap()).unwrap();
+    assert_eq!(json_artifact, artifact);
+    assert_eq!(binary_artifact, artifact);
+
+    // The rebuilt split is exactly the same
+    let loaded_result = binary_artifact.to_split_result(&main_script).unwrap();
+    assert_eq!(loaded_result.shards, split_result.shards);
+    assert_eq!(
+        loaded_result.intermediate_states,
+        split_result.intermediate_states
+    );
+    assert_eq!(
+        loaded_result.verify(&input_script, &main_script, &script! { OP_TRUE }),
+        Ok(())
+    );
+    assert_eq!(
+        binary_artifact.to_split_result(&script! { OP_TRUE }).err(),
+        Some(ArtifactError::ScriptHashMismatch)
+    );
+
+    // Saving and loading from the disk
+    for (format, extension) in [
+        (ArtifactFormat::Json, "json"),
+        (ArtifactFormat::Binary, "bin"),
+    ] {
+        let path = std::env::temp_dir().join(format!("split_artifact_test.{}", extension));
+        artifact.save(&path, format).unwrap();
+        assert_eq!(SplitArtifact::load(&path), Ok(artifact.clone()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    // Corrupted artifacts are rejected
+    let mut bytes = artifact.to_bytes().unwrap();
+    bytes.push(0);
+    assert_eq!(
+        SplitArtifact::from_bytes(&bytes),
+        Err(ArtifactError::TrailingBytes)
+    );
+    bytes.truncate(bytes.len() - 2);
+    assert_eq!(
+        SplitArtifact::from_bytes(&bytes),
+        Err(ArtifactError::UnexpectedEnd)
+    );
+    // The tag of the split type follows the magic, the version and the splitter name
+    let mut bytes = artifact.to_bytes().unwrap();
+    bytes[12 + "naive".len()] = 2;
+    assert_eq!(
+        SplitArtifact::from_bytes(&bytes),
+        Err(ArtifactError::InvalidTag(2))
+    );
+    let mut invalid_artifact = artifact.clone();
+    invalid_artifact.shards[0].script = "not hex".to_string();
+    assert_eq!(
+        SplitArtifact::from_json(&invalid_artifact.to_json()),
+        Err(ArtifactError::InvalidHex)
+    );
+    assert_eq!(invalid_artifact.to_bytes(), Err(ArtifactError::InvalidHex));
+    let mut oversized_artifact = artifact.clone();
+    oversized_artifact.params.chunk_size = Some(u32::MAX as usize + 1);
+    assert_eq!(
+        oversized_artifact.to_bytes(),
+        Err(ArtifactError::ValueOverflow)
+    );
+    let mut outdated_artifact = artifact.clone();
+    outdated_artifact.version += 1;
+    assert_eq!(
+        SplitArtifact::from_json(&outdated_artifact.to_json()),
+        Err(ArtifactError::UnsupportedVersion(outdated_artifact.version))
+    );
+
+    // Short shards are debugged without panicking
+    assert!(format!("{:?}", split_result).contains("Shard 0: OP_ADD OP_TOALTSTACK"));
+}
+
+#[test]
+fn test_split_report() {
+    const ALPHA: usize = 10;
+
+    let input_script = script! {
+        { 10 } { 20 } { 30 }
+    };
+    let main_script = script! {
+        OP_ADD OP_ADD { 60 } OP_EQUAL
+    };
+
+    let split_result = naive_split(
+        input_script.clone(),
+        main_script,
+        SplitType::ByInstructions,
+        3,
+    );
+    let metric = MaxDisproveCost::new(ALPHA);
+    let report = SplitReport::new(&input_script, &split_result, &metric);
+
+    // The first shard takes { 10, 20, 30 } to { 60, 60 }, while the second one leaves { 1 }
+    assert_eq!(report.shards.len(), 2);
+    let first_shard = &report.shards[0];
+    assert_eq!(first_shard.instructions, 3);
+    assert_eq!((first_shard.stack_before, first_shard.stack_after), (3, 2));
+    assert_eq!(first_shard.max_depth, 3);
+    assert_eq!(first_shard.cost, split_result.shards[0].len() + ALPHA * 2);
+    assert_eq!(report.shards[1].stack_after, 1);
+    assert_eq!(report.cost, split_result.cost(&metric));
+
+    // Rendering
+    let markdown = report.to_markdown();
+    assert_eq!(
+        markdown
+            .lines()
+            .filter(|line| line.starts_with("| "))
+            .count(),
+        3
+    );
+    assert!(markdown.contains("| 1 | 1 | 1 | 2 | 0 | 1 | 0 |"));
+
+    let csv = report.to_csv();
+    assert_eq!(csv.lines().count(), 3);
+    assert!(csv.lines().nth(2).unwrap().starts_with("1,1,1,2,0,1,0,"));
+
+    let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+    assert_eq!(json["shards"][0]["max_depth"], 3);
+}
+
+/// Tests that the elements of any width are encoded by the words below 2^31
+/// and are decoded back to the exact same bytes
+#[test]
+fn test_wide_elements_encoding() {
+    // Small non-negative numbers are committed as is
+    assert_eq!(encode_element(&[]), vec![0]);
+    assert_eq!(encode_element(&[0x05]), vec![5]);
+    assert_eq!(encode_element(&[0xff, 0x00]), vec![255]);
+
+    // Other elements are encoded by the header and the limbs
+    assert_eq!(encode_element(&[0x00]), vec![TAG_FLAG | 1, 0]);
+    assert_eq!(encode_element(&[0x80]), vec![TAG_FLAG | 1, 0x80]);
+    assert_eq!(
+        encode_element(&[0x01, 0x02, 0x03, 0x04, 0x05]),
+        vec![TAG_FLAG | 5, 0x030201, 0x0504]
+    );
+
+    let elements = vec![
+        vec![],
+        vec![0x00],
+        vec![0x80],
+        vec![0xff, 0xff, 0xff, 0x7f],
+        vec![0x2a],
+        (0..32).map(|i| i * 8).collect::<Vec<u8>>(),
+    ];
+    for element in elements.iter() {
+        let words = encode_element(element);
+        assert!(words.iter().all(|&word| word < 1 << 31));
+        assert_eq!(&decode_element(&words), element);
+    }
+    assert_eq!(
+        decode_elements(&encode_elements(elements.clone())),
+        elements
+    );
+}
+
+/// Tests that the state with hashes is injected from its byte representation exactly
+#[test]
+fn test_wide_elements_inject_script() {
+    let state = IntermediateState::from_input_script(
+        &script! {},
+        &script! {
+            OP_1 OP_SHA256 { 100_000 } OP_3 OP_HASH160
+            OP_4 OP_SHA256 OP_TOALTSTACK OP_5 OP_TOALTSTACK
+        },
+    );
+
+    let injected_state = IntermediateState::from_inject_script(&state.to_bytes().inject_script());
+    assert_eq!(injected_state, state);
+    assert_eq!(injected_state.stack.len(), 3);
+    assert_eq!(injected_state.altstack.len(), 2);
+}
+
+/// Tests that the script restores the wide numbers from their words without OP_CAT,
+/// and rejects the wide elements which are not numbers
+#[test]
+fn test_wide_elements_decode_script() {
+    for number in [
+        1i64 << 30,
+        (1 << 31) - 1,
+        -(1 << 29),
+        -(1 << 31) + 1,
+        -(1 << 20),
+        42,
+    ] {
+        let words = encode_element(&scriptint_vec(number));
+
+        for from_altstack in [false, true] {
+            let decode_script = decode_element_script(&words, from_altstack).unwrap();
+            let result = execute_script_without_op_cat(script! {
+                for &word in words.iter().rev() {
+                    { word }
+                    if from_altstack {
+                        OP_TOALTSTACK
+                    }
+                }
+                { decode_script }
+                { number } OP_EQUAL
+            });
+            assert!(result.success, "failed to restore {}", number);
+        }
+    }
+
+    let hash = (0..32).collect::<Vec<u8>>();
+    assert_eq!(
+        decode_element_script(&encode_element(&hash), true),
+        Err(EncodingError::UnrestorableElement { length: 32 })
+    );
+    assert_eq!(
+        decode_element_script(&encode_element(&[0x00]), false),
+        Err(EncodingError::UnrestorableElement { length: 1 })
+    );
+}
+
+/// Tests that the negative numbers keep their sign in the word encoding
+#[test]
+fn test_negative_elements_encoding() {
+    assert_eq!(read_script_number(&[0x81]), Some(-1));
+    assert_eq!(read_script_number(&[0xff, 0x80]), Some(-255));
+    assert_eq!(read_script_number(&[0x80]), None);
+    assert_eq!(read_script_number(&[0x01, 0x00]), None);
+
+    // Small negative numbers are committed as a single word
+    assert_eq!(encode_element(&[0x81]), vec![TAG_FLAG | NEGATIVE_FLAG | 1]);
+    assert_eq!(
+        encode_element(&[0x00, 0x00, 0x00, 0x90]),
+        vec![TAG_FLAG | NEGATIVE_FLAG | (1 << 28)]
+    );
+    // Positive numbers with the same bit set are not confused with them
+    assert_eq!(encode_element(&[0x00, 0x00, 0x00, 0x20]), vec![1 << 29]);
+    assert_eq!(decode_element(&[1 << 29]), vec![0x00, 0x00, 0x00, 0x20]);
+
+    // Injecting the state with negative numbers restores it exactly
+    let state = IntermediateState::from_input_script(
+        &script! {},
+        &script! {
+            OP_2 OP_5 OP_SUB { 1 << 30 } OP_NEGATE { 7 }
+            { 1 << 20 } OP_NEGATE OP_TOALTSTACK
+        },
+    );
+    for element in state.stack.iter_str().chain(state.altstack.iter_str()) {
+        assert_eq!(decode_element(&encode_element(&element)), element);
+    }
+
+    let injected_state = IntermediateState::from_inject_script(&state.to_bytes().inject_script());
+    assert_eq!(injected_state, state);
+}
+
+/// Tests that replaying the split on other inputs reports the inputs
+/// on which the shape of the states depends on the input
+#[test]
+fn test_split_robustness() {
+    // The number of elements left by the first shard depends on the flag
+    let main_script = script! {
+        OP_IF OP_DUP OP_ENDIF OP_1 OP_ADD
+    };
+    let io_pair = |x: i64, flag: i64, output: Script| IOPair::<2, 2> {
+        input: script! { { x } { flag } },
+        output,
+    };
+
+    let split_result = naive_split(
+        script! { { 5 } OP_1 },
+        main_script,
+        SplitType::ByInstructions,
+        3,
+    );
+    assert_eq!(split_result.len(), 2);
+
+    let report = check_split_robustness(
+        &split_result,
+        vec![
+            io_pair(7, 1, script! { { 7 } { 8 } }),
+            io_pair(7, 0, script! { { 8 } }),
+            io_pair(7, 1, script! { { 7 } { 9 } }),
+            // The number of elements is the same, but they are encoded by more words
+            io_pair(1 << 40, 1, script! {}),
+        ],
+    );
+    assert_eq!(report.inputs_checked, 4);
+    assert!(!report.is_robust());
+    assert_eq!(
+        report.divergences,
+        vec![
+            SplitDivergence::ShapeMismatch {
+                input_id: 1,
+                shard_id: 0,
+                expected: StateShape {
+                    stack: vec![1, 1],
+                    altstack: vec![]
+                },
+                actual: StateShape {
+                    stack: vec![1],
+                    altstack: vec![]
+                },
+            },
+            SplitDivergence::OutputMismatch { input_id: 2 },
+            SplitDivergence::ShapeMismatch {
+                input_id: 3,
+                shard_id: 0,
+                expected: StateShape {
+                    stack: vec![1, 1],
+                    altstack: vec![]
+                },
+                actual: StateShape {
+                    stack: vec![3, 3],
+                    altstack: vec![]
+                },
+            },
+        ]
+    );
+}
+
+/// Program summing the given number of terms, with the size known at runtime only
+struct SumProgram {
+    terms: usize,
+}
+
+impl SplitProgram for SumProgram {
+    fn input_size(&self) -> usize {
+        self.terms
+    }
+
+    fn output_size(&self) -> usize {
+        1
+    }
+
+    fn script(&self) -> Script {
+        script! {
+            for _ in 1..self.terms {
+                OP_ADD
+            }
+        }
+    }
+
+    fn generate_valid_io_pair(&self) -> ProgramIOPair {
+        ProgramIOPair {
+            input: script! { for i in 1..=self.terms { { i } } },
+            output: script! { { self.terms * (self.terms + 1) / 2 } },
+        }
+    }
+
+    fn generate_invalid_io_pair(&self) -> ProgramIOPair {
+        ProgramIOPair {
+            input: script! { for i in 1..=self.terms { { i } } },
+            output: script! { { self.terms * (self.terms + 1) / 2 + 1 } },
+        }
+    }
+}
+
+/// Tests that the programs of different sizes can be picked from the registry by name
+#[test]
+fn test_program_registry() {
+    let mut registry = ProgramRegistry::new();
+    assert!(registry.is_empty());
+    assert!(registry
+        .register("sum_10", Box::new(SumProgram { terms: 10 }))
+        .is_none());
+    assert!(registry
+        .register("sum_3", Box::new(SumProgram { terms: 3 }))
+        .is_none());
+    assert_eq!(registry.len(), 2);
+    assert_eq!(
+        registry.names().collect::<Vec<_>>(),
+        vec!["sum_10", "sum_3"]
+    );
+
+    for (name, terms) in [("sum_10", 10), ("sum_3", 3)] {
+        let program = registry.get(name).unwrap();
+        assert_eq!(program.input_size(), terms);
+        assert!(program.verify_random());
+
+        let ProgramIOPair { input, output } = program.generate_invalid_io_pair();
+        assert!(!program.verify(input, output));
+
+        let ProgramIOPair { input, output } = program.generate_valid_io_pair();
+        let split_result = program.split(input.clone(), SplitType::ByInstructions, 2);
+        // A full last shard is followed by the empty one
+        assert_eq!(split_result.len(), (terms - 1) / 2 + 1);
+        assert_eq!(
+            split_result.verify(&input, &program.script(), &output),
+            Ok(())
+        );
+    }
+
+    // Registering under the same name replaces the program
+    let previous = registry.register("sum_3", Box::new(SumProgram { terms: 4 }));
+    assert_eq!(previous.map(|program| program.input_size()), Some(3));
+    assert_eq!(registry.get("sum_3").unwrap().input_size(), 4);
+}
+
+/// Tests that the composed program chains the IO pairs and the splits of its components
+#[test]
+fn test_composed_program() {
+    // Programs of mismatching sizes cannot be composed
+    let result = ComposedProgram::new(
+        Box::new(SumProgram { terms: 3 }),
+        Box::new(SumProgram { terms: 2 }),
+    );
+    assert_eq!(
+        result.err(),
+        Some(CompositionError::SizeMismatch {
+            output_size: 1,
+            input_size: 2
+        })
+    );
+
+    let program = ComposedProgram::new(
+        Box::new(SumProgram { terms: 5 }),
+        Box::new(SumProgram { terms: 1 }),
+    )
+    .unwrap();
+    assert_eq!((program.input_size(), program.output_size()), (5, 1));
+    assert!(program.verify_random());
+
+    let ProgramIOPair { input, output } = program.generate_invalid_io_pair();
+    assert!(!program.verify(input, output));
+
+    // Each component is split separately: the first one into two shards,
+    // while the empty second one gives a single empty shard
+    let ProgramIOPair { input, output } = program.generate_valid_io_pair();
+    let split_result = program.split(input.clone(), SplitType::ByInstructions, 3);
+    assert_eq!(split_result.len(), 2 + 1);
+    assert_eq!(
+        split_result.verify(&input, &program.script(), &output),
+        Ok(())
+    );
+}
+
+/// Tests that byte-identical shards are grouped into the same class
+#[test]
+fn test_shard_classes() {
+    let input_script = script! { OP_0 };
+    let main_script = script! {
+        OP_1 OP_ADD OP_1 OP_ADD OP_1 OP_ADD OP_2 OP_ADD
+    };
+
+    let split_result = naive_split(
+        input_script.clone(),
+        main_script,
+        SplitType::ByInstructions,
+        2,
+    );
+    assert_eq!(split_result.len(), 5);
+
+    // Three shards { OP_1 OP_ADD }, then { OP_2 OP_ADD } and the empty one
+    let classes = split_result.shard_classes();
+    assert_eq!(classes.len(), 3);
+    assert_eq!(classes.class_ids(), &[0, 0, 0, 1, 2]);
+    assert_eq!(classes.class_of(2), 0);
+    assert_eq!(classes.representatives(), &[0, 3, 4]);
+    assert_eq!(classes.representative(1), 3);
+    assert_eq!(classes.members(0).collect::<Vec<_>>(), vec![0, 1, 2]);
+
+    let report = SplitReport::new(&input_script, &split_result, &MaxDisproveCost::default());
+    assert_eq!(report.unique_shards, 3);
+    assert_eq!(report.unique_shards_size, 2 + 2);
+    assert!(report
+        .to_markdown()
+        .contains("- Unique shards: 3 (4 bytes)"));
+}
+
+/// Tests that the unrolled loop is detected and the script is cut at its iteration boundaries
+#[test]
+fn test_periodic_split() {
+    let main_script = script! {
+        OP_1
+        for _ in 0..5 {
+            OP_DUP OP_1ADD OP_SWAP OP_DROP OP_DUP OP_2 OP_ADD OP_NIP
+        }
+        OP_16 OP_EQUAL
+    };
+
+    let loops = LoopStructure::detect(&main_script, 8).unwrap();
+    assert_eq!(
+        loops.regions,
+        vec![PeriodicRegion {
+            start: 1,
+            period: 8,
+            iterations: 5,
+        }]
+    );
+    assert_eq!(loops.boundaries(), vec![1, 9, 17, 25, 33, 41]);
+
+    // Preferred cut points are placed at the boundaries inside the script
+    let marked_script = loops.mark(&main_script);
+    assert_eq!(marked_script.script(), &main_script);
+    assert_eq!(
+        marked_script.checked_hints().unwrap().preferred,
+        vec![1, 9, 17, 25, 33]
+    );
+
+    // The prefix, one shard per iteration and the suffix
+    let split_result = periodic_split(
+        Script::new(),
+        main_script.clone(),
+        SplitType::ByInstructions,
+        1,
+    )
+    .unwrap();
+    assert_eq!(split_result.len(), 1 + 5 + 1);
+    assert_eq!(split_result.shard_classes().len(), 3);
+    assert_eq!(
+        split_result.verify(&Script::new(), &main_script, &script! { OP_TRUE }),
+        Ok(())
+    );
+
+    // Hand-written hints take precedence over the detected loops
+    let mut hinted_script = AnnotatedScript::from(&main_script);
+    hinted_script.push(split_here()).push(script! { OP_VERIFY });
+    assert_eq!(
+        with_loop_hints(hinted_script.clone()).unwrap(),
+        hinted_script
+    );
+}
+
+/// Tests that the streaming splitter gives the same split as the naive one
+#[test]
+fn test_stream_split() {
+    let input_script = script! { OP_0 };
+    let mut atomic_script = AnnotatedScript::from(script! { OP_1 OP_ADD });
+    atomic_script
+        .push(atomic(script! { OP_DUP OP_ADD OP_DUP OP_ADD }))
+        .push(script! {
+            OP_DUP OP_IF OP_1 OP_ADD OP_ENDIF
+            OP_2 OP_ADD
+        });
+    let mut preferred_script = AnnotatedScript::from(script! { OP_1 OP_ADD OP_DUP OP_ADD });
+    preferred_script
+        .push(split_here())
+        .push(script! { OP_DUP OP_ADD })
+        .push(split_here())
+        .push(script! { OP_2 OP_ADD })
+        .push(split_here());
+
+    for main_script in [atomic_script.clone(), preferred_script] {
+        for chunk_size in 1..=4 {
+            for split_type in [SplitType::ByInstructions, SplitType::ByBytes] {
+                let expected = naive_split(
+                    input_script.clone(),
+                    main_script.clone(),
+                    split_type.clone(),
+                    chunk_size,
+                );
+
+                let (shards, states): (Vec<_>, Vec<_>) =
+                    ShardStream::new(&input_script, &main_script, split_type, chunk_size)
+                        .unwrap()
+                        .map(|streamed_shard| {
+                            let streamed_shard = streamed_shard.unwrap();
+                            (streamed_shard.shard, streamed_shard.state)
+                        })
+                        .unzip();
+                assert_eq!(shards, expected.shards);
+                assert_eq!(states, expected.intermediate_states);
+            }
+        }
+    }
+
+    // The stack-size split needs the profile of the whole script
+    assert!(matches!(
+        ShardStream::new(
+            &input_script,
+            &atomic_script,
+            SplitType::ByStackSize { tolerance: 1 },
+            2
+        ),
+        Err(SplitError::UnsupportedSplitType)
+    ));
+
+    // Shards written to the disk give the same split back. The directory is unique
+    // for the process, so that concurrent runs do not collide.
+    let directory = std::env::temp_dir().join(format!(
+        "split_stream_test_{}_{}",
+        std::process::id(),
+        "test_stream_split"
+    ));
+    let _ = std::fs::remove_dir_all(&directory);
+    let shards_number = split_to_directory(
+        &input_script,
+        &atomic_script,
+        SplitType::ByInstructions,
+        2,
+        &directory,
+    )
+    .unwrap();
+
+    let expected = naive_split(
+        input_script.clone(),
+        atomic_script.clone(),
+        SplitType::ByInstructions,
+        2,
+    );
+    assert_eq!(shards_number, expected.len());
+    for shard_id in 0..shards_number {
+        let json = std::fs::read_to_string(directory.join(shard_file_name(shard_id))).unwrap();
+        let (shard, state) = ShardArtifact::from_json(&json).unwrap().to_shard().unwrap();
+        assert_eq!(shard, expected.shards[shard_id]);
+        assert_eq!(state, expected.intermediate_states[shard_id]);
+    }
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+/// Tests the split at the checkpoints pinned by the user
+#[test]
+fn test_split_at_checkpoints() {
+    let main_script = script! {
+        OP_1 OP_2 OP_ADD
+        OP_DUP
+        OP_IF
+            OP_1 OP_ADD
+        OP_ELSE
+            OP_2 OP_ADD
+        OP_ENDIF
+        OP_3 OP_ADD
+    };
+    let output_script = script! { 7 };
+
+    // Cutting right before OP_DUP (given by its byte offset) and right after OP_ENDIF
+    let checkpoints = [Checkpoint::Byte(3), Checkpoint::Instruction(11)];
+    let split_result = split_at_checkpoints(
+        script! {},
+        main_script.clone(),
+        &checkpoints,
+        CheckpointFallback::Reject,
+    )
+    .unwrap();
+    assert_eq!(split_result.len(), 3);
+    assert_eq!(split_result.cuts(), vec![3, 11]);
+    assert_eq!(
+        split_result.verify(&script! {}, &main_script, &output_script),
+        Ok(())
+    );
+
+    // Checkpoints inside the conditional block are either rejected or moved
+    let resolve = |checkpoints: &[Checkpoint], fallback| {
+        resolve_checkpoints(&main_script, checkpoints, fallback)
+    };
+    let inside = Checkpoint::Instruction(7);
+    assert_eq!(
+        resolve(&[inside], CheckpointFallback::Reject),
+        Err(CheckpointError::IllegalCut(inside))
+    );
+    assert_eq!(resolve(&[inside], CheckpointFallback::Nearest), Ok(vec![4]));
+    assert_eq!(
+        resolve(&[Checkpoint::Instruction(9)], CheckpointFallback::Nearest),
+        Ok(vec![11])
+    );
+    assert_eq!(
+        resolve(
+            &[Checkpoint::Instruction(4), inside],
+            CheckpointFallback::Nearest
+        ),
+        Ok(vec![4])
+    );
+
+    // Checkpoints must be increasing, lie inside the script and at the instruction starts
+    assert_eq!(
+        resolve(&[Checkpoint::Instruction(0)], CheckpointFallback::Reject),
+        Err(CheckpointError::OutOfRange(Checkpoint::Instruction(0)))
+    );
+    assert_eq!(
+        resolve(
+            &[Checkpoint::Instruction(11), Checkpoint::Instruction(3)],
+            CheckpointFallback::Reject
+        ),
+        Err(CheckpointError::NotIncreasing(Checkpoint::Instruction(3)))
+    );
+    assert_eq!(
+        resolve(&[Checkpoint::Byte(100)], CheckpointFallback::Reject),
+        Err(CheckpointError::NotInstructionBoundary(Checkpoint::Byte(
+            100
+        )))
+    );
+
+    // Moving a single boundary of the existing split keeps the rest of it
+    let split_result = naive_split(
+        script! {},
+        main_script.clone(),
+        SplitType::ByInstructions,
+        3,
+    );
+    let mut cuts = split_result.cuts();
+    assert_eq!(cuts, vec![3, 12]);
+    cuts[1] = 11;
+    let checkpoints: Vec<Checkpoint> = cuts.into_iter().map(Checkpoint::Instruction).collect();
+    let moved_result = split_at_checkpoints(
+        script! {},
+        main_script.clone(),
+        &checkpoints,
+        CheckpointFallback::Reject,
+    )
+    .unwrap();
+    assert_eq!(moved_result.shards[..1], split_result.shards[..1]);
+    assert_eq!(moved_result.cuts(), vec![3, 11]);
+    assert_eq!(
+        moved_result.verify(&script! {}, &main_script, &output_script),
+        Ok(())
+    );
+}
+
+/// Tests that the split under the resource limits keeps every disprove script within them
+/// and names the region that cannot fit
+#[test]
+fn test_constrained_split() {
+    let input_script = script! { OP_0 };
+    let main_script = script! {
+        for _ in 0..10 {
+            OP_1 OP_ADD
+        }
+    };
+    let output_script = script! { 10 };
+
+    let model = DisproveModel {
+        leaf_overhead: 0,
+        leaf_bytes_per_input_element: 10,
+        leaf_bytes_per_output_element: 10,
+        witness_bytes_per_element: 5,
+        witness_items_per_element: 1,
+        verification_items: 0,
+    };
+    let constraints = SplitConstraints {
+        max_leaf_size: 26,
+        max_stack_items: 100,
+        max_witness_size: 1000,
+        max_tx_weight: 1000,
+        tx_overhead: 0,
+        model,
+    };
+
+    // Cuts can only be made between the iterations, where the state has a single element,
+    // so the shards take at most 6 instructions
+    let split_result =
+        constrained_split(input_script.clone(), main_script.clone(), &constraints).unwrap();
+    assert_eq!(split_result.cuts(), vec![6, 12, 18]);
+    assert_eq!(
+        split_result.verify(&input_script, &main_script, &output_script),
+        Ok(())
+    );
+    for shard in split_result.shards.iter() {
+        assert_eq!(constraints.check(shard.len(), 2, 1, 1), Ok(()));
+    }
+
+    // No shard fits the leaf, so the first shard is blocked
+    let tight_leaf = SplitConstraints {
+        max_leaf_size: 21,
+        ..constraints
+    };
+    assert_eq!(
+        constrained_split(input_script.clone(), main_script.clone(), &tight_leaf).unwrap_err(),
+        ConstraintError::Blocked {
+            start: 0,
+            end: 1,
+            constraint: Constraint::LeafSize,
+            required: 31,
+            limit: 21,
+        }
+    );
+
+    // The leaf and the witness fit separately, but not together
+    let tight_tx = SplitConstraints {
+        max_tx_weight: 34,
+        ..constraints
+    };
+    let split_result =
+        constrained_split(input_script.clone(), main_script.clone(), &tight_tx).unwrap();
+    assert_eq!(split_result.cuts(), vec![4, 8, 12, 16]);
+
+    let tight_tx = SplitConstraints {
+        max_leaf_size: 1000,
+        max_tx_weight: 30,
+        ..constraints
+    };
+    assert_eq!(
+        constrained_split(input_script.clone(), main_script.clone(), &tight_tx).unwrap_err(),
+        ConstraintError::Blocked {
+            start: 0,
+            end: 1,
+            constraint: Constraint::TxWeight,
+            required: 46,
+            limit: 30,
+        }
+    );
+
+    let tight_stack = SplitConstraints {
+        max_leaf_size: 1000,
+        max_stack_items: 2,
+        ..constraints
+    };
+    assert_eq!(
+        constrained_split(input_script, main_script, &tight_stack).unwrap_err(),
+        ConstraintError::Blocked {
+            start: 0,
+            end: 1,
+            constraint: Constraint::StackItems,
+            required: 4,
+            limit: 2,
+        }
+    );
+
+    // The conditional block cannot be cut, so it is named as a whole
+    let conditional_script = script! {
+        OP_1
+        OP_IF
+            OP_2 OP_3 OP_ADD OP_DROP
+        OP_ENDIF
+        OP_4
+    };
+    let shard_only = SplitConstraints {
+        max_leaf_size: 3,
+        max_stack_items: 100,
+        max_witness_size: 1000,
+        max_tx_weight: 1000,
+        tx_overhead: 0,
+        model: DisproveModel {
+            leaf_bytes_per_input_element: 0,
+            leaf_bytes_per_output_element: 0,
+            ..model
+        },
+    };
+    assert_eq!(
+        constrained_split(script! {}, conditional_script, &shard_only).unwrap_err(),
+        ConstraintError::Blocked {
+            start: 1,
+            end: 7,
+            constraint: Constraint::LeafSize,
+            required: 6,
+            limit: 3,
+        }
+    );
+}
+
+/// Tests that the split under the resource limits measures the states as they are signed
+/// and never cuts at the state the disprove script cannot restore
+#[test]
+fn test_constrained_split_signed_states() {
+    let input_script = script! { OP_0 };
+    let main_script = script! {
+        OP_1ADD OP_1ADD OP_SHA256 OP_SIZE OP_NIP
+    };
+    let output_script = script! { 32 };
+
+    let constraints = SplitConstraints {
+        max_leaf_size: 24,
+        max_stack_items: 100,
+        max_witness_size: 1000,
+        max_tx_weight: 1000,
+        tx_overhead: 0,
+        model: DisproveModel {
+            leaf_overhead: 0,
+            leaf_bytes_per_input_element: 10,
+            leaf_bytes_per_output_element: 10,
+            witness_bytes_per_element: 5,
+            witness_items_per_element: 1,
+            verification_items: 0,
+        },
+    };
+
+    // The whole script does not fit, and the latest cut leaving the short last shard
+    // has the hash in the state, so the script is cut before hashing
+    let split_result =
+        constrained_split(input_script.clone(), main_script.clone(), &constraints).unwrap();
+    assert_eq!(split_result.cuts(), vec![2]);
+    assert_eq!(
+        split_result.verify(&input_script, &main_script, &output_script),
+        Ok(())
+    );
+
+    // The output itself cannot be restored
+    assert_eq!(
+        constrained_split(input_script, script! { OP_SHA256 }, &constraints).unwrap_err(),
+        ConstraintError::Encoding(EncodingError::UnrestorableElement { length: 32 })
+    );
+}
+
+/// Tests that the static analysis of the script gives the same profile as its execution
+/// and ranks the cuts without executing the script
+#[test]
+fn test_static_profile() {
+    let input_script = script! { 1 2 3 };
+    let main_script = script! {
+        OP_ADD OP_TOALTSTACK OP_DUP OP_FROMALTSTACK OP_ADD
+        OP_DUP
+        OP_IF
+            OP_1ADD
+        OP_ENDIF
+        OP_2DUP OP_ADD OP_ADD OP_NIP
+    };
+    let output_script = script! { 15 };
+
+    let executed = ScriptProfile::execute(&input_script, &main_script);
+    let analyzed = ScriptProfile::analyze(&input_script, &main_script);
+    assert_eq!(analyzed.state_sizes, executed.state_sizes);
+    assert_eq!(analyzed.legal, executed.legal);
+
+    // Boundaries with the smallest states come first
+    assert_eq!(
+        analyzed.ranked_cuts(),
+        vec![1, 2, 5, 9, 12, 3, 4, 6, 11, 10]
+    );
+
+    let metric = MaxDisproveCost::default();
+    let split_result = static_optimal_split(input_script.clone(), main_script.clone(), &metric);
+    assert_eq!(
+        split_result.cuts(),
+        optimal_split(input_script.clone(), main_script.clone(), &metric).cuts()
+    );
+    assert_eq!(
+        split_result.verify(&input_script, &main_script, &output_script),
+        Ok(())
+    );
+
+    // The state after the block pushing the element only in one branch depends on the values,
+    // so the script is never cut there
+    let conditional_script = script! {
+        OP_DUP
+        OP_IF
+            OP_DUP
+        OP_ENDIF
+        OP_DROP OP_DROP
+    };
+    let analyzed = ScriptProfile::analyze(&script! { 1 }, &conditional_script);
+    assert_eq!(
+        analyzed.legal,
+        vec![true, true, false, false, false, false, false]
+    );
+    assert_eq!(analyzed.ranked_cuts(), vec![1]);
+}
+
+/// Tests that the hints of the hinted script are routed to the shards consuming them
+#[test]
+fn test_hinted_split() {
+    // Doubles the top element, taking the result from the hint and checking it
+    let double = || {
+        atomic(script! {
+            OP_DEPTH OP_1SUB OP_ROLL
+            OP_OVER OP_DUP OP_ADD OP_OVER OP_EQUALVERIFY
+            OP_NIP
+        })
+    };
+    let mut main_script = double();
+    main_script.push(double());
+
+    let input_script = script! { 5 };
+    let output_script = script! { 20 };
+    let hinted_script = HintedScript::new(&main_script, vec![script! { 10 }, script! { 20 }]);
+    assert_eq!(hinted_script.consumed_elements(), 2);
+
+    // The smallest chunks never cut the gadget between taking the hint and checking it
+    let split_result = hinted_split(
+        input_script.clone(),
+        &hinted_script,
+        SplitType::ByInstructions,
+        1,
+    )
+    .unwrap();
+    assert_eq!(split_result.split_result.cuts(), vec![9]);
+
+    let shard_hints: Vec<IntermediateState> = split_result
+        .shard_hints
+        .iter()
+        .map(IntermediateState::from_inject_script)
+        .collect();
+    let expected_hints: Vec<IntermediateState> = [script! { 10 }, script! { 20 }, script! {}]
+        .iter()
+        .map(IntermediateState::from_inject_script)
+        .collect();
+    assert_eq!(shard_hints, expected_hints);
+
+    // The hints are checked before they get into the intermediate states
+    assert_eq!(
+        split_result.split_result.intermediate_states[0],
+        IntermediateState::from_inject_script(&script! { 10 })
+    );
+    assert_eq!(
+        split_result.verify(&input_script, &hinted_script, &output_script),
+        Ok(())
+    );
+
+    // The same hints in the other order are not the hints of the split
+    let swapped_script = HintedScript::new(&main_script, vec![script! { 20 }, script! { 10 }]);
+    assert_eq!(
+        split_result.verify(&input_script, &swapped_script, &output_script),
+        Err(SplitVerificationError::HintMismatch)
+    );
+
+    // The wrong hint fails the shard taking it instead of getting into the state
+    let wrong_script = HintedScript::new(&main_script, vec![script! { 11 }, script! { 22 }]);
+    let wrong_result = hinted_split(
+        input_script.clone(),
+        &wrong_script,
+        SplitType::ByInstructions,
+        1,
+    )
+    .unwrap();
+    assert!(
+        !execute_script(script! {
+            { wrong_result.shard_input(&input_script, 0) }
+            { wrong_result.split_result.shards[0].clone() }
+        })
+        .success
+    );
+
+    // Every hint element must be consumed
+    let missing_script = HintedScript::new(&main_script, vec![script! { 10 }]);
+    assert_eq!(
+        hinted_split(
+            input_script.clone(),
+            &missing_script,
+            SplitType::ByInstructions,
+            1
+        )
+        .unwrap_err(),
+        HintError::CountMismatch {
+            provided: 1,
+            consumed: 2,
+        }
+    );
+
+    // The hint must be taken inside the gadget checking it
+    let mut unguarded_script = AnnotatedScript::from(script! { OP_DEPTH OP_1SUB OP_ROLL });
+    unguarded_script.push(script! { OP_ADD }).push(double());
+    let unguarded_script = HintedScript::new(unguarded_script, vec![script! { 1 }, script! { 12 }]);
+    assert_eq!(
+        hinted_split(
+            input_script,
+            &unguarded_script,
+            SplitType::ByInstructions,
+            1
+        )
+        .unwrap_err(),
+        HintError::UnguardedConsumption { instruction: 0 }
+    );
+}
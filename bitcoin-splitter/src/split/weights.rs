@@ -0,0 +1,60 @@
+//! Module containing the [`OpcodeWeights`] table, which assigns the cost to each
+//! instruction of the script for [`super::core::SplitType::ByWeight`].
+
+use bitcoin::{opcodes::Opcode, script::Instruction};
+
+/// Table of per-opcode weights. Pushes of the data are weighted separately as
+/// `push_weight + push_byte_weight * data_length`, since their opcode depends
+/// on the length of the data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpcodeWeights {
+    /// Weight of each opcode indexed by its byte value
+    op_weights: Vec<usize>,
+    /// Weight of a single data push
+    push_weight: usize,
+    /// Additional weight of each pushed byte
+    push_byte_weight: usize,
+}
+
+impl OpcodeWeights {
+    /// Creates the table where each instruction has the same weight
+    pub fn uniform(weight: usize) -> Self {
+        Self {
+            op_weights: vec![weight; 256],
+            push_weight: weight,
+            push_byte_weight: 0,
+        }
+    }
+
+    /// Sets the weight of the given opcode
+    pub fn with_op(mut self, op: Opcode, weight: usize) -> Self {
+        self.op_weights[op.to_u8() as usize] = weight;
+        self
+    }
+
+    /// Sets the weight of the data pushes
+    pub fn with_push(mut self, push_weight: usize, push_byte_weight: usize) -> Self {
+        self.push_weight = push_weight;
+        self.push_byte_weight = push_byte_weight;
+        self
+    }
+
+    /// Returns the weight of the given opcode
+    pub fn op_weight(&self, op: Opcode) -> usize {
+        self.op_weights[op.to_u8() as usize]
+    }
+
+    /// Returns the weight of the given instruction
+    pub fn instruction_weight(&self, instruction: &Instruction) -> usize {
+        match instruction {
+            Instruction::Op(op) => self.op_weight(*op),
+            Instruction::PushBytes(bytes) => self.push_weight + self.push_byte_weight * bytes.len(),
+        }
+    }
+}
+
+impl Default for OpcodeWeights {
+    fn default() -> Self {
+        Self::uniform(1)
+    }
+}
@@ -0,0 +1,166 @@
+//! This module contains the test script
+//! for repeatedly squaring the field element with the hinted
+//! field arithmetic, which takes the quotients from the witness
+
+use crate::bitvm::bn254::{fp254impl::Fp254Impl, fq::Fq, utils::fq_push_not_montgomery};
+use bitcoin_splitter::split::{
+    hinted::{HintedIOPair, HintedSplitableScript},
+    hints::{atomic, AnnotatedScript},
+};
+use bitcoin_utils::treepp::*;
+
+use ark_std::UniformRand;
+use core::ops::Mul;
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+
+/// Script that squares the field element `STEPS` times, that is,
+/// computes `x**(2**STEPS)`, using the hinted squaring
+pub struct FqSquareChainScript<const STEPS: usize>;
+
+/// Input size is the number of limbs of the field element
+const INPUT_SIZE: usize = Fq::N_LIMBS as usize;
+/// Output size is the number of limbs of the field element
+const OUTPUT_SIZE: usize = Fq::N_LIMBS as usize;
+
+impl<const STEPS: usize> FqSquareChainScript<STEPS> {
+    /// Returns the scripts pushing the hints consumed while squaring `x`
+    pub fn hints(x: ark_bn254::Fq) -> Vec<Script> {
+        let mut x = x;
+        let mut hints = Vec::with_capacity(STEPS);
+
+        for _ in 0..STEPS {
+            let (_, step_hints) = Fq::hinted_square(x);
+            hints.extend(step_hints.iter().map(|hint| hint.push()));
+            x = x.mul(&x);
+        }
+
+        hints
+    }
+
+    /// Calculates the result of the squaring chain
+    pub fn calculate_result(x: ark_bn254::Fq) -> ark_bn254::Fq {
+        (0..STEPS).fold(x, |x, _| x.mul(&x))
+    }
+}
+
+impl<const STEPS: usize> HintedSplitableScript<{ INPUT_SIZE }, { OUTPUT_SIZE }>
+    for FqSquareChainScript<STEPS>
+{
+    fn script() -> Script {
+        Self::annotated_script().into_script()
+    }
+
+    fn annotated_script() -> AnnotatedScript {
+        // The hinted squaring script does not depend on the squared element,
+        // only its hints do
+        let (square, _) = Fq::hinted_square(ark_bn254::Fq::from(1u32));
+
+        // Squaring checks the hints it takes, so it is never cut in the middle
+        let mut script = AnnotatedScript::default();
+        for _ in 0..STEPS {
+            script.push(atomic(square.clone()));
+        }
+        script
+    }
+
+    fn generate_valid_io_pair() -> HintedIOPair<{ INPUT_SIZE }, { OUTPUT_SIZE }> {
+        let x = generate_random_fq();
+
+        HintedIOPair {
+            input: fq_push_not_montgomery(x),
+            output: fq_push_not_montgomery(Self::calculate_result(x)),
+            hints: Self::hints(x),
+        }
+    }
+
+    fn generate_invalid_io_pair() -> HintedIOPair<{ INPUT_SIZE }, { OUTPUT_SIZE }> {
+        // The hints are valid, but the output is not the result of the chain
+        let x = generate_random_fq();
+
+        HintedIOPair {
+            input: fq_push_not_montgomery(x),
+            output: fq_push_not_montgomery(Self::calculate_result(x) + x),
+            hints: Self::hints(x),
+        }
+    }
+}
+
+/// Generates a random Fq element
+fn generate_random_fq() -> ark_bn254::Fq {
+    let mut prng = ChaCha20Rng::seed_from_u64(0);
+    ark_bn254::Fq::rand(&mut prng)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin_splitter::split::{core::SplitType, hinted::HintedScript};
+    use bitcoin_utils::{comparison::OP_LONGEQUALVERIFY, stack_to_script};
+
+    #[test]
+    fn test_verify() {
+        const STEPS: usize = 8;
+        println!(
+            "Fq square chain is {} bytes in size",
+            FqSquareChainScript::<STEPS>::script().len()
+        );
+        assert!(FqSquareChainScript::<STEPS>::verify_random());
+    }
+
+    #[test]
+    fn test_invalid_generate() {
+        const STEPS: usize = 4;
+        let io_pair = FqSquareChainScript::<STEPS>::generate_invalid_io_pair();
+        assert!(!FqSquareChainScript::<STEPS>::verify(&io_pair));
+    }
+
+    #[test]
+    fn test_hinted_split_correctness() {
+        const STEPS: usize = 4;
+        type SquareChainScript = FqSquareChainScript<STEPS>;
+
+        let io_pair = SquareChainScript::generate_valid_io_pair();
+        let hinted_script = SquareChainScript::hinted_script(&io_pair);
+
+        // The smallest chunks cut the script after each squaring, but never inside it
+        let split_result = SquareChainScript::split(&io_pair, SplitType::ByInstructions, 1);
+        assert_eq!(split_result.len(), STEPS + 1);
+
+        // Each shard takes exactly the hints given to it
+        assert_eq!(
+            split_result.verify(&io_pair.input, &hinted_script, &io_pair.output),
+            Ok(())
+        );
+
+        // The last state must be the output
+        let last_state = split_result
+            .split_result
+            .intermediate_states
+            .last()
+            .unwrap();
+        let verification_script = script! {
+            { stack_to_script(&last_state.stack) }
+            { io_pair.output.clone() }
+            { OP_LONGEQUALVERIFY(SquareChainScript::OUTPUT_SIZE) }
+            OP_TRUE
+        };
+        assert!(execute_script(verification_script).success);
+    }
+
+    #[test]
+    fn test_hinted_split_wrong_hints() {
+        const STEPS: usize = 2;
+        type SquareChainScript = FqSquareChainScript<STEPS>;
+
+        let io_pair = SquareChainScript::generate_valid_io_pair();
+        let split_result = SquareChainScript::split(&io_pair, SplitType::ByInstructions, 1);
+
+        // Hints of the other element must not be accepted
+        let other_hints = SquareChainScript::hints(ark_bn254::Fq::from(2u32));
+        let other_script = HintedScript::new(SquareChainScript::annotated_script(), other_hints);
+        assert!(split_result
+            .verify(&io_pair.input, &other_script, &io_pair.output)
+            .is_err());
+    }
+}
@@ -71,7 +71,9 @@ impl SplitableScript<{ INPUT_SIZE }, { OUTPUT_SIZE }> for U261MulKaratsubaScript
 #[cfg(test)]
 mod tests {
     use super::*;
-    use bitcoin_splitter::split::core::SplitType;
+    use bitcoin_splitter::split::{
+        core::SplitType, metric::MaxDisproveCost, observer::ProgressBarObserver,
+    };
     use bitcoin_utils::stack_to_script;
     use bitcoin_window_mul::traits::comparable::Comparable;
 
@@ -167,7 +169,13 @@ mod tests {
         let IOPair { input, output } = U261MulKaratsubaScript::generate_valid_io_pair();
 
         // Splitting the script into shards
-        let split_result = U261MulKaratsubaScript::fuzzy_split(input, SplitType::ByInstructions);
+        let split_result = U261MulKaratsubaScript::fuzzy_split(
+            input,
+            SplitType::ByInstructions,
+            &MaxDisproveCost::default(),
+            &mut ProgressBarObserver::default(),
+        )
+        .expect("fuzzy split should find a valid split");
 
         for shard in split_result.shards.iter() {
             println!("Shard: {:?}", shard.len());
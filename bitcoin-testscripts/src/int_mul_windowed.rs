@@ -68,7 +68,11 @@ impl SplitableScript<{ INPUT_SIZE }, { OUTPUT_SIZE }> for U254MulScript {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use bitcoin_splitter::split::core::SplitType;
+    use bitcoin_splitter::split::{
+        core::{form_states_from_shards, SplitType},
+        metric::MaxDisproveCost,
+        observer::ProgressBarObserver,
+    };
     use bitcoin_utils::stack_to_script;
     use bitcoin_window_mul::traits::comparable::Comparable;
 
@@ -270,6 +274,96 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_incremental_states() {
+        // First, we generate the pair of input and output scripts
+        let IOPair { input, output: _ } = U254MulScript::generate_valid_io_pair();
+
+        // Splitting the script into shards and computing the states in both ways
+        let split_result = U254MulScript::default_split(input.clone(), SplitType::ByInstructions);
+        let reinjected_states = form_states_from_shards(split_result.shards, input);
+
+        assert_eq!(
+            split_result.intermediate_states, reinjected_states,
+            "incremental states differ from the reinjected ones"
+        );
+    }
+
+    #[test]
+    fn test_stack_size_split() {
+        const CHUNK_SIZE: usize = 7000;
+
+        // First, we generate the pair of input and output scripts
+        let IOPair { input, output } = U254MulScript::generate_valid_io_pair();
+
+        // Splitting the script by bytes and by the stack size with the same target size
+        let bytes_result = U254MulScript::split(input.clone(), SplitType::ByBytes, CHUNK_SIZE);
+        let stack_size_result = U254MulScript::split(
+            input,
+            SplitType::ByStackSize { tolerance: 1000 },
+            CHUNK_SIZE,
+        );
+        println!(
+            "Total size of the states: by bytes is {}, by stack size is {}",
+            bytes_result.total_states_size(),
+            stack_size_result.total_states_size()
+        );
+
+        // Each cut is moved to the smallest state around it, so the largest state
+        // must not exceed the one of the split by bytes
+        assert!(
+            stack_size_result.max_states_size() <= bytes_result.max_states_size(),
+            "split by stack size has larger states: {} against {}",
+            stack_size_result.max_states_size(),
+            bytes_result.max_states_size()
+        );
+
+        // Checking the last state (which must be equal to the result of the multiplication)
+        let last_state = stack_size_result.must_last_state();
+        assert!(last_state.altstack.is_empty(), "altstack is not empty!");
+
+        let verification_script = script! {
+            { stack_to_script(&last_state.stack) }
+            { output }
+            { U508::OP_EQUAL(0, 1) }
+        };
+
+        let result = execute_script(verification_script);
+        assert!(result.success, "verification has failed");
+    }
+
+    #[test]
+    fn test_optimal_split() {
+        // First, we generate the pair of input and output scripts
+        let IOPair { input, output } = U254MulScript::generate_valid_io_pair();
+
+        // Splitting the script into shards with both the default and optimal splits
+        let default_result = U254MulScript::default_split(input.clone(), SplitType::ByInstructions);
+        let optimal_result = U254MulScript::optimal_split(input, &MaxDisproveCost::default());
+        println!(
+            "Complexity index: default is {}, optimal is {}",
+            default_result.complexity_index(),
+            optimal_result.complexity_index()
+        );
+        assert!(
+            optimal_result.complexity_index() <= default_result.complexity_index(),
+            "optimal split is worse than the default one"
+        );
+
+        // Checking the last state (which must be equal to the result of the multiplication)
+        let last_state = optimal_result.must_last_state();
+        assert!(last_state.altstack.is_empty(), "altstack is not empty!");
+
+        let verification_script = script! {
+            { stack_to_script(&last_state.stack) }
+            { output }
+            { U508::OP_EQUAL(0, 1) }
+        };
+
+        let result = execute_script(verification_script);
+        assert!(result.success, "verification has failed");
+    }
+
     #[test]
     #[ignore = "too-large computation, run separately"]
     fn test_fuzzy_split() {
@@ -277,7 +371,13 @@ mod tests {
         let IOPair { input, output } = U254MulScript::generate_valid_io_pair();
 
         // Splitting the script into shards
-        let split_result = U254MulScript::fuzzy_split(input, SplitType::ByInstructions);
+        let split_result = U254MulScript::fuzzy_split(
+            input,
+            SplitType::ByInstructions,
+            &MaxDisproveCost::default(),
+            &mut ProgressBarObserver::default(),
+        )
+        .expect("fuzzy split should find a valid split");
 
         for shard in split_result.shards.iter() {
             println!("Shard: {:?}", shard.len());
@@ -1,7 +1,9 @@
 // Test scripts
+pub mod fq_square_chain;
 pub mod int_add;
 pub mod int_mul_karatsuba;
 pub mod int_mul_windowed;
+pub mod registry;
 pub mod sha256;
 pub mod square_fibonacci;
 pub mod u29mul;
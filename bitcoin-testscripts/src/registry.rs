@@ -0,0 +1,71 @@
+//! This module contains the registry of the test scripts,
+//! so that tools can pick the script by its name
+
+use bitcoin_splitter::split::{program::ProgramRegistry, script::SplitableScript};
+
+use crate::{
+    int_add::U254AddScript, int_mul_karatsuba::U261MulKaratsubaScript,
+    int_mul_windowed::U254MulScript, sha256::SHA256Script, square_fibonacci::SquareFibonacciScript,
+    u29mul::U29MulScript,
+};
+
+/// Number of bytes hashed by the registered SHA-256 script
+const SHA256_INPUT_SIZE: usize = 32;
+/// Number of steps of the registered square fibonacci script
+const SQUARE_FIBONACCI_STEPS: usize = 64;
+
+/// Returns the registry containing all test scripts
+pub fn registry() -> ProgramRegistry {
+    let mut registry = ProgramRegistry::new();
+
+    registry.register("int_add", U254AddScript::program());
+    registry.register("int_mul_karatsuba", U261MulKaratsubaScript::program());
+    registry.register("int_mul_windowed", U254MulScript::program());
+    registry.register("sha256", SHA256Script::<SHA256_INPUT_SIZE>::program());
+    registry.register(
+        "square_fibonacci",
+        SquareFibonacciScript::<SQUARE_FIBONACCI_STEPS>::program(),
+    );
+    registry.register("u29mul", U29MulScript::program());
+
+    registry
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin_splitter::split::{core::SplitType, program::ProgramIOPair};
+
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        let registry = registry();
+        assert_eq!(
+            registry.names().collect::<Vec<_>>(),
+            vec![
+                "int_add",
+                "int_mul_karatsuba",
+                "int_mul_windowed",
+                "sha256",
+                "square_fibonacci",
+                "u29mul"
+            ]
+        );
+        assert!(registry.get("unknown").is_none());
+
+        // The program picked by name behaves as the original script
+        let program = registry.get("int_add").unwrap();
+        assert_eq!(program.input_size(), U254AddScript::INPUT_SIZE);
+        assert_eq!(program.output_size(), U254AddScript::OUTPUT_SIZE);
+        assert_eq!(program.script(), U254AddScript::script());
+        assert!(program.verify_random());
+
+        let ProgramIOPair { input, output } = program.generate_invalid_io_pair();
+        assert!(!program.verify(input, output));
+
+        let ProgramIOPair { input, output: _ } = program.generate_valid_io_pair();
+        let split_result = program.default_split(input.clone(), SplitType::ByInstructions);
+        let expected_split_result = U254AddScript::default_split(input, SplitType::ByInstructions);
+        assert_eq!(split_result.shards, expected_split_result.shards);
+    }
+}
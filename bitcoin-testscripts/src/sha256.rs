@@ -85,6 +85,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_split_robustness() {
+        const TEST_BYTES_NUM: usize = 32;
+        type SHA256ScriptType = SHA256Script<TEST_BYTES_NUM>;
+
+        // The split made for one random message must work for the other ones as well
+        let report = SHA256ScriptType::check_robustness(SplitType::ByInstructions, 3);
+        assert_eq!(report.inputs_checked, 3);
+        assert!(
+            report.is_robust(),
+            "split diverged: {:?}",
+            report.divergences
+        );
+    }
+
     #[test]
     fn test_naive_split_correctness() {
         // Choosing the number of bytes for the test
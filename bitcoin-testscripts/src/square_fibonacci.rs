@@ -4,7 +4,8 @@
 
 use crate::bitvm::bn254::{fp254impl::Fp254Impl, fq::Fq};
 use bitcoin_splitter::split::{
-    core::{form_states_from_shards, SplitType},
+    core::SplitType,
+    hints::{split_here, AnnotatedScript},
     script::{IOPair, SplitResult, SplitableScript},
 };
 use bitcoin_utils::treepp::*;
@@ -56,13 +57,21 @@ impl<const STEPS: usize> SplitableScript<{ INPUT_SIZE }, { OUTPUT_SIZE }>
     for SquareFibonacciScript<STEPS>
 {
     fn script() -> Script {
-        script! {
-            for _ in 0..STEPS {
-                { SquareFibonacciScript::<STEPS>::transition_script() }
-            }
+        Self::annotated_script().into_script()
+    }
+
+    fn annotated_script() -> AnnotatedScript {
+        let mut script = AnnotatedScript::default();
+        for _ in 0..STEPS {
+            script
+                .push(SquareFibonacciScript::<STEPS>::transition_script())
+                .push(split_here());
+        }
+        script.push(script! {
             { Fq::roll(1) }
             { Fq::drop() }
-        }
+        });
+        script
     }
 
     fn generate_valid_io_pair() -> IOPair<{ INPUT_SIZE }, { OUTPUT_SIZE }> {
@@ -97,23 +106,10 @@ impl<const STEPS: usize> SplitableScript<{ INPUT_SIZE }, { OUTPUT_SIZE }>
         }
     }
 
-    fn default_split(input: Script, _split_type: SplitType) -> SplitResult {
-        // First, form shards
-        let mut shards = vec![Self::transition_script(); STEPS + 1];
-        // Change last one to be { OP_ROLL, OP_DROP } to remove the element before last.
-        if let Some(last) = shards.last_mut() {
-            *last = script! {
-                { Fq::roll(1) }
-                { Fq::drop() }
-            }
-        }
-
-        // Secondly, form the intermediate states and return
-        let intermediate_states = form_states_from_shards(shards.clone(), input);
-        SplitResult {
-            shards,
-            intermediate_states,
-        }
+    fn default_split(input: Script, split_type: SplitType) -> SplitResult {
+        // The script is marked to be cut only after each transition, so
+        // the smallest chunk size gives exactly one transition per shard
+        Self::split(input, split_type, 1)
     }
 }
 
@@ -141,7 +137,12 @@ pub(super) fn square_fq(x: &BigUint) -> BigUint {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use bitcoin_splitter::split::core::SplitType;
+    use bitcoin_splitter::split::{
+        core::SplitType,
+        metric::MaxDisproveCost,
+        observer::ProgressBarObserver,
+        periodic::{periodic_split, LoopStructure, PeriodicRegion, DEFAULT_MIN_PERIOD},
+    };
     use bitcoin_utils::{comparison::OP_LONGEQUALVERIFY, stack_to_script};
 
     #[test]
@@ -222,6 +223,19 @@ mod tests {
         // Splitting the script into shards
         let split_result = FibonacciScript::default_split(input, SplitType::ByInstructions);
 
+        // Split hints give exactly one transition per shard and the final shard
+        assert_eq!(
+            split_result.len(),
+            128 + 1,
+            "one shard per transition expected"
+        );
+        assert_eq!(split_result.shards[0], FibonacciScript::transition_script());
+
+        // All transitions are the same, so there are only two distinct shards
+        let classes = split_result.shard_classes();
+        assert_eq!(classes.len(), 2, "transition shards must be deduplicated");
+        assert_eq!(classes.members(0).count(), 128);
+
         for i in 0..split_result.len() {
             let shard_size = split_result.shards[i].len();
             let stack_size = split_result.intermediate_states[i].stack.len();
@@ -248,6 +262,34 @@ mod tests {
         assert!(result.success, "verification has failed");
     }
 
+    #[test]
+    fn test_periodic_split() {
+        type FibonacciScript = SquareFibonacciScript<16>;
+
+        // The script itself, without the hand-written hints
+        let script = FibonacciScript::script();
+
+        // Transitions are detected as the iterations of the single loop
+        let loops = LoopStructure::detect(&script, DEFAULT_MIN_PERIOD).unwrap();
+        assert_eq!(
+            loops.regions,
+            vec![PeriodicRegion {
+                start: 0,
+                period: FibonacciScript::transition_script().instructions().count(),
+                iterations: 16,
+            }]
+        );
+
+        // Cutting at the detected boundaries gives the same decomposition as the hints
+        let IOPair { input, output } = FibonacciScript::generate_valid_io_pair();
+        let split_result =
+            periodic_split(input.clone(), script.clone(), SplitType::ByInstructions, 1).unwrap();
+        let hinted_result =
+            FibonacciScript::default_split(input.clone(), SplitType::ByInstructions);
+        assert_eq!(split_result.shards, hinted_result.shards);
+        assert_eq!(split_result.verify(&input, &script, &output), Ok(()));
+    }
+
     #[test]
     #[ignore = "too-large computation, run separately"]
     fn test_fuzzy_split() {
@@ -255,8 +297,13 @@ mod tests {
         let IOPair { input, output } = SquareFibonacciScript::<1024>::generate_valid_io_pair();
 
         // Splitting the script into shards
-        let split_result =
-            SquareFibonacciScript::<1024>::fuzzy_split(input, SplitType::ByInstructions);
+        let split_result = SquareFibonacciScript::<1024>::fuzzy_split(
+            input,
+            SplitType::ByInstructions,
+            &MaxDisproveCost::default(),
+            &mut ProgressBarObserver::default(),
+        )
+        .expect("fuzzy split should find a valid split");
 
         for shard in split_result.shards.iter() {
             println!("Shard: {:?}", shard.len());
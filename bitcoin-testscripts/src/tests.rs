@@ -1,10 +1,11 @@
 use bitcoin_splitter::split::{
+    compose::ComposedScript,
     core::{split_into_shards, SplitType},
     script::{IOPair, SplitableScript},
 };
 use bitcoin_utils::treepp::*;
 
-use crate::int_mul_windowed::U254MulScript;
+use crate::{int_mul_windowed::U254MulScript, sha256::SHA256Script};
 
 /// Tests whether splitting the script into subprograms (shards)
 /// works properly for the most advanced script (two big integers multipication)
@@ -44,3 +45,30 @@ fn test_split_mul() {
     let result = execute_script(verification_script);
     assert!(result.success, "Verification has failed");
 }
+
+/// Tests that the composition of two scripts (double SHA-256 here)
+/// is verified and split as the chain of its components
+#[test]
+fn test_composed_script() {
+    const HASH_SIZE: usize = 32;
+    type HashScript = SHA256Script<HASH_SIZE>;
+    type DoubleHashScript = ComposedScript<HashScript, HashScript, HASH_SIZE>;
+
+    assert!(DoubleHashScript::verify_random(), "verification has failed");
+    let IOPair { input, output } = DoubleHashScript::generate_invalid_io_pair();
+    assert!(!DoubleHashScript::verify(input, output));
+
+    // The composition keeps the split points of both components
+    let IOPair { input, output } = DoubleHashScript::generate_valid_io_pair();
+    let split_result = DoubleHashScript::default_split(input.clone(), SplitType::ByInstructions);
+    let first_split = HashScript::default_split(input.clone(), SplitType::ByInstructions);
+    assert_eq!(
+        split_result.shards[..first_split.len()],
+        first_split.shards[..]
+    );
+    assert_eq!(split_result.len(), 2 * first_split.len());
+    assert_eq!(
+        split_result.verify(&input, &DoubleHashScript::script(), &output),
+        Ok(())
+    );
+}
@@ -1,6 +1,8 @@
 use crate::treepp;
 use bitcoin::{hashes::Hash, ScriptBuf, TapLeafHash, Transaction};
-use bitcoin_scriptexec::{Exec, ExecCtx, ExecError, ExecStats, Options, Stack, TxTemplate};
+use bitcoin_scriptexec::{
+    Exec, ExecCtx, ExecError, ExecStats, Experimental, Options, Stack, TxTemplate,
+};
 use core::fmt;
 
 /// Information about the status of the script execution.
@@ -42,18 +44,40 @@ impl fmt::Display for ExecuteInfo {
     }
 }
 
-/// Executes the given script and returns the result of the execution
-/// (success, error, stack, etc.)
-pub fn execute_script(script: ScriptBuf) -> ExecuteInfo {
-    let mut exec = Exec::new(
+/// Creates the execution body for the given script with the same options
+/// that [`execute_script`] uses, so that the caller can step through the
+/// script instruction by instruction.
+pub fn script_exec(script: ScriptBuf) -> Exec {
+    script_exec_with_stack(script, Stack::new(), Stack::new())
+}
+
+/// Same as [`script_exec`], but starts the execution with the given
+/// stack and altstack instead of the empty ones.
+pub fn script_exec_with_stack(script: ScriptBuf, stack: Stack, altstack: Stack) -> Exec {
+    script_exec_with_options(script, stack, altstack, default_options())
+}
+
+/// Options of the execution used by [`script_exec`] and [`execute_script`]
+fn default_options() -> Options {
+    Options {
+        // TODO(ZamDimon): Figure our how to optimize stack_to_script function to avoid disabling require_minimal
+        // TODO(ZamDimon): Currently, Winternitz does not work with the stack limit
+        require_minimal: false,
+        enforce_stack_limit: false,
+        ..Default::default()
+    }
+}
+
+/// Creates the execution body for the given script with the given options
+fn script_exec_with_options(
+    script: ScriptBuf,
+    stack: Stack,
+    altstack: Stack,
+    options: Options,
+) -> Exec {
+    Exec::with_stack(
         ExecCtx::Tapscript,
-        Options {
-            // TODO(ZamDimon): Figure our how to optimize stack_to_script function to avoid disabling require_minimal
-            // TODO(ZamDimon): Currently, Winternitz does not work with the stack limit
-            require_minimal: false,
-            enforce_stack_limit: false,
-            ..Default::default()
-        },
+        options,
         TxTemplate {
             tx: Transaction {
                 version: bitcoin::transaction::Version::TWO,
@@ -67,9 +91,35 @@ pub fn execute_script(script: ScriptBuf) -> ExecuteInfo {
         },
         script,
         vec![],
+        stack,
+        altstack,
     )
-    .expect("error when creating the execution body");
+    .expect("error when creating the execution body")
+}
+
+/// Executes the given script and returns the result of the execution
+/// (success, error, stack, etc.)
+pub fn execute_script(script: ScriptBuf) -> ExecuteInfo {
+    execute(script_exec(script))
+}
+
+/// Same as [`execute_script`], but with `OP_CAT` disabled as it is on the mainnet,
+/// which is needed to check the scripts that must be spendable on-chain
+pub fn execute_script_without_op_cat(script: ScriptBuf) -> ExecuteInfo {
+    let options = Options {
+        experimental: Experimental { op_cat: false },
+        ..default_options()
+    };
+    execute(script_exec_with_options(
+        script,
+        Stack::new(),
+        Stack::new(),
+        options,
+    ))
+}
 
+/// Executes all the opcodes of the given execution body while possible
+fn execute(mut exec: Exec) -> ExecuteInfo {
     // Execute all the opcodes while possible
     loop {
         if exec.exec_next().is_err() {
@@ -4,6 +4,7 @@ use treepp::*;
 pub mod comparison;
 pub mod debug;
 pub mod pseudo;
+pub mod stack_effect;
 
 #[allow(dead_code)]
 // Re-export what is needed to write treepp scripts
@@ -0,0 +1,404 @@
+//! Static analysis of the stack effect of the script.
+//!
+//! Almost every opcode takes and pushes the fixed number of elements regardless of their
+//! values, so the depths of the stack and altstack after each instruction can be computed
+//! without executing the script. The exceptions are the conditional blocks, whose branches
+//! may leave different depths, and a few opcodes like `OP_IFDUP` whose effect depends on
+//! the values. Depths after them are flagged as input-dependent.
+
+use bitcoin::{
+    opcodes::{all::*, Opcode},
+    script::{self, Instruction},
+};
+
+use crate::treepp::*;
+
+/// Number of elements the instruction takes from and pushes to the stack,
+/// together with the change of the altstack depth
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackEffect {
+    /// Number of elements taken from the stack
+    pub pops: usize,
+    /// Number of elements pushed to the stack
+    pub pushes: usize,
+    /// Change of the altstack depth
+    pub altstack: isize,
+}
+
+impl StackEffect {
+    /// Effect of the instruction touching only the stack
+    const fn stack(pops: usize, pushes: usize) -> Self {
+        Self {
+            pops,
+            pushes,
+            altstack: 0,
+        }
+    }
+
+    /// Returns the change of the stack depth
+    pub fn stack_delta(&self) -> isize {
+        self.pushes as isize - self.pops as isize
+    }
+}
+
+/// Returns the stack effect of the instruction executed outside of the conditional block
+/// handling. Returns [`None`] for the opcodes whose effect depends on the values
+/// (`OP_IFDUP`, `OP_CHECKMULTISIG`) or which always fail in Tapscript. Note that
+/// `OP_PICK` and `OP_ROLL` read the element at the depth given by the top of the stack,
+/// but their effect on the depth is fixed.
+pub fn instruction_effect(instruction: &Instruction) -> Option<StackEffect> {
+    let op = match instruction {
+        Instruction::PushBytes(_) => return Some(StackEffect::stack(0, 1)),
+        Instruction::Op(op) => *op,
+    };
+
+    let effect = match op {
+        OP_PUSHNUM_NEG1 | OP_PUSHNUM_1 | OP_PUSHNUM_2 | OP_PUSHNUM_3 | OP_PUSHNUM_4
+        | OP_PUSHNUM_5 | OP_PUSHNUM_6 | OP_PUSHNUM_7 | OP_PUSHNUM_8 | OP_PUSHNUM_9
+        | OP_PUSHNUM_10 | OP_PUSHNUM_11 | OP_PUSHNUM_12 | OP_PUSHNUM_13 | OP_PUSHNUM_14
+        | OP_PUSHNUM_15 | OP_PUSHNUM_16 | OP_DEPTH => StackEffect::stack(0, 1),
+
+        // Control
+        OP_NOP | OP_NOP1 | OP_CLTV | OP_CSV | OP_NOP4 | OP_NOP5 | OP_NOP6 | OP_NOP7 | OP_NOP8
+        | OP_NOP9 | OP_NOP10 | OP_ELSE | OP_ENDIF | OP_RETURN | OP_CODESEPARATOR => {
+            StackEffect::stack(0, 0)
+        }
+        OP_IF | OP_NOTIF | OP_VERIFY | OP_DROP => StackEffect::stack(1, 0),
+
+        // Altstack
+        OP_TOALTSTACK => StackEffect {
+            pops: 1,
+            pushes: 0,
+            altstack: 1,
+        },
+        OP_FROMALTSTACK => StackEffect {
+            pops: 0,
+            pushes: 1,
+            altstack: -1,
+        },
+
+        // Stack manipulation
+        OP_2DROP => StackEffect::stack(2, 0),
+        OP_2DUP => StackEffect::stack(2, 4),
+        OP_3DUP => StackEffect::stack(3, 6),
+        OP_2OVER => StackEffect::stack(4, 6),
+        OP_2ROT => StackEffect::stack(6, 6),
+        OP_2SWAP => StackEffect::stack(4, 4),
+        OP_DUP => StackEffect::stack(1, 2),
+        OP_NIP => StackEffect::stack(2, 1),
+        OP_OVER => StackEffect::stack(2, 3),
+        OP_PICK => StackEffect::stack(1, 1),
+        OP_ROLL => StackEffect::stack(1, 0),
+        OP_ROT => StackEffect::stack(3, 3),
+        OP_SWAP => StackEffect::stack(2, 2),
+        OP_TUCK => StackEffect::stack(2, 3),
+        OP_SIZE => StackEffect::stack(1, 2),
+
+        // Arithmetic and crypto
+        OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL | OP_RIPEMD160 | OP_SHA1
+        | OP_SHA256 | OP_HASH160 | OP_HASH256 => StackEffect::stack(1, 1),
+        OP_CAT
+        | OP_EQUAL
+        | OP_ADD
+        | OP_SUB
+        | OP_BOOLAND
+        | OP_BOOLOR
+        | OP_NUMEQUAL
+        | OP_NUMNOTEQUAL
+        | OP_LESSTHAN
+        | OP_GREATERTHAN
+        | OP_LESSTHANOREQUAL
+        | OP_GREATERTHANOREQUAL
+        | OP_MIN
+        | OP_MAX
+        | OP_CHECKSIG => StackEffect::stack(2, 1),
+        OP_EQUALVERIFY | OP_NUMEQUALVERIFY | OP_CHECKSIGVERIFY => StackEffect::stack(2, 0),
+        OP_WITHIN | OP_CHECKSIGADD => StackEffect::stack(3, 1),
+
+        _ => return None,
+    };
+
+    Some(effect)
+}
+
+/// Depths of the stack and altstack relative to the start of the script
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Depth {
+    pub stack: isize,
+    pub altstack: isize,
+}
+
+impl Depth {
+    /// Returns the depth after the instruction with the given effect
+    fn apply(self, effect: StackEffect) -> Self {
+        Self {
+            stack: self.stack + effect.stack_delta(),
+            altstack: self.altstack + effect.altstack,
+        }
+    }
+}
+
+/// Depth at the instruction boundary as far as it is known statically
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaticDepth {
+    /// Depth does not depend on the input
+    Known(Depth),
+    /// Depth depends on the values in the stack
+    InputDependent,
+}
+
+impl StaticDepth {
+    /// Returns the depth if it is known
+    pub fn known(&self) -> Option<Depth> {
+        match self {
+            StaticDepth::Known(depth) => Some(*depth),
+            StaticDepth::InputDependent => None,
+        }
+    }
+}
+
+/// Why the depth became input-dependent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DependenceReason {
+    /// Branches of the conditional block leave different depths
+    UnbalancedBranches,
+    /// Effect of the opcode depends on the values
+    ValueDependentOpcode(Opcode),
+    /// `OP_ELSE` or `OP_ENDIF` outside of any conditional block, or the block
+    /// with several `OP_ELSE`
+    MalformedConditional,
+}
+
+/// Instruction after which the depth becomes input-dependent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DependenceFlag {
+    /// Index of the instruction
+    pub instruction: usize,
+    pub reason: DependenceReason,
+}
+
+/// Conditional block open at the current instruction
+struct Branches {
+    /// Depth right after `OP_IF` or `OP_NOTIF`, at which every branch starts
+    start: StaticDepth,
+    /// Depths at the ends of the already closed branches
+    ends: Vec<StaticDepth>,
+}
+
+/// Stack effect of every instruction of the script, computed without executing it.
+///
+/// Similarly to the profile of the execution, boundary `k` is the point right before
+/// the `k`-th instruction, so the script with `n` instructions has `n+1` boundaries.
+/// Depths inside the conditional branch are the ones the branch would have if executed.
+/// Once the depth becomes input-dependent, it stays so until the end of the script,
+/// apart from the following branches of the enclosing conditional blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackAnalysis {
+    /// Depth at each boundary relative to the start of the script
+    pub depths: Vec<StaticDepth>,
+    /// Instructions making the depth input-dependent
+    pub flags: Vec<DependenceFlag>,
+}
+
+impl StackAnalysis {
+    /// Analyzes the stack effect of the script
+    pub fn analyze(script: &Script) -> Result<Self, script::Error> {
+        let mut depths = vec![StaticDepth::Known(Depth::default())];
+        let mut flags = vec![];
+        let mut blocks: Vec<Branches> = vec![];
+        let mut current = StaticDepth::Known(Depth::default());
+
+        for (instruction_id, instruction) in script.instructions().enumerate() {
+            let instruction = instruction?;
+            let mut reason = None;
+
+            let next = match instruction {
+                Instruction::Op(OP_ELSE) => match blocks.last_mut() {
+                    Some(block) if block.ends.is_empty() => {
+                        block.ends.push(current);
+                        block.start
+                    }
+                    _ => {
+                        reason = Some(DependenceReason::MalformedConditional);
+                        StaticDepth::InputDependent
+                    }
+                },
+                Instruction::Op(OP_ENDIF) => match blocks.pop() {
+                    Some(mut block) => {
+                        // Block without `OP_ELSE` may be skipped entirely
+                        if block.ends.is_empty() {
+                            block.ends.push(block.start);
+                        }
+                        block.ends.push(current);
+
+                        if block.ends.windows(2).all(|ends| ends[0] == ends[1]) {
+                            current
+                        } else {
+                            // Otherwise, the dependence is already flagged inside the block
+                            if block.ends.iter().all(|end| end.known().is_some()) {
+                                reason = Some(DependenceReason::UnbalancedBranches);
+                            }
+                            StaticDepth::InputDependent
+                        }
+                    }
+                    None => {
+                        reason = Some(DependenceReason::MalformedConditional);
+                        StaticDepth::InputDependent
+                    }
+                },
+                instruction => match (current, instruction_effect(&instruction)) {
+                    (StaticDepth::Known(depth), Some(effect)) => {
+                        StaticDepth::Known(depth.apply(effect))
+                    }
+                    (StaticDepth::Known(_), None) => {
+                        if let Instruction::Op(op) = instruction {
+                            reason = Some(DependenceReason::ValueDependentOpcode(op));
+                        }
+                        StaticDepth::InputDependent
+                    }
+                    (StaticDepth::InputDependent, _) => StaticDepth::InputDependent,
+                },
+            };
+
+            if let Instruction::Op(OP_IF | OP_NOTIF) = instruction {
+                blocks.push(Branches {
+                    start: next,
+                    ends: vec![],
+                });
+            }
+            if let (StaticDepth::Known(_), Some(reason)) = (current, reason) {
+                flags.push(DependenceFlag {
+                    instruction: instruction_id,
+                    reason,
+                });
+            }
+
+            current = next;
+            depths.push(current);
+        }
+
+        Ok(Self { depths, flags })
+    }
+
+    /// Returns the number of instructions in the analyzed script
+    pub fn len(&self) -> usize {
+        self.depths.len() - 1
+    }
+
+    /// Returns whether the analyzed script is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns whether the depth at every boundary is known
+    pub fn is_static(&self) -> bool {
+        self.flags.is_empty()
+    }
+
+    /// Returns the number of elements in the stack and altstack together at each
+    /// boundary, given their numbers at the start of the script. The boundaries with
+    /// the input-dependent depth, as well as the ones at which the script would take
+    /// more elements than there are, give [`None`].
+    pub fn state_sizes(&self, stack: usize, altstack: usize) -> Vec<Option<usize>> {
+        self.depths
+            .iter()
+            .map(|depth| {
+                let depth = depth.known()?;
+                let stack = stack.checked_add_signed(depth.stack)?;
+                let altstack = altstack.checked_add_signed(depth.altstack)?;
+                Some(stack + altstack)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debug::script_exec;
+
+    #[test]
+    fn test_branch_free_depths_match_execution() {
+        let script = script! {
+            OP_1 OP_2 OP_3
+            OP_2DUP OP_ADD
+            OP_TOALTSTACK
+            OP_ROT OP_ROT
+            { 2 } OP_PICK
+            OP_FROMALTSTACK
+            OP_2 OP_ROLL
+            OP_2DROP
+            OP_SHA256
+            OP_DEPTH
+        };
+
+        let analysis = StackAnalysis::analyze(&script).unwrap();
+        assert!(analysis.is_static());
+        assert_eq!(analysis.len(), script.instructions().count());
+
+        // Comparing with the depths reached by the actual execution
+        let mut exec = script_exec(script);
+        for depth in analysis.depths.iter().skip(1) {
+            exec.exec_next().unwrap();
+            let expected = Depth {
+                stack: exec.stack().len() as isize,
+                altstack: exec.altstack().len() as isize,
+            };
+            assert_eq!(depth, &StaticDepth::Known(expected));
+        }
+    }
+
+    #[test]
+    fn test_conditional_depths() {
+        // Both branches leave one element, so the depth after the block is known
+        let balanced = script! {
+            OP_1
+            OP_IF
+                OP_2
+            OP_ELSE
+                OP_3
+            OP_ENDIF
+        };
+        let analysis = StackAnalysis::analyze(&balanced).unwrap();
+        assert!(analysis.is_static());
+        assert_eq!(
+            analysis.depths.last(),
+            Some(&StaticDepth::Known(Depth {
+                stack: 1,
+                altstack: 0
+            }))
+        );
+        assert_eq!(analysis.state_sizes(2, 1).last(), Some(&Some(4)));
+
+        // The block without `OP_ELSE` pushing an element is input-dependent
+        let unbalanced = script! {
+            OP_DUP
+            OP_IF
+                OP_DUP
+            OP_ENDIF
+            OP_DROP
+        };
+        let analysis = StackAnalysis::analyze(&unbalanced).unwrap();
+        assert_eq!(
+            analysis.flags,
+            vec![DependenceFlag {
+                instruction: 3,
+                reason: DependenceReason::UnbalancedBranches,
+            }]
+        );
+        assert_eq!(
+            analysis.state_sizes(1, 0),
+            vec![Some(1), Some(2), Some(1), Some(2), None, None]
+        );
+
+        // Value-dependent opcodes are flagged as well
+        let analysis = StackAnalysis::analyze(&script! { OP_IFDUP OP_DROP }).unwrap();
+        assert_eq!(
+            analysis.flags,
+            vec![DependenceFlag {
+                instruction: 0,
+                reason: DependenceReason::ValueDependentOpcode(OP_IFDUP),
+            }]
+        );
+    }
+}
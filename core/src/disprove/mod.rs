@@ -3,9 +3,11 @@ use bitcoin_utils::{comparison::OP_LONGNOTEQUAL, stack_to_script, treepp::*};
 use signing::SignedIntermediateState;
 
 use bitcoin_splitter::split::{
-    core::SplitType, intermediate_state::IntermediateState, script::SplitableScript,
+    core::SplitType, error::EncodingError, hinted::HintedSplitResult,
+    intermediate_state::IntermediateState, script::SplitableScript,
 };
 
+pub mod model;
 pub mod signing;
 
 #[cfg(test)]
@@ -19,6 +21,7 @@ mod tests;
 /// The script structure in general is simple:
 /// ## Witness:
 /// ```bitcoin_script
+/// { hints[i] }                 // Only for hinted scripts, see [`DisproveScript::with_hints`]
 /// { Enc(z[i+1]) and Sig[i+1] } // Zipped
 /// { Enc(z[i]) and Sig[i] }     // Zipped
 /// ```
@@ -46,16 +49,42 @@ pub struct DisproveScript {
 impl DisproveScript {
     /// Given the previous and current states, and the function that was executed,
     /// creates a new DisproveScript according to the BitVM2 protocol.
-    pub fn new(from: &IntermediateState, to: &IntermediateState, function: &Script) -> Self {
+    ///
+    /// Returns an error if some of the states has a wide element which is not a script
+    /// number (for instance, a hash): without `OP_CAT` the script cannot restore it from
+    /// the signed words, so such a transition cannot be disproved.
+    pub fn new(
+        from: &IntermediateState,
+        to: &IntermediateState,
+        function: &Script,
+    ) -> Result<Self, EncodingError> {
+        Self::with_hints(from, to, function, &Script::new())
+    }
+
+    /// Same as [`DisproveScript::new`], but the function consumes the given hints
+    /// (see [`bitcoin_splitter::split::hinted`]). Hints are not signed: they are pushed
+    /// to the witness below the signed states, so that the function takes them from
+    /// the bottom of the stack, and whoever spends the script chooses them freely.
+    /// Thus, the function must check every hint it consumes, otherwise the wrong hint
+    /// makes the correct transition look incorrect. The shards of
+    /// [`hinted_split`](bitcoin_splitter::split::hinted::hinted_split) do so, since it
+    /// never cuts the hinted gadget before its checks.
+    pub fn with_hints(
+        from: &IntermediateState,
+        to: &IntermediateState,
+        function: &Script,
+        hints: &Script,
+    ) -> Result<Self, EncodingError> {
         // Step 1.
         // First, we sign the states
-        let from_signed = SignedIntermediateState::sign(from);
-        let to_signed = SignedIntermediateState::sign(to);
+        let from_signed = SignedIntermediateState::try_sign(from)?;
+        let to_signed = SignedIntermediateState::try_sign(to)?;
 
         // Step 2.
         // Now, we form the witness script. Just pushing all
         // signatures + messages to the witness script
         let script_witness = script! {
+            { hints.clone() }                // Hints consumed by fn[i]
             { from_signed.witness_script() } // Zipped Enc(z[i]) and Sig[i]
             { to_signed.witness_script() }   // Zipped Enc(z[i+1]) and Sig[i+1]
         };
@@ -69,7 +98,7 @@ impl DisproveScript {
 
             // 2. Applying function and popping "to" state
             { function.clone() } // This leaves f[i](z[i]).mainstack in the mainstack and { z[i+1].altstack, f[i](z[i]).altstack } in the altstack
-            for _ in 0..to_signed.altstack.len() {
+            for _ in 0..to_signed.altstack_len() {
                 OP_FROMALTSTACK
             }
             { to_signed.verification_script_fromaltstack() } // This leaves z[i+1].mainstack and f[i](z[i]).mainstack in the mainstack, while f[i](z[i]).altstack and z[i+1].alstack is in the altstack
@@ -78,8 +107,8 @@ impl DisproveScript {
             // { f[i](z[i]).mainstack, f[i](z[i]).altstack, z[i+1].mainstack }
             // while the altstack has z[i+1].altstack.
             // Thus, we have to pick f[i](z[i]).mainstack to the top of the stack
-            for _ in (0..to_signed.stack.len()).rev() {
-                { to_signed.total_len() + to_signed.stack.len() - 1 } OP_ROLL
+            for _ in (0..to_signed.stack_len()).rev() {
+                { to_signed.total_len() + to_signed.stack_len() - 1 } OP_ROLL
             }
 
             // At this point, we should have
@@ -87,28 +116,29 @@ impl DisproveScript {
 
             // 3. Checking if z[i+1] == f(z[i])
             // 3.1. Mainstack verification
-            { OP_LONGNOTEQUAL(to_signed.stack.len()) }
+            { OP_LONGNOTEQUAL(to_signed.stack_len()) }
 
             // 3.2. Altstack verification
-            for _ in 0..to_signed.altstack.len() {
+            { to_signed.decode_altstack_script() }
+            for _ in 0..to_signed.altstack_len() {
                 OP_FROMALTSTACK
             }
 
             // Since currently our stack looks like:
             // { f[i](z[i]).altstack, {bit}, z[i+1].altstack, },
             // we need to push f[i](z[i]).altstack to the top of the stack
-            for _ in 0..to_signed.altstack.len() {
-                { 2*to_signed.altstack.len() } OP_ROLL
+            for _ in 0..to_signed.altstack_len() {
+                { 2*to_signed.altstack_len() } OP_ROLL
             }
 
-            { OP_LONGNOTEQUAL(to_signed.altstack.len()) }
+            { OP_LONGNOTEQUAL(to_signed.altstack_len()) }
             OP_BOOLOR
         };
 
-        Self {
+        Ok(Self {
             script_witness,
             script_pubkey,
-        }
+        })
     }
 }
 
@@ -116,13 +146,15 @@ impl DisproveScript {
 /// - Splits the script into shards
 /// - For each shard, creates a DisproveScript
 /// - Returns the list of DisproveScripts
+///
+/// Returns an error if some intermediate state cannot be restored, see [`DisproveScript::new`].
 pub fn form_disprove_scripts<
     const INPUT_SIZE: usize,
     const OUTPUT_SIZE: usize,
     S: SplitableScript<INPUT_SIZE, OUTPUT_SIZE>,
 >(
     input: Script,
-) -> Vec<DisproveScript> {
+) -> Result<Vec<DisproveScript>, EncodingError> {
     // Splitting the script into shards
     let split_result = S::default_split(input.clone(), SplitType::default());
 
@@ -149,6 +181,33 @@ pub fn form_disprove_scripts<
         .collect()
 }
 
+/// Given the hinted script split with the input, creates the DisproveScript
+/// for each shard, supplying the hints consumed by the shard through the witness.
+/// Returns an error if some intermediate state cannot be restored, see [`DisproveScript::new`].
+pub fn form_hinted_disprove_scripts(
+    input: Script,
+    hinted_split: &HintedSplitResult,
+) -> Result<Vec<DisproveScript>, EncodingError> {
+    let split_result = &hinted_split.split_result;
+
+    (0..split_result.shards.len())
+        .map(|i| {
+            let from_state = if i == 0 {
+                IntermediateState::from_inject_script(&input)
+            } else {
+                split_result.intermediate_states[i - 1].clone()
+            };
+
+            DisproveScript::with_hints(
+                &from_state,
+                &split_result.intermediate_states[i],
+                &split_result.shards[i],
+                &hinted_split.shard_hints[i],
+            )
+        })
+        .collect()
+}
+
 /// Given the script and its input, does the following:
 /// - Splits the script into shards
 /// - Distorts the random intermediate state, making
@@ -161,7 +220,7 @@ pub fn form_disprove_scripts_distorted<
     S: SplitableScript<INPUT_SIZE, OUTPUT_SIZE>,
 >(
     input: Script,
-) -> (Vec<DisproveScript>, usize) {
+) -> Result<(Vec<DisproveScript>, usize), EncodingError> {
     // Splitting the script into shards
     let mut split_result = S::default_split(input.clone(), SplitType::default());
 
@@ -201,7 +260,7 @@ pub fn form_disprove_scripts_distorted<
                 &split_result.shards[i],
             )
         })
-        .collect();
+        .collect::<Result<_, _>>()?;
 
-    (disprove_scripts, distorted_shard_id)
+    Ok((disprove_scripts, distorted_shard_id))
 }
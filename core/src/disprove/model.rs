@@ -0,0 +1,116 @@
+//! Module measuring the resources taken by the disprove scripts, so that the splitter
+//! can pick the shards whose disprove scripts fit into the Tapscript limits
+//! (see [`bitcoin_splitter::split::constraints`]).
+
+use bitcoin_splitter::split::{constraints::DisproveModel, intermediate_state::IntermediateState};
+use bitcoin_utils::{debug::script_exec_with_stack, treepp::*};
+
+use super::DisproveScript;
+
+/// Number of elements in the states the model is measured on. The growth of the leaf script
+/// is measured between the states of this size and of twice this size, where all pushes of
+/// the element indices already take three bytes, as they do for any state within the stack limit.
+const SAMPLE_SIZE: usize = 128;
+
+/// Largest word of the signed state
+const SAMPLE_WORD: u32 = (1 << 31) - 1;
+
+/// Returns the state with the given number of single-word elements,
+/// placed either in the stack or in the altstack
+fn sample_state(size: usize, in_altstack: bool) -> IntermediateState {
+    IntermediateState::from_input_script(
+        &script! {},
+        &script! {
+            for _ in 0..size {
+                { SAMPLE_WORD }
+                if in_altstack {
+                    OP_TOALTSTACK
+                }
+            }
+        },
+    )
+}
+
+/// Returns the largest number of items in the stack and altstack together
+/// while the witness of the disprove script is verified
+pub fn peak_stack_items(disprove_script: &DisproveScript) -> usize {
+    let script = script! {
+        { disprove_script.script_witness.clone() }
+        { disprove_script.script_pubkey.clone() }
+    };
+    let empty = sample_state(0, false);
+
+    let mut exec = script_exec_with_stack(script, empty.stack, empty.altstack);
+    let mut peak = 0;
+    loop {
+        peak = peak.max(exec.stack().len() + exec.altstack().len());
+        if exec.exec_next().is_err() {
+            break;
+        }
+    }
+
+    peak
+}
+
+/// Measures the [`DisproveModel`] of the scripts built by [`DisproveScript::new`].
+///
+/// The leaf script and the witness grow linearly with the number of signed words, apart
+/// from the pushes of the element indices, which only get longer with the larger states.
+/// Thus, the coefficients are the per-element growth of the scripts on the largest sample
+/// states, rounded up, for the worse of the stack and the altstack placement.
+pub fn disprove_model() -> DisproveModel {
+    let empty = sample_state(0, false);
+    let empty_function = script! {};
+    let disprove_script = |from: &IntermediateState, to: &IntermediateState| {
+        DisproveScript::new(from, to, &empty_function).expect("sample states consist of numbers")
+    };
+
+    let base = disprove_script(&empty, &empty);
+    let leaf_overhead = base.script_pubkey.len();
+
+    // Growth of the leaf script per element between the sample states
+    let growth = |small: &DisproveScript, large: &DisproveScript| {
+        (large.script_pubkey.len() - small.script_pubkey.len()).div_ceil(SAMPLE_SIZE)
+    };
+
+    let mut leaf_bytes_per_input_element = 0;
+    let mut leaf_bytes_per_output_element = 0;
+    for in_altstack in [false, true] {
+        let small = sample_state(SAMPLE_SIZE, in_altstack);
+        let large = sample_state(2 * SAMPLE_SIZE, in_altstack);
+
+        leaf_bytes_per_input_element = leaf_bytes_per_input_element.max(growth(
+            &disprove_script(&small, &empty),
+            &disprove_script(&large, &empty),
+        ));
+        leaf_bytes_per_output_element = leaf_bytes_per_output_element.max(growth(
+            &disprove_script(&empty, &small),
+            &disprove_script(&empty, &large),
+        ));
+    }
+
+    let state = sample_state(SAMPLE_SIZE, false);
+    let sample_script = disprove_script(&state, &state);
+    let witness_bytes_per_element = sample_script.script_witness.len().div_ceil(2 * SAMPLE_SIZE);
+    let witness_items_per_element = sample_script
+        .script_witness
+        .instructions()
+        .count()
+        .div_ceil(2 * SAMPLE_SIZE);
+
+    // Items on top of the witness (or of both states during the shard execution)
+    // taken by the hash chains and the recovery of the words
+    let sample_items = (witness_items_per_element * 2 * SAMPLE_SIZE).max(2 * SAMPLE_SIZE);
+    let verification_items = peak_stack_items(&sample_script)
+        .saturating_sub(sample_items)
+        .max(peak_stack_items(&base));
+
+    DisproveModel {
+        leaf_overhead,
+        leaf_bytes_per_input_element,
+        leaf_bytes_per_output_element,
+        witness_bytes_per_element,
+        witness_items_per_element,
+        verification_items,
+    }
+}
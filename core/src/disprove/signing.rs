@@ -1,6 +1,10 @@
 use bitcoin_utils::treepp::*;
 
-use bitcoin_splitter::split::intermediate_state::IntermediateState;
+use bitcoin_splitter::split::{
+    encoding::{decode_element_script, group_words, TAG_FLAG},
+    error::EncodingError,
+    intermediate_state::IntermediateState,
+};
 use bitcoin_winternitz::u32::{checksig_verify_script, Message, PublicKey, SecretKey, Signature};
 use rand::{rngs::SmallRng, SeedableRng};
 
@@ -45,7 +49,9 @@ impl SignedStackElement {
 /// Struct holding the intermediate state of the script execution.
 ///
 /// Note that the intermediate state itself is just an array of
-/// u32 values (both in mainstack and altstack), but this struct
+/// u32 values (both in mainstack and altstack), where elements
+/// wider than a single word are encoded by several values
+/// (see [`bitcoin_splitter::split::encoding`]), but this struct
 /// also contains the public keys, secret keys, and signatures
 /// of the elements in the state array.
 #[derive(Clone, Debug)]
@@ -55,12 +61,34 @@ pub struct SignedIntermediateState {
 }
 
 impl SignedIntermediateState {
-    /// Creates a new IntermediateStateHolder from the given intermediate state
+    /// Creates a new IntermediateStateHolder from the given intermediate state.
+    ///
+    /// Panics if the verification script cannot restore the state, see
+    /// [`SignedIntermediateState::try_sign`] for the non-panicking version.
     pub fn sign(state: &IntermediateState) -> Self {
+        Self::try_sign(state).unwrap_or_else(|err| panic!("failed to sign the state: {:?}", err))
+    }
+
+    /// Creates a new IntermediateStateHolder from the given intermediate state.
+    ///
+    /// Elements are signed in their word encoding, where negative numbers keep
+    /// their sign (see [`bitcoin_splitter::split::encoding`]), so the state
+    /// restored by the verification script is exactly the original one. Returns
+    /// an error if the state has a wide element which is not a script number
+    /// (for instance, a hash), since Tapscript cannot restore it.
+    pub fn try_sign(state: &IntermediateState) -> Result<Self, EncodingError> {
         let stack = state.to_bytes().stack_as_u32();
         let altstack = state.to_bytes().altstack_as_u32();
 
-        // Now, verifying that all elements are below 1<<31 - 1
+        for element in group_words(&stack)
+            .into_iter()
+            .chain(group_words(&altstack))
+        {
+            decode_element_script(element, true)?;
+        }
+
+        // Now, verifying that all words are below 1<<31 - 1. The encoding
+        // guarantees it for any element, including the negative numbers.
         for element in stack.iter().chain(altstack.iter()) {
             assert!(*element <= MAX_STACK_ELEMENT_VALUE, "element is too large");
         }
@@ -69,12 +97,22 @@ impl SignedIntermediateState {
         let stack = stack.into_iter().map(SignedStackElement::sign).collect();
         let altstack = altstack.into_iter().map(SignedStackElement::sign).collect();
 
-        Self { stack, altstack }
+        Ok(Self { stack, altstack })
+    }
+
+    /// Returns the number of elements in the stack
+    pub fn stack_len(&self) -> usize {
+        group_words(&signed_words(&self.stack)).len()
+    }
+
+    /// Returns the number of elements in the altstack
+    pub fn altstack_len(&self) -> usize {
+        group_words(&signed_words(&self.altstack)).len()
     }
 
-    /// Returns the total length of the stack and altstack
+    /// Returns the total number of elements in the stack and altstack
     pub fn total_len(&self) -> usize {
-        self.stack.len() + self.altstack.len()
+        self.stack_len() + self.altstack_len()
     }
 
     /// Script that pushes zipped signature and message to the stack for
@@ -121,10 +159,33 @@ impl SignedIntermediateState {
         script! {
             // Currently, the altstack contains the following elements:
             // { altstack_elements, stack_elements }
-            // Thus, we can simply pop top stack elements and call it a day
-            for _ in 0..self.stack.len() {
+            // Thus, we can simply pop top stack elements (restoring the wide ones)
+            // and call it a day
+            for element in group_words(&signed_words(&self.stack)) {
+                { decode_element_script(element, true).expect("checked when signing") }
+            }
+        }
+    }
+
+    /// Script that restores the altstack elements encoded by the tagged words
+    /// (the wide elements and the negative numbers), assuming the altstack words
+    /// are on top of the altstack. If all elements are plain words, the script is empty.
+    pub fn decode_altstack_script(&self) -> Script {
+        let words = signed_words(&self.altstack);
+        if words.iter().all(|&word| word < TAG_FLAG) {
+            return Script::new();
+        }
+
+        script! {
+            // Moving the words to the mainstack, so the first word is on top
+            for _ in 0..words.len() {
                 OP_FROMALTSTACK
             }
+
+            for element in group_words(&words) {
+                { decode_element_script(element, false).expect("checked when signing") }
+                OP_TOALTSTACK
+            }
         }
     }
 
@@ -135,6 +196,15 @@ impl SignedIntermediateState {
         script! {
             { self.verification_script_toaltstack() }
             { self.verification_script_fromaltstack() }
+            { self.decode_altstack_script() }
         }
     }
 }
+
+/// Returns the signed words of the given elements
+fn signed_words(elements: &[SignedStackElement]) -> Vec<u32> {
+    elements
+        .iter()
+        .map(|element| element.stack_element)
+        .collect()
+}
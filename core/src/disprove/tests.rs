@@ -1,20 +1,35 @@
-use crate::disprove::{form_disprove_scripts_distorted, DisproveScript};
+use crate::disprove::{
+    form_disprove_scripts_distorted, form_hinted_disprove_scripts, DisproveScript,
+};
 
 use bitcoin_splitter::split::{
-    core::SplitType,
+    constraints::{constrained_split, SplitConstraints},
+    core::{naive_split, SplitType},
+    error::EncodingError,
+    hinted::{hinted_split, HintedScript, HintedSplitableScript},
     intermediate_state::IntermediateState,
     script::{IOPair, SplitableScript},
 };
 use bitcoin_testscripts::{
-    bitvm::bn254::{fp254impl::Fp254Impl, fq::Fq},
+    bitvm::{
+        bigint::U64,
+        bn254::{fp254impl::Fp254Impl, fq::Fq},
+    },
+    fq_square_chain::FqSquareChainScript,
     int_mul_windowed::U254MulScript,
     square_fibonacci::SquareFibonacciScript,
 };
 use bitcoin_utils::stack_to_script;
-use bitcoin_utils::{comparison::OP_LONGEQUALVERIFY, treepp::*};
+use bitcoin_utils::{
+    comparison::OP_LONGEQUALVERIFY, debug::execute_script_without_op_cat, treepp::*,
+};
 use bitcoin_window_mul::{bigint::U508, traits::comparable::Comparable};
 
-use super::{form_disprove_scripts, signing::SignedIntermediateState};
+use super::{
+    form_disprove_scripts,
+    model::{disprove_model, peak_stack_items},
+    signing::SignedIntermediateState,
+};
 
 #[test]
 pub fn test_stack_sign_and_verify() {
@@ -40,7 +55,7 @@ pub fn test_stack_sign_and_verify() {
         OP_TRUE
     };
 
-    let result = execute_script(verify_script);
+    let result = execute_script_without_op_cat(verify_script);
     assert!(result.success, "Verification failed");
 }
 
@@ -106,6 +121,53 @@ pub fn test_stack_sign_and_verify_with_altstack() {
     assert!(result.success, "Verification failed");
 }
 
+#[test]
+pub fn test_wide_stack_sign_and_verify() {
+    // Define the test intermediate state with the numbers wider than a single word
+    let state = IntermediateState::from_input_script(
+        &script! {},
+        &script! {
+            { (1i64 << 31) - 1 } { 1 << 30 } OP_NEGATE OP_7
+            { 1 << 29 } OP_NEGATE OP_TOALTSTACK
+        },
+    );
+
+    // Now, we sign the state
+    let signed_state = SignedIntermediateState::sign(&state);
+    assert_eq!(signed_state.stack_len(), 3);
+    assert_eq!(signed_state.altstack_len(), 1);
+
+    // Check that witness + verification scripts restore the exact elements without OP_CAT
+    let verify_script = script! {
+        { signed_state.witness_script() }
+        { signed_state.verification_script() }
+        OP_7 OP_EQUALVERIFY
+        { 1 << 30 } OP_NEGATE OP_EQUALVERIFY
+        { (1i64 << 31) - 1 } OP_EQUALVERIFY
+        OP_FROMALTSTACK { 1 << 29 } OP_NEGATE OP_EQUALVERIFY
+        OP_TRUE
+    };
+
+    let result = execute_script_without_op_cat(verify_script);
+    assert!(result.success, "Verification failed");
+
+    // Hashes cannot be restored in Tapscript, so they cannot be signed
+    let hash_state = IntermediateState::from_input_script(
+        &script! {},
+        &script! {
+            OP_1 OP_SHA256 OP_7
+        },
+    );
+    assert_eq!(
+        SignedIntermediateState::try_sign(&hash_state).err(),
+        Some(EncodingError::UnrestorableElement { length: 32 })
+    );
+    assert_eq!(
+        DisproveScript::new(&hash_state, &hash_state, &script! {}).err(),
+        Some(EncodingError::UnrestorableElement { length: 32 })
+    );
+}
+
 #[test]
 pub fn test_stack_sign_and_verify_bigint() {
     // First, we generate the pair of input and output scripts
@@ -123,7 +185,7 @@ pub fn test_stack_sign_and_verify_bigint() {
             { signed_state.witness_script() }
             { signed_state.verification_script() }
             { stack_to_script(&intermediate_state.stack) }
-            { OP_LONGEQUALVERIFY(signed_state.stack.len()) }
+            { OP_LONGEQUALVERIFY(signed_state.stack_len()) }
             OP_TRUE
         };
 
@@ -155,7 +217,7 @@ pub fn test_trivial_disprove_script_success() {
     };
 
     // Now, form the disprove script
-    let disprove_script = DisproveScript::new(&state_from, &state_to, &function);
+    let disprove_script = DisproveScript::new(&state_from, &state_to, &function).unwrap();
 
     // Check that witness + verification scripts are satisfied
     let verify_script = script! {
@@ -190,7 +252,7 @@ pub fn test_trivial_disprove_script_should_fail() {
     };
 
     // Now, form the disprove script
-    let disprove_script = DisproveScript::new(&state_from, &state_to, &function);
+    let disprove_script = DisproveScript::new(&state_from, &state_to, &function).unwrap();
 
     // Check that witness + verification scripts are satisfied
     let verify_script = script! {
@@ -226,7 +288,7 @@ pub fn test_disprove_script_with_altstack_should_fail() {
     };
 
     // Now, form the disprove script
-    let disprove_script = DisproveScript::new(&state_from, &state_to, &function);
+    let disprove_script = DisproveScript::new(&state_from, &state_to, &function).unwrap();
 
     // Check that witness + verification scripts are satisfied
     let verify_script = script! {
@@ -262,7 +324,7 @@ pub fn test_disprove_script_with_altstack_success() {
     };
 
     // Now, form the disprove script
-    let disprove_script = DisproveScript::new(&state_from, &state_to, &function);
+    let disprove_script = DisproveScript::new(&state_from, &state_to, &function).unwrap();
 
     // Check that witness + verification scripts are satisfied
     let verify_script = script! {
@@ -298,7 +360,7 @@ pub fn test_disprove_script_with_altstack_2() {
     };
 
     // Now, form the disprove script
-    let disprove_script = DisproveScript::new(&state_from, &state_to, &function);
+    let disprove_script = DisproveScript::new(&state_from, &state_to, &function).unwrap();
 
     // Check that witness + verification scripts are satisfied
     let verify_script = script! {
@@ -311,6 +373,45 @@ pub fn test_disprove_script_with_altstack_2() {
     assert!(!result.success, "Verification failed");
 }
 
+#[test]
+pub fn test_disprove_script_with_small_negative_altstack() {
+    // Define the following setup:
+    // Transition function: { OP_1SUB OP_FROMALTSTACK OP_1SUB OP_TOALTSTACK }
+    // From: { mainstack: { 5 }, altstack: { -1 } }
+    // To:   { mainstack: { 4 }, altstack: { -2 } }
+    let state_from = IntermediateState::from_input_script(
+        &script! {},
+        &script! {
+            { 5 } { -1 } OP_TOALTSTACK
+        },
+    );
+    let function = script! {
+        OP_1SUB OP_FROMALTSTACK OP_1SUB OP_TOALTSTACK
+    };
+
+    // Small negative numbers are single tagged words, which must be decoded
+    // in the altstack as well, so the correct transition cannot be disproved
+    for (to_altstack, disproved) in [(-2i64, false), (-3, true)] {
+        let state_to = IntermediateState::from_input_script(
+            &script! {},
+            &script! {
+                { 4 } { to_altstack } OP_TOALTSTACK
+            },
+        );
+        let disprove_script = DisproveScript::new(&state_from, &state_to, &function).unwrap();
+
+        let result = execute_script_without_op_cat(script! {
+            { disprove_script.script_witness }
+            { disprove_script.script_pubkey }
+        });
+        assert_eq!(
+            result.success, disproved,
+            "altstack {} is handled incorrectly",
+            to_altstack
+        );
+    }
+}
+
 #[test]
 pub fn test_disprove_script_mul_script() {
     // First, we generate the pair of input and output scripts
@@ -338,7 +439,8 @@ pub fn test_disprove_script_mul_script() {
             &split_result.intermediate_states[i],
             &split_result.intermediate_states[i + 1],
             &split_result.shards[i + 1],
-        );
+        )
+        .unwrap();
 
         // Check that witness + verification scripts are satisfied
         let verify_script = script! {
@@ -382,7 +484,8 @@ pub fn test_disprove_script_fibonacci_script_invalid_input() {
             &split_result.intermediate_states[i],
             &split_result.intermediate_states[i + 1],
             &split_result.shards[i + 1],
-        );
+        )
+        .unwrap();
 
         // Check that witness + verification scripts are satisfied
         let verify_script = script! {
@@ -413,7 +516,8 @@ pub fn test_disprove_script_fibonacci_script_valid_input() {
             &split_result.intermediate_states[i],
             &split_result.intermediate_states[i + 1],
             &split_result.shards[i + 1],
-        );
+        )
+        .unwrap();
 
         // Check that witness + verification scripts are satisfied
         let verify_script = script! {
@@ -440,7 +544,8 @@ pub fn test_distorted_disprove_script_fibonacci_sequence() {
         { FibonacciScript::INPUT_SIZE },
         { FibonacciScript::OUTPUT_SIZE },
         FibonacciScript,
-    >(input.clone());
+    >(input.clone())
+    .unwrap();
 
     println!("Distorted ID: {:?}", distorted_id);
 
@@ -472,7 +577,8 @@ pub fn test_disprove_script_batch_correctness() {
         { U254MulScript::INPUT_SIZE },
         { U254MulScript::OUTPUT_SIZE },
         U254MulScript,
-    >(input.clone());
+    >(input.clone())
+    .unwrap();
 
     // Now, we form the disprove script for each shard
     for (i, disprove_script) in disprove_scripts.into_iter().enumerate() {
@@ -486,3 +592,241 @@ pub fn test_disprove_script_batch_correctness() {
         assert!(!result.success, "Verification {:?} failed", i + 1);
     }
 }
+
+#[test]
+pub fn test_disprove_script_with_wide_elements() {
+    // Define the following setup:
+    // Transition function: { OP_DUP OP_ADD }
+    // From: { mainstack: { 2^29 + 1 }, altstack: { -2^30 } }
+    // To:   { mainstack: { x }, altstack: { -2^30 } }
+    let state_from = IntermediateState::from_input_script(
+        &script! {},
+        &script! {
+            { (1 << 29) + 1 } { 1 << 30 } OP_NEGATE OP_TOALTSTACK
+        },
+    );
+    let function = script! {
+        OP_DUP OP_ADD
+    };
+
+    for (x, should_disprove) in [((1 << 30) + 2, false), ((1 << 30) + 3, true)] {
+        let state_to = IntermediateState::from_input_script(
+            &script! {},
+            &script! {
+                { x } { 1 << 30 } OP_NEGATE OP_TOALTSTACK
+            },
+        );
+
+        // Now, form the disprove script
+        let disprove_script = DisproveScript::new(&state_from, &state_to, &function).unwrap();
+
+        // Check that witness + verification scripts are satisfied only for the wrong state,
+        // and that the leaf does not rely on OP_CAT
+        let verify_script = script! {
+            { disprove_script.script_witness }
+            { disprove_script.script_pubkey }
+        };
+
+        let result = execute_script_without_op_cat(verify_script);
+        assert_eq!(result.success, should_disprove, "Verification failed");
+    }
+}
+
+#[test]
+pub fn test_disprove_script_with_negative_elements() {
+    // Subtracting the larger number makes each limb difference negative
+    // before the borrow is applied
+    let input = script! {
+        { U64::push_hex("1") }
+        { U64::push_hex("2") }
+    };
+    let split_result = naive_split(input.clone(), U64::sub(1, 0), SplitType::ByInstructions, 1);
+
+    let is_negative = |element: Vec<u8>| element.last().is_some_and(|byte| byte & 0x80 != 0);
+    assert!(
+        split_result
+            .intermediate_states
+            .iter()
+            .any(|state| state.stack.iter_str().any(is_negative)),
+        "split must contain negative intermediates"
+    );
+
+    // The states are correct, so no shard can be disproven
+    for i in 0..split_result.shards.len() {
+        let from_state = if i == 0 {
+            IntermediateState::from_inject_script(&input)
+        } else {
+            split_result.intermediate_states[i - 1].clone()
+        };
+
+        let disprove_script = DisproveScript::new(
+            &from_state,
+            &split_result.intermediate_states[i],
+            &split_result.shards[i],
+        )
+        .unwrap();
+
+        let verify_script = script! {
+            { disprove_script.script_witness }
+            { disprove_script.script_pubkey }
+        };
+
+        let result = execute_script(verify_script);
+        assert!(!result.success, "Verification {:?} failed", i);
+    }
+}
+
+#[test]
+pub fn test_constrained_split_disprove_scripts_fit() {
+    // The number of steps for the Fibonacci script
+    const STEPS: usize = 16;
+    type FibonacciScript = SquareFibonacciScript<STEPS>;
+
+    let IOPair { input, output: _ } = FibonacciScript::generate_valid_io_pair();
+
+    // Limiting the leaf so that the script does not fit into a single one
+    let constraints = SplitConstraints {
+        max_leaf_size: 100_000,
+        ..SplitConstraints::tapscript(disprove_model())
+    };
+    let split_result = constrained_split(input.clone(), FibonacciScript::script(), &constraints)
+        .expect("the script must be split under the limits");
+
+    for (i, shard) in split_result.shards.iter().enumerate() {
+        let from_state = if i == 0 {
+            IntermediateState::from_inject_script(&input)
+        } else {
+            split_result.intermediate_states[i - 1].clone()
+        };
+        let disprove_script =
+            DisproveScript::new(&from_state, &split_result.intermediate_states[i], shard).unwrap();
+
+        assert!(disprove_script.script_pubkey.len() <= constraints.max_leaf_size);
+        assert!(disprove_script.script_witness.len() <= constraints.max_witness_size);
+        assert!(
+            constraints.tx_overhead
+                + disprove_script.script_pubkey.len()
+                + disprove_script.script_witness.len()
+                <= constraints.max_tx_weight
+        );
+        assert!(peak_stack_items(&disprove_script) <= constraints.max_stack_items);
+
+        // The transitions are correct, so none of the disprove scripts can be spent
+        let verify_script = script! {
+            { disprove_script.script_witness }
+            { disprove_script.script_pubkey }
+        };
+        let result = execute_script(verify_script);
+        assert!(!result.success, "Verification {:?} failed", i + 1);
+    }
+}
+
+#[test]
+pub fn test_hinted_disprove_scripts() {
+    // The number of squarings in the chain
+    const STEPS: usize = 3;
+    type SquareChainScript = FqSquareChainScript<STEPS>;
+
+    let io_pair = SquareChainScript::generate_valid_io_pair();
+    let mut hinted_split = SquareChainScript::split(&io_pair, SplitType::ByInstructions, 1);
+    assert!(
+        hinted_split.len() >= STEPS,
+        "the script must be split into several shards"
+    );
+
+    // Each shard gets its hints through the witness, so none of the
+    // disprove scripts of the correct split can be spent
+    let disprove_scripts =
+        form_hinted_disprove_scripts(io_pair.input.clone(), &hinted_split).unwrap();
+    for (i, disprove_script) in disprove_scripts.into_iter().enumerate() {
+        let verify_script = script! {
+            { disprove_script.script_witness }
+            { disprove_script.script_pubkey }
+        };
+
+        let result = execute_script(verify_script);
+        assert!(!result.success, "Verification {:?} failed", i + 1);
+    }
+
+    // Distorting the state in the middle of the split
+    let distorted_id = hinted_split.len() / 2;
+    let distorted_state = &mut hinted_split.split_result.intermediate_states[distorted_id];
+    distorted_state.stack = execute_script(script! {
+        { stack_to_script(&distorted_state.stack) }
+        OP_DROP OP_0 // Changing the last limb to OP_0
+    })
+    .main_stack;
+
+    // The shard producing the distorted state is disproved. The next shard is executed
+    // on the distorted state, which its hints do not match, so it is not checked here
+    let disprove_scripts =
+        form_hinted_disprove_scripts(io_pair.input.clone(), &hinted_split).unwrap();
+    for (i, disprove_script) in disprove_scripts.into_iter().enumerate() {
+        if i == distorted_id + 1 {
+            continue;
+        }
+
+        let verify_script = script! {
+            { disprove_script.script_witness }
+            { disprove_script.script_pubkey }
+        };
+
+        let result = execute_script(verify_script);
+        assert_eq!(
+            result.success,
+            i == distorted_id,
+            "Verification {:?} failed",
+            i + 1
+        );
+    }
+}
+
+#[test]
+pub fn test_hinted_disprove_scripts_wrong_hints() {
+    const STEPS: usize = 2;
+    type SquareChainScript = FqSquareChainScript<STEPS>;
+
+    let io_pair = SquareChainScript::generate_valid_io_pair();
+    let valid_split = SquareChainScript::split(&io_pair, SplitType::ByInstructions, 1);
+    let split_result = &valid_split.split_result;
+
+    // The same shards consuming the hints of the other squaring
+    let mut other_hints = io_pair.hints.clone();
+    other_hints.rotate_left(io_pair.hints.len() / STEPS);
+    let other_script = HintedScript::new(SquareChainScript::annotated_script(), other_hints);
+    let other_split = hinted_split(
+        io_pair.input.clone(),
+        &other_script,
+        SplitType::ByInstructions,
+        1,
+    )
+    .unwrap();
+    assert_eq!(other_split.split_result.shards, split_result.shards);
+
+    // Hints are not signed, so the challenger can put any of them into the witness.
+    // Each shard checks its hints, so the wrong ones never disprove the correct transition
+    for i in 0..split_result.len() {
+        if other_split.shard_hints[i].is_empty() {
+            continue;
+        }
+
+        let from_state = match i {
+            0 => IntermediateState::from_inject_script(&io_pair.input),
+            _ => split_result.intermediate_states[i - 1].clone(),
+        };
+        let disprove_script = DisproveScript::with_hints(
+            &from_state,
+            &split_result.intermediate_states[i],
+            &split_result.shards[i],
+            &other_split.shard_hints[i],
+        )
+        .unwrap();
+
+        let verify_script = script! {
+            { disprove_script.script_witness }
+            { disprove_script.script_pubkey }
+        };
+        let result = execute_script(verify_script);
+        assert!(!result.success, "Wrong hints spend the leaf {:?}", i + 1);
+    }
+}